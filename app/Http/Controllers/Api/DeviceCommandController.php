@@ -106,6 +106,7 @@ public function result(Request $request, int $commandId): JsonResponse
         }
 
         $validated = $request->validate([
+            'status' => 'nullable|string|max:32',
             'exit_code' => 'required|integer',
             'output' => 'nullable|string|max:1000000',
             'error_message' => 'nullable|string|max:10000',
@@ -113,12 +114,17 @@ public function result(Request $request, int $commandId): JsonResponse
 
         $exitCode = (int) $validated['exit_code'];
         $output = $validated['output'] ?? '';
-
-        if (isset($validated['error_message']) && $validated['error_message']) {
-            $command->markAsFailed($validated['error_message'], $output, $exitCode);
-        } else {
-            $command->markAsCompleted($output, $exitCode);
-        }
+        $errorMessage = ($validated['error_message'] ?? null) ?: null;
+
+        // The agent's status says how the command ended; any other status
+        // (or none, from older agents) falls back to the error and exit code
+        match ($validated['status'] ?? null) {
+            DeviceCommand::STATUS_TIMED_OUT => $command->markAsTimedOut($output, $exitCode, $errorMessage),
+            DeviceCommand::STATUS_CANCELLED => $command->markAsCancelled($output, $exitCode, $errorMessage),
+            default => $errorMessage !== null
+                ? $command->markAsFailed($errorMessage, $output, $exitCode)
+                : $command->markAsCompleted($output, $exitCode),
+        };
 
         Log::info('api.command.completed', [
             'device_id' => $device->id,
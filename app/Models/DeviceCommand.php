@@ -127,11 +127,24 @@ public function markAsFailed(string $errorMessage, ?string $output = null, ?int
         ]);
     }
 
-    public function markAsTimedOut(): void
+    public function markAsTimedOut(?string $output = null, ?int $exitCode = null, ?string $errorMessage = null): void
     {
         $this->update([
             'status' => self::STATUS_TIMED_OUT,
-            'error_message' => "Command timed out after {$this->timeout_seconds} seconds",
+            'error_message' => $errorMessage ?? "Command timed out after {$this->timeout_seconds} seconds",
+            'output' => $output,
+            'exit_code' => $exitCode,
+            'completed_at' => now(),
+        ]);
+    }
+
+    public function markAsCancelled(?string $output = null, ?int $exitCode = null, ?string $errorMessage = null): void
+    {
+        $this->update([
+            'status' => self::STATUS_CANCELLED,
+            'error_message' => $errorMessage,
+            'output' => $output,
+            'exit_code' => $exitCode,
             'completed_at' => now(),
         ]);
     }
@@ -4,9 +4,14 @@ use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
+use crate::api::{ApiClient, ApiError};
+use crate::commands::CommandExecutor;
 use crate::config::Config;
 use crate::enrollment::{EnrollmentManager, EnrollmentStatus};
 use crate::metrics::MetricsCollector;
+use crate::push::{PushChannel, PushSignals};
+use crate::schedule::Schedule;
+use crate::server_config::ServerConfigStore;
 use crate::storage::Storage;
 use crate::sysinfo::SystemInfo;
 use crate::updater::Updater;
@@ -22,6 +27,8 @@ pub enum AgentState {
     Active,
     /// Revoked by server
     Revoked,
+    /// The backend's certificate didn't match the pinned keys (API key kept)
+    PinMismatch,
     /// Error state
     Error(String),
 }
@@ -34,11 +41,13 @@ impl AgentState {
             AgentState::PendingApproval => "Pending Approval".to_string(),
             AgentState::Active => "Online".to_string(),
             AgentState::Revoked => "Revoked".to_string(),
+            AgentState::PinMismatch => "Certificate Pin Mismatch".to_string(),
             AgentState::Error(msg) => format!("Error: {}", msg),
         }
     }
 
     /// Get a status label
+    #[allow(dead_code)]
     pub fn status_label(&self) -> String {
         format!("Status: {}", self.as_display())
     }
@@ -48,13 +57,19 @@ impl AgentState {
 pub struct Agent {
     config: Config,
     system_info: SystemInfo,
+    api: ApiClient,
     enrollment_manager: EnrollmentManager,
+    /// Config pushed by the server in heartbeat responses
+    server_config: Arc<ServerConfigStore>,
+    /// Per-device tick schedule, seeded from the hardware fingerprint
+    schedule: Schedule,
     state: Arc<RwLock<AgentState>>,
     cancellation_token: CancellationToken,
 }
 
 impl Agent {
     /// Create a new agent instance with default config
+    #[allow(dead_code)]
     pub async fn new() -> Result<Self> {
         Self::with_config(Config::default()).await
     }
@@ -68,8 +83,11 @@ impl Agent {
         let system_info = SystemInfo::gather().context("Failed to gather system information")?;
         info!("System info: {}", system_info.summary());
 
+        let api = ApiClient::new(&config)?;
         let storage = Storage::new(&config.key_file);
-        let enrollment_manager = EnrollmentManager::new(config.clone(), storage)?;
+        let enrollment_manager = EnrollmentManager::new(config.clone(), storage, api.clone());
+        let server_config = Arc::new(ServerConfigStore::load(&config));
+        let schedule = Schedule::new(&system_info.hardware_fingerprint);
 
         // Determine initial state
         let initial_state = if enrollment_manager.is_enrolled().await {
@@ -81,7 +99,10 @@ impl Agent {
         Ok(Self {
             config,
             system_info,
+            api,
             enrollment_manager,
+            server_config,
+            schedule,
             state: Arc::new(RwLock::new(initial_state)),
             cancellation_token: CancellationToken::new(),
         })
@@ -102,6 +123,7 @@ impl Agent {
     }
 
     /// Get the cancellation token for graceful shutdown
+    #[allow(dead_code)]
     pub fn cancellation_token(&self) -> CancellationToken {
         self.cancellation_token.clone()
     }
@@ -180,15 +202,17 @@ impl Agent {
         Ok(())
     }
 
-    /// Run the metrics and heartbeat collection loops (blocks until cancelled)
+    /// Run the metrics, heartbeat, command and update loops (blocks until cancelled)
     async fn run_metrics_loop(&self, api_key: String) {
-        info!("Starting metrics, heartbeat, and update check loops");
+        info!("Starting metrics, heartbeat, command, and update check loops");
 
+        // One collector serves both the metrics and heartbeat loops
         let collector = match MetricsCollector::new(
             self.config.clone(),
             self.system_info.hostname.clone(),
+            self.api.clone(),
         ) {
-            Ok(c) => c,
+            Ok(c) => Arc::new(c),
             Err(e) => {
                 error!("Failed to create metrics collector: {}", e);
                 return;
@@ -201,49 +225,129 @@ impl Agent {
             warn!("Please ensure Netdata is installed and running");
         }
 
-        // Create a separate collector for heartbeat loop
-        let heartbeat_collector = match MetricsCollector::new(
-            self.config.clone(),
-            self.system_info.hostname.clone(),
-        ) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to create heartbeat collector: {}", e);
-                return;
-            }
+        // The loops stop on shutdown or once a pushed revocation is confirmed
+        let loops_token = self.cancellation_token.child_token();
+        let push = Arc::new(PushSignals::default());
+
+        // Spawn push channel as a separate task (loops fall back to polling without it)
+        let push_handle = if self.config.push_enabled {
+            let channel = PushChannel::new(self.api.clone(), push.clone());
+            let push_api_key = api_key.clone();
+            let push_token = loops_token.clone();
+            Some(tokio::spawn(async move {
+                channel.run(push_api_key, push_token).await;
+            }))
+        } else {
+            None
         };
 
         // Spawn heartbeat loop as a separate task
+        let heartbeat_collector = collector.clone();
         let heartbeat_api_key = api_key.clone();
-        let heartbeat_token = self.cancellation_token.clone();
+        let heartbeat_schedule = self.schedule.clone();
+        let heartbeat_push = push.clone();
+        let heartbeat_server_config = self.server_config.clone();
+        let heartbeat_token = loops_token.clone();
         let heartbeat_handle = tokio::spawn(async move {
             heartbeat_collector
-                .start_heartbeat_loop(heartbeat_api_key, heartbeat_token)
+                .start_heartbeat_loop(
+                    heartbeat_api_key,
+                    &heartbeat_schedule,
+                    &heartbeat_push,
+                    &heartbeat_server_config,
+                    heartbeat_token,
+                )
                 .await;
         });
 
-        // Spawn update check loop as a separate task
-        let update_config = self.config.clone();
-        let update_token = self.cancellation_token.clone();
-        let update_handle = tokio::spawn(async move {
-            match Updater::new(update_config) {
-                Ok(updater) => {
-                    updater.start_update_loop(update_token).await;
+        // Spawn command poll loop as a separate task
+        let command_config = self.config.clone();
+        let command_hostname = self.system_info.hostname.clone();
+        let command_api = self.api.clone();
+        let command_api_key = api_key.clone();
+        let command_push = push.clone();
+        let command_settings = self.server_config.subscribe();
+        let command_token = loops_token.clone();
+        let command_handle = tokio::spawn(async move {
+            match CommandExecutor::new(command_config, command_hostname, command_api, command_settings) {
+                Ok(executor) => {
+                    executor
+                        .start_command_loop(command_api_key, &command_push, command_token)
+                        .await;
                 }
                 Err(e) => {
-                    error!("Failed to create updater: {}", e);
+                    error!("Failed to create command executor: {}", e);
                 }
             }
         });
 
-        // Start the metrics loop (blocks until cancelled)
-        collector
-            .start_metrics_loop(api_key, self.cancellation_token.clone())
-            .await;
+        // Spawn update check loop as a separate task
+        let updater = Updater::new(self.config.clone(), self.api.clone());
+        let update_schedule = self.schedule.clone();
+        let update_push = push.clone();
+        let update_settings = self.server_config.subscribe();
+        let update_token = loops_token.clone();
+        let update_handle = tokio::spawn(async move {
+            updater
+                .start_update_loop(&update_schedule, &update_push, update_settings, update_token)
+                .await;
+        });
+
+        // Run the metrics loop and the revocation watch (block until cancelled)
+        let (_, revoked) = tokio::join!(
+            collector.start_metrics_loop(
+                api_key,
+                &self.schedule,
+                self.server_config.subscribe(),
+                loops_token.clone(),
+            ),
+            self.watch_for_revocation(&push, &loops_token),
+        );
 
         // Wait for other loops to finish
         let _ = heartbeat_handle.await;
+        let _ = command_handle.await;
         let _ = update_handle.await;
+        if let Some(handle) = push_handle {
+            let _ = handle.await;
+        }
+
+        if revoked {
+            warn!("Device was revoked by the server");
+            if let Err(e) = self.enrollment_manager.clear_api_key().await {
+                warn!("Failed to clear API key: {}", e);
+            }
+            self.set_state(AgentState::Revoked).await;
+        }
+    }
+
+    /// Stop the loops once `/api/check` confirms a pushed revocation
+    ///
+    /// Returns whether the device was revoked. A `revoked` event the backend
+    /// doesn't confirm is ignored, so one stray event can't drop the API key.
+    async fn watch_for_revocation(
+        &self,
+        push: &PushSignals,
+        loops_token: &CancellationToken,
+    ) -> bool {
+        loop {
+            tokio::select! {
+                _ = push.revoked.notified() => {}
+                _ = loops_token.cancelled() => return false,
+            }
+
+            match self.check_status().await {
+                Ok(AgentState::Revoked) => {
+                    loops_token.cancel();
+                    return true;
+                }
+                Ok(state) => warn!(
+                    "Ignoring revocation pushed by the server: status check reports {}",
+                    state.as_display()
+                ),
+                Err(e) => warn!("Ignoring unconfirmed revocation: {:#}", e),
+            }
+        }
     }
 
     /// Trigger graceful shutdown
@@ -276,6 +380,11 @@ impl Agent {
                 self.set_state(state.clone()).await;
                 Ok(state)
             }
+            Err(e) if is_pin_mismatch(&e) => {
+                error!("Backend certificate does not match the pinned keys: {:#}", e);
+                self.set_state(AgentState::PinMismatch).await;
+                Ok(AgentState::PinMismatch)
+            }
             Err(e) => {
                 let msg = format!("Status check failed: {}", e);
                 debug!("{}", msg);
@@ -286,6 +395,7 @@ impl Agent {
     }
 
     /// Clear API key and force re-enrollment
+    #[allow(dead_code)]
     pub async fn reset(&self) -> Result<()> {
         info!("Resetting agent - clearing API key");
         self.enrollment_manager.clear_api_key().await?;
@@ -294,12 +404,21 @@ impl Agent {
     }
 
     /// Get system information
+    #[allow(dead_code)]
     pub fn system_info(&self) -> &SystemInfo {
         &self.system_info
     }
 
     /// Get the config
+    #[allow(dead_code)]
     pub fn config(&self) -> &Config {
         &self.config
     }
 }
+
+/// Whether an error came from a backend certificate failing its pins
+fn is_pin_mismatch(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|e| matches!(e.downcast_ref::<ApiError>(), Some(ApiError::PinMismatch { .. })))
+}
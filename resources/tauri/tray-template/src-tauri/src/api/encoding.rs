@@ -0,0 +1,72 @@
+//! Request body compression
+//!
+//! JSON bodies at or over `request_compression_threshold` bytes are sent
+//! with `Content-Encoding` once the heartbeat response lists the configured
+//! algorithm in `accepted_encodings`. A backend that answers 415 has the
+//! encoding switched off again until the next heartbeat.
+
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+/// A `Content-Encoding` the agent can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    /// Parse a config or header value; `none` and unknown names mean no compression
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "gzip" => Some(Encoding::Gzip),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Header value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// Compress a request body
+    pub fn compress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Encoding::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_compression_round_trips() {
+        let body = br#"{"netdata_cpu":{"view":{"dimensions":{}}}}"#.repeat(100);
+
+        let gzipped = Encoding::Gzip.compress(&body).unwrap();
+        assert!(gzipped.len() < body.len() / 10);
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let zstded = Encoding::Zstd.compress(&body).unwrap();
+        assert_eq!(zstd::decode_all(&zstded[..]).unwrap(), body);
+
+        assert_eq!(Encoding::parse(" ZSTD"), Some(Encoding::Zstd));
+        assert_eq!(Encoding::parse("none"), None);
+    }
+}
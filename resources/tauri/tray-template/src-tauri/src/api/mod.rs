@@ -0,0 +1,737 @@
+//! Backend API client
+//!
+//! A single `ApiClient` is shared by enrollment, metrics, heartbeat, commands
+//! and the updater. It owns the connection pool, the base URL, the user
+//! agent, timeouts and `X-Agent-Key` auth, and sorts failures into
+//! `ApiError` so callers can tell "try again later" from "give up".
+//! Idempotent requests that hit a transient failure (network error or 5xx)
+//! are retried once before the error is returned.
+//!
+//! Every `/api/*` route has a method here and typed bodies in `types`, so a
+//! protocol change only touches this module. Large metrics and command
+//! bodies are compressed once the backend says it accepts it (see
+//! `encoding`).
+
+mod encoding;
+mod types;
+
+pub use types::*;
+
+use anyhow::Context;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, warn};
+
+use crate::backoff::{parse_retry_after, Backoff};
+use crate::commands::{CommandResult, OutputChunk, PendingCommand};
+use crate::config::{Config, AGENT_VERSION};
+use crate::proxy::ProxySettings;
+use crate::tls;
+use encoding::Encoding;
+
+/// Default limit on a whole request, including reading the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limit on establishing a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts made for an idempotent request before giving up
+const MAX_ATTEMPTS: u32 = 2;
+
+/// Pause between attempts of an idempotent request
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Limit on opening a WebSocket, including the upgrade handshake
+const WEBSOCKET_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An open WebSocket to the backend
+pub type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Why a backend request failed
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// The request never got a response (DNS, connect, TLS, timeout)
+    #[error("network error: {0}")]
+    Network(#[source] reqwest::Error),
+    /// The API key was refused (401)
+    #[error("authentication failed: {body}")]
+    Unauthorized { body: String },
+    /// Too many requests (429), with the server's `Retry-After` if sent
+    #[error("rate limited: {body}")]
+    RateLimited {
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    /// The backend failed (5xx), with the server's `Retry-After` if sent
+    #[error("server error {status}: {body}")]
+    Server {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    /// The backend refused the request (any other 4xx)
+    #[error("request rejected with status {status}: {body}")]
+    Rejected { status: StatusCode, body: String },
+    /// The response body wasn't what the route returns
+    #[error("invalid response: {0}")]
+    InvalidResponse(#[source] reqwest::Error),
+    /// The request body couldn't be built
+    #[error("failed to encode request: {0}")]
+    Encode(String),
+    /// The backend's certificate matched none of the pinned public keys
+    #[error("certificate pin mismatch for {host}")]
+    PinMismatch { host: String },
+}
+
+impl ApiError {
+    /// HTTP status of the failed response, if there was one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ApiError::Unauthorized { .. } => Some(StatusCode::UNAUTHORIZED),
+            ApiError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            ApiError::Server { status, .. } | ApiError::Rejected { status, .. } => Some(*status),
+            ApiError::Network(_)
+            | ApiError::InvalidResponse(_)
+            | ApiError::Encode(_)
+            | ApiError::PinMismatch { .. } => None,
+        }
+    }
+
+    /// Whether the same request may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ApiError::Network(_) | ApiError::RateLimited { .. } | ApiError::Server { .. }
+        )
+    }
+
+    /// Response body of the failed request, if there was one
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            ApiError::Unauthorized { body }
+            | ApiError::RateLimited { body, .. }
+            | ApiError::Server { body, .. }
+            | ApiError::Rejected { body, .. } => Some(body),
+            ApiError::Network(_)
+            | ApiError::InvalidResponse(_)
+            | ApiError::Encode(_)
+            | ApiError::PinMismatch { .. } => None,
+        }
+    }
+
+    /// How long the backend asked us to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::RateLimited { retry_after, .. } | ApiError::Server { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Client for the backend API (cheap to clone; clones share the pool)
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    http: reqwest::Client,
+    base_url: String,
+    /// Configured request body compression
+    compression: Option<Encoding>,
+    /// Smallest body worth compressing, in bytes
+    compression_threshold: usize,
+    /// Whether the backend accepts `compression`, as told by the last heartbeat
+    compression_accepted: Arc<AtomicBool>,
+    /// Configured proxy, which WebSockets tunnel through themselves
+    proxy: Option<Arc<ProxySettings>>,
+    /// Extra CA certificates and pins, shared with WebSockets
+    tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl ApiClient {
+    /// Create a client for the configured backend
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let proxy = ProxySettings::from_config(config)?;
+        let tls = tls::client_config(config)?;
+
+        // Without a configured proxy reqwest uses HTTP(S)_PROXY from the environment
+        let mut builder = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .user_agent(format!("RMM-Agent/{}", AGENT_VERSION));
+        if let Some(proxy) = &proxy {
+            builder = builder.proxy(proxy.reqwest_proxy()?);
+        }
+        if let Some(tls) = &tls {
+            builder = builder.use_preconfigured_tls(rustls::ClientConfig::clone(tls));
+        }
+        let http = builder.build().context("Failed to create HTTP client")?;
+
+        Ok(Self {
+            http,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            compression: Encoding::parse(&config.request_compression),
+            compression_threshold: config.request_compression_threshold,
+            compression_accepted: Arc::new(AtomicBool::new(false)),
+            proxy: proxy.map(Arc::new),
+            tls,
+        })
+    }
+
+    /// The underlying HTTP client, for requests that aren't to the backend
+    /// (GitHub releases) but should share its pool and settings
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn request(&self, method: Method, path: &str, api_key: Option<&str>) -> RequestBuilder {
+        let request = self.http.request(method, self.url(path));
+        match api_key {
+            Some(key) => request.header("X-Agent-Key", key),
+            None => request,
+        }
+    }
+
+    /// Send a request, retrying transient failures if it is safe to repeat
+    async fn send(
+        &self,
+        build: impl Fn() -> RequestBuilder,
+        idempotent: bool,
+    ) -> Result<Response, ApiError> {
+        let mut backoff = Backoff::new(RETRY_DELAY, RETRY_DELAY * 4);
+        loop {
+            let result = match build().send().await {
+                Ok(response) => check_status(response).await,
+                Err(e) => Err(network_error(e)),
+            };
+
+            // A server that asked for a wait gets it from the caller's loop, not here
+            match result {
+                Err(e)
+                    if idempotent
+                        && backoff.failures() + 1 < MAX_ATTEMPTS
+                        && e.is_transient()
+                        && e.retry_after().is_none()
+                        && !matches!(e, ApiError::RateLimited { .. }) =>
+                {
+                    let delay = backoff.fail(None);
+                    debug!("Request failed ({}), retrying in {:?}", e, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Encoding to use for a body of `len` bytes, if any
+    fn encoding_for(&self, len: usize) -> Option<Encoding> {
+        if len < self.compression_threshold || !self.compression_accepted.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.compression
+    }
+
+    /// Record which encodings the backend accepts
+    fn set_accepted_encodings(&self, accepted: &[String]) {
+        let Some(encoding) = self.compression else {
+            return;
+        };
+        let supported = accepted
+            .iter()
+            .any(|e| Encoding::parse(e) == Some(encoding));
+        if self.compression_accepted.swap(supported, Ordering::Relaxed) != supported {
+            debug!(
+                "{} request compression {}",
+                encoding.as_str(),
+                if supported { "enabled" } else { "disabled" }
+            );
+        }
+    }
+
+    /// Send a JSON body, compressed when it's large and the backend accepts it
+    ///
+    /// A 415 answer to a compressed body switches compression off and the
+    /// request is sent again uncompressed.
+    async fn send_json<T: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        api_key: &str,
+        body: &T,
+        idempotent: bool,
+    ) -> Result<Response, ApiError> {
+        let json = serde_json::to_vec(body).map_err(|e| ApiError::Encode(e.to_string()))?;
+        let build = |body: &Vec<u8>| {
+            self.request(method.clone(), path, Some(api_key))
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone())
+        };
+
+        if let Some(encoding) = self.encoding_for(json.len()) {
+            let compressed = encoding
+                .compress(&json)
+                .map_err(|e| ApiError::Encode(e.to_string()))?;
+            debug!(
+                "Sending {} ({} bytes, {} {})",
+                path,
+                json.len(),
+                compressed.len(),
+                encoding.as_str()
+            );
+
+            match self
+                .send(
+                    || build(&compressed).header(CONTENT_ENCODING, encoding.as_str()),
+                    idempotent,
+                )
+                .await
+            {
+                Err(ApiError::Rejected { status, .. })
+                    if status == StatusCode::UNSUPPORTED_MEDIA_TYPE =>
+                {
+                    warn!(
+                        "Backend refused {} request bodies, sending uncompressed",
+                        encoding.as_str()
+                    );
+                    self.compression_accepted.store(false, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+
+        self.send(|| build(&json), idempotent).await
+    }
+
+    // ------------------------------------------------------------------------
+    // Enrollment
+    // ------------------------------------------------------------------------
+
+    /// `POST /api/enroll`
+    pub async fn enroll(&self, request: &EnrollRequest) -> Result<(), ApiError> {
+        self.send(
+            || {
+                self.request(Method::POST, "/api/enroll", None)
+                    .json(request)
+            },
+            false,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `POST /api/check`
+    pub async fn check(&self, request: &CheckRequest) -> Result<CheckResponse, ApiError> {
+        let response = self
+            .send(
+                || self.request(Method::POST, "/api/check", None).json(request),
+                true,
+            )
+            .await?;
+        parse_json(response).await
+    }
+
+    // ------------------------------------------------------------------------
+    // Metrics and Heartbeat
+    // ------------------------------------------------------------------------
+
+    /// `POST /api/metrics`
+    pub async fn submit_metrics(
+        &self,
+        api_key: &str,
+        payload: &RawMetricsPayload,
+    ) -> Result<(), ApiError> {
+        self.send_json(Method::POST, "/api/metrics", api_key, payload, false)
+            .await?;
+        Ok(())
+    }
+
+    /// `POST /api/metrics/batch`, only when the heartbeat advertised `metrics_batch`
+    pub async fn submit_metrics_batch(
+        &self,
+        api_key: &str,
+        samples: &[&RawMetricsPayload],
+    ) -> Result<(), ApiError> {
+        let batch = MetricsBatchPayload { samples };
+        self.send_json(Method::POST, "/api/metrics/batch", api_key, &batch, false)
+            .await?;
+        Ok(())
+    }
+
+    /// `POST /api/heartbeat`
+    ///
+    /// A body that can't be parsed is treated as empty; the heartbeat itself
+    /// still succeeded.
+    pub async fn heartbeat(
+        &self,
+        api_key: &str,
+        payload: &HeartbeatPayload,
+    ) -> Result<HeartbeatResponse, ApiError> {
+        let response = self
+            .send(
+                || {
+                    self.request(Method::POST, "/api/heartbeat", Some(api_key))
+                        .json(payload)
+                },
+                false,
+            )
+            .await?;
+
+        let response: HeartbeatResponse = match response.json().await {
+            Ok(body) => body,
+            Err(e) => {
+                debug!("Ignoring unreadable heartbeat response: {}", e);
+                HeartbeatResponse::default()
+            }
+        };
+        self.set_accepted_encodings(&response.accepted_encodings);
+        Ok(response)
+    }
+
+    // ------------------------------------------------------------------------
+    // Commands
+    // ------------------------------------------------------------------------
+
+    /// `GET /api/commands/pending`
+    ///
+    /// Not retried: the backend marks the command as sent when it answers,
+    /// so a repeat after a lost response would skip it.
+    pub async fn pending_command(&self, api_key: &str) -> Result<Option<PendingCommand>, ApiError> {
+        let response = self
+            .send(
+                || self.request(Method::GET, "/api/commands/pending", Some(api_key)),
+                false,
+            )
+            .await?;
+        let pending: PendingCommandResponse = parse_json(response).await?;
+        Ok(pending.command)
+    }
+
+    /// `POST /api/commands/{id}/started`
+    pub async fn mark_command_started(
+        &self,
+        api_key: &str,
+        command_id: u64,
+    ) -> Result<(), ApiError> {
+        let path = format!("/api/commands/{}/started", command_id);
+        self.send(|| self.request(Method::POST, &path, Some(api_key)), true)
+            .await?;
+        Ok(())
+    }
+
+    /// `POST /api/commands/{id}/result`
+    ///
+    /// A 4xx other than 401, 408 or 429 means the backend will never take
+    /// this result, which the caller needs to tell apart from a failure
+    /// worth retrying.
+    pub async fn submit_command_result(
+        &self,
+        api_key: &str,
+        command_id: u64,
+        result: &CommandResult,
+    ) -> Result<ResultDelivery, ApiError> {
+        let path = format!("/api/commands/{}/result", command_id);
+        match self
+            .send_json(Method::POST, &path, api_key, result, true)
+            .await
+        {
+            Ok(_) => Ok(ResultDelivery::Accepted),
+            Err(ApiError::Rejected { status, .. }) if status != StatusCode::REQUEST_TIMEOUT => {
+                Ok(ResultDelivery::Rejected(status))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// `GET /api/commands/{id}/status`
+    pub async fn command_status(
+        &self,
+        api_key: &str,
+        command_id: u64,
+    ) -> Result<CommandStatusResponse, ApiError> {
+        let path = format!("/api/commands/{}/status", command_id);
+        let response = self
+            .send(|| self.request(Method::GET, &path, Some(api_key)), true)
+            .await?;
+        parse_json(response).await
+    }
+
+    /// `POST /api/commands/{id}/output`
+    pub async fn submit_command_output(
+        &self,
+        api_key: &str,
+        command_id: u64,
+        chunk: &OutputChunk,
+    ) -> Result<(), ApiError> {
+        let path = format!("/api/commands/{}/output", command_id);
+        // Chunks are numbered, so the backend drops repeats
+        self.send_json(Method::POST, &path, api_key, chunk, true)
+            .await?;
+        Ok(())
+    }
+
+    /// `POST /api/commands/{id}/attachments` with a gzipped output stream
+    pub async fn upload_command_attachment(
+        &self,
+        api_key: &str,
+        command_id: u64,
+        stream: &str,
+        gzipped: Vec<u8>,
+    ) -> Result<(), ApiError> {
+        let path = format!("/api/commands/{}/attachments", command_id);
+        let name = format!("{}.txt.gz", stream);
+        self.send(
+            || {
+                self.request(Method::POST, &path, Some(api_key))
+                    .header(reqwest::header::CONTENT_TYPE, "application/gzip")
+                    .query(&[("stream", stream), ("name", &name)])
+                    .body(gzipped.clone())
+            },
+            true,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// `POST /api/commands/{id}/files` with a streamed file body
+    pub async fn upload_command_file(
+        &self,
+        api_key: &str,
+        command_id: u64,
+        file_name: &str,
+        size: u64,
+        body: reqwest::Body,
+        timeout: Duration,
+    ) -> Result<(), ApiError> {
+        let path = format!("/api/commands/{}/files", command_id);
+        // A streamed body can only be sent once, so no retry
+        let request = self
+            .request(Method::POST, &path, Some(api_key))
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header("X-File-Name", file_name)
+            .header("X-File-Size", size)
+            .timeout(timeout)
+            .body(body);
+
+        let response = request.send().await.map_err(network_error)?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// `GET /api/commands/{id}/files`, returning the response to stream from
+    pub async fn download_command_file(
+        &self,
+        api_key: &str,
+        command_id: u64,
+        timeout: Duration,
+    ) -> Result<Response, ApiError> {
+        let path = format!("/api/commands/{}/files", command_id);
+        self.send(
+            || {
+                self.request(Method::GET, &path, Some(api_key))
+                    .timeout(timeout)
+            },
+            true,
+        )
+        .await
+    }
+
+    /// Open the WebSocket for a command's shell session (`/api/commands/{id}/shell`)
+    pub async fn connect_command_shell(
+        &self,
+        api_key: &str,
+        command_id: u64,
+    ) -> anyhow::Result<WebSocket> {
+        let path = format!("/api/commands/{}/shell", command_id);
+        self.connect_websocket(&path, api_key).await
+    }
+
+    /// Open the push event WebSocket (`/api/events`)
+    pub async fn connect_events(&self, api_key: &str) -> anyhow::Result<WebSocket> {
+        self.connect_websocket("/api/events", api_key).await
+    }
+
+    async fn connect_websocket(&self, path: &str, api_key: &str) -> anyhow::Result<WebSocket> {
+        let url = self.websocket_url(path)?;
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .context("Invalid WebSocket URL")?;
+        request.headers_mut().insert(
+            "X-Agent-Key",
+            HeaderValue::from_str(api_key).context("Invalid API key header")?,
+        );
+
+        let connect = async {
+            let uri = request.uri();
+            let host = uri.host().unwrap_or_default().to_string();
+            let default_port = if uri.scheme_str() == Some("wss") { 443 } else { 80 };
+            let port = uri.port_u16().unwrap_or(default_port);
+            let connector = self.tls.clone().map(Connector::Rustls);
+            match self.proxy.as_deref().filter(|proxy| !proxy.bypasses(&host)) {
+                Some(proxy) => {
+                    let stream = proxy.connect(&host, port).await?;
+                    tokio_tungstenite::client_async_tls_with_config(request, stream, None, connector)
+                        .await
+                        .map_err(|e| handshake_error(e, &host))
+                }
+                None => tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+                    .await
+                    .map_err(|e| handshake_error(e, &host)),
+            }
+        };
+
+        let (socket, _) = tokio::time::timeout(WEBSOCKET_CONNECT_TIMEOUT, connect)
+            .await
+            .with_context(|| format!("Timed out connecting to {}", path))?
+            .with_context(|| format!("Failed to connect to {}", path))?;
+        Ok(socket)
+    }
+
+    /// `ws://` or `wss://` URL for a path on the backend
+    fn websocket_url(&self, path: &str) -> anyhow::Result<String> {
+        let base = if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else {
+            anyhow::bail!("Unsupported server URL for WebSockets: {}", self.base_url);
+        };
+        Ok(format!("{}{}", base, path))
+    }
+}
+
+/// Turn a non-success response into the matching `ApiError`
+///
+/// Also used for GitHub release requests, so the updater backs off the same way.
+pub async fn check_status(response: Response) -> Result<Response, ApiError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = parse_retry_after(response.headers());
+    let body = response.text().await.unwrap_or_default();
+    Err(error_for_status(status, body, retry_after))
+}
+
+fn error_for_status(status: StatusCode, body: String, retry_after: Option<Duration>) -> ApiError {
+    match status {
+        StatusCode::UNAUTHORIZED => ApiError::Unauthorized { body },
+        StatusCode::TOO_MANY_REQUESTS => ApiError::RateLimited { body, retry_after },
+        s if s.is_server_error() => ApiError::Server {
+            status,
+            body,
+            retry_after,
+        },
+        _ => ApiError::Rejected { status, body },
+    }
+}
+
+/// Sort a failed request into `Network` or, for a refused pin, `PinMismatch`
+fn network_error(error: reqwest::Error) -> ApiError {
+    if tls::is_pin_mismatch(&error) {
+        let host = error
+            .url()
+            .and_then(|url| url.host_str())
+            .unwrap_or_default()
+            .to_string();
+        return ApiError::PinMismatch { host };
+    }
+    ApiError::Network(error)
+}
+
+/// Keep the `Retry-After` of a refused WebSocket upgrade, so callers back off alike
+fn handshake_error(error: tungstenite::Error, host: &str) -> anyhow::Error {
+    match error {
+        e if tls::is_pin_mismatch(&e) => ApiError::PinMismatch {
+            host: host.to_string(),
+        }
+        .into(),
+        tungstenite::Error::Http(response) => {
+            let retry_after = parse_retry_after(response.headers());
+            let body = String::from_utf8_lossy(response.body().as_deref().unwrap_or_default())
+                .into_owned();
+            error_for_status(response.status(), body, retry_after).into()
+        }
+        e => e.into(),
+    }
+}
+
+async fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T, ApiError> {
+    response.json().await.map_err(ApiError::InvalidResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_classification() {
+        let server = ApiError::Server {
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(server.is_transient());
+        assert_eq!(server.status(), Some(StatusCode::BAD_GATEWAY));
+
+        let rejected = ApiError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            body: "Device revoked".to_string(),
+        };
+        assert!(!rejected.is_transient());
+        assert_eq!(rejected.body(), Some("Device revoked"));
+        assert!(!ApiError::Unauthorized {
+            body: String::new()
+        }
+        .is_transient());
+    }
+
+    #[test]
+    fn test_compression_needs_backend_support() {
+        let config = Config {
+            request_compression: "zstd".to_string(),
+            request_compression_threshold: 100,
+            ..Default::default()
+        };
+        let api = ApiClient::new(&config).unwrap();
+        assert_eq!(api.encoding_for(1000), None);
+
+        api.set_accepted_encodings(&["gzip".to_string()]);
+        assert_eq!(api.encoding_for(1000), None);
+
+        api.set_accepted_encodings(&["gzip".to_string(), "zstd".to_string()]);
+        assert_eq!(api.clone().encoding_for(1000), Some(Encoding::Zstd));
+        assert_eq!(api.encoding_for(99), None);
+    }
+
+    #[test]
+    fn test_urls() {
+        let api = ApiClient::new(&Config::new("https://rmm.example.com/".to_string())).unwrap();
+        assert_eq!(api.url("/api/check"), "https://rmm.example.com/api/check");
+        assert_eq!(
+            api.websocket_url("/api/events").unwrap(),
+            "wss://rmm.example.com/api/events"
+        );
+
+        let api = ApiClient::new(&Config::new("http://localhost:8000".to_string())).unwrap();
+        assert_eq!(
+            api.websocket_url("/api/commands/9/shell").unwrap(),
+            "ws://localhost:8000/api/commands/9/shell"
+        );
+
+        let api = ApiClient::new(&Config::new("ftp://example.com".to_string())).unwrap();
+        assert!(api.websocket_url("/api/events").is_err());
+    }
+}
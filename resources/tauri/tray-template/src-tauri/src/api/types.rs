@@ -0,0 +1,227 @@
+//! Request and response bodies for the backend's `/api/*` routes
+//!
+//! Command payloads (`PendingCommand`, `CommandResult`, `OutputChunk`) carry
+//! behaviour and live in `commands`; everything else on the wire is here.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::commands::{InterpreterInfo, PendingCommand};
+use crate::server_config::ServerConfig;
+
+// ============================================================================
+// Enrollment
+// ============================================================================
+
+/// Body for `POST /api/enroll`
+#[derive(Debug, Serialize)]
+pub struct EnrollRequest {
+    pub hostname: String,
+    pub os: String,
+    pub hardware_fingerprint: String,
+    pub cpu_model: String,
+    pub cpu_cores: usize,
+    pub total_ram_bytes: u64,
+}
+
+/// Body for `POST /api/check`
+#[derive(Debug, Serialize)]
+pub struct CheckRequest {
+    pub hostname: String,
+    pub hardware_fingerprint: String,
+}
+
+/// Response from `POST /api/check`
+#[derive(Debug, Deserialize)]
+pub struct CheckResponse {
+    pub status: String,
+    pub api_key: Option<String>,
+}
+
+// ============================================================================
+// Metrics and Heartbeat
+// ============================================================================
+
+/// Body for `POST /api/metrics` - raw Netdata JSON, parsed by Laravel
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RawMetricsPayload {
+    /// Device hostname
+    pub hostname: String,
+    /// Timestamp of collection
+    pub timestamp: String,
+    /// Agent version
+    pub agent_version: String,
+    /// Raw Netdata /api/v3/info response (optional)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_info: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for CPU metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_cpu: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for RAM metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_ram: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for load metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_load: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for uptime metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_uptime: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for disk space metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_disk: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data response for network metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netdata_net: Option<serde_json::Value>,
+    /// Raw Netdata /api/v3/data responses for other contexts the server asked for
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub netdata_extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Body for `POST /api/metrics/batch` - several samples in one request
+#[derive(Debug, Serialize)]
+pub struct MetricsBatchPayload<'a> {
+    pub samples: &'a [&'a RawMetricsPayload],
+}
+
+/// Body for `POST /api/heartbeat` - what this agent can do, for the backend to record
+#[derive(Debug, Serialize)]
+pub struct HeartbeatPayload {
+    /// Agent version
+    pub agent_version: String,
+    /// Features available on this machine
+    pub capabilities: Capabilities,
+    /// Version of the server config block the agent is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<u64>,
+}
+
+/// Agent capabilities reported with each heartbeat
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    /// Script interpreters found on this machine
+    pub interpreters: &'static [InterpreterInfo],
+    /// `"enforced"` when a publisher key is pinned, otherwise `"unsigned"`
+    pub command_signing: &'static str,
+    /// Signed-message format the agent verifies
+    pub signature_version: &'static str,
+}
+
+/// Response from `POST /api/heartbeat`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HeartbeatResponse {
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Backend clock (RFC 3339)
+    #[serde(default)]
+    pub server_time: Option<String>,
+    /// Optional protocol features the backend supports (e.g. `metrics_batch`)
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// `Content-Encoding`s the backend accepts on request bodies
+    #[serde(default)]
+    pub accepted_encodings: Vec<String>,
+    /// Versioned agent configuration, kept raw so a bad block can't hide the rest
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+}
+
+impl HeartbeatResponse {
+    /// Whether the backend advertised a protocol feature
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// The config block, if the response carried one
+    pub fn server_config(&self) -> Option<serde_json::Result<ServerConfig>> {
+        self.config.clone().map(serde_json::from_value)
+    }
+}
+
+// ============================================================================
+// Commands
+// ============================================================================
+
+/// Response from `GET /api/commands/pending`
+#[derive(Debug, Deserialize)]
+pub struct PendingCommandResponse {
+    pub command: Option<PendingCommand>,
+}
+
+/// Response from `GET /api/commands/{id}/status`
+#[derive(Debug, Deserialize)]
+pub struct CommandStatusResponse {
+    pub status: String,
+}
+
+/// What happened to a result sent to `POST /api/commands/{id}/result`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultDelivery {
+    /// The backend stored the result
+    Accepted,
+    /// The backend refused it for good (e.g. the command no longer exists)
+    Rejected(reqwest::StatusCode),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_payload_serialization() {
+        let payload = RawMetricsPayload {
+            hostname: "test-host".to_string(),
+            timestamp: "2025-12-09T10:00:00Z".to_string(),
+            agent_version: "0.3.0".to_string(),
+            netdata_info: None,
+            netdata_cpu: Some(serde_json::json!({
+                "view": {
+                    "dimensions": {
+                        "ids": ["user", "system"],
+                        "sts": {
+                            "avg": [10.5, 5.2]
+                        }
+                    }
+                }
+            })),
+            netdata_ram: None,
+            netdata_load: None,
+            netdata_uptime: None,
+            netdata_disk: None,
+            netdata_net: None,
+            netdata_extra: BTreeMap::new(),
+        };
+
+        let json = serde_json::to_string(&payload).unwrap();
+        assert!(json.contains("test-host"));
+        assert!(json.contains("netdata_cpu"));
+        assert!(json.contains("10.5"));
+    }
+
+    #[test]
+    fn test_heartbeat_response_is_lenient() {
+        let response: HeartbeatResponse =
+            serde_json::from_str(r#"{"status":"ok","server_time":"2025-12-09T10:00:00Z"}"#)
+                .unwrap();
+        assert_eq!(response.status.as_deref(), Some("ok"));
+
+        assert!(!response.supports("metrics_batch"));
+
+        let response: HeartbeatResponse =
+            serde_json::from_str(r#"{"features":["metrics_batch"]}"#).unwrap();
+        assert!(response.server_time.is_none());
+        assert!(response.supports("metrics_batch"));
+        assert!(response.server_config().is_none());
+
+        let response: HeartbeatResponse =
+            serde_json::from_str(r#"{"config":{"version":2,"log_level":"debug"}}"#).unwrap();
+        let config = response.server_config().unwrap().unwrap();
+        assert_eq!(config.version, 2);
+        assert!(config.metrics_interval.is_none());
+
+        let response: HeartbeatResponse =
+            serde_json::from_str(r#"{"features":["metrics_batch"],"config":{"log_level":1}}"#)
+                .unwrap();
+        assert!(response.supports("metrics_batch"));
+        assert!(response.server_config().unwrap().is_err());
+    }
+}
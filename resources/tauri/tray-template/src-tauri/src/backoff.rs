@@ -0,0 +1,175 @@
+//! Shared retry policy for every loop that talks to the backend
+//!
+//! After a failure the wait grows exponentially from `initial` up to `max`,
+//! with random jitter so a fleet that failed together doesn't retry
+//! together. A `Retry-After` from the server is honoured (up to an hour)
+//! and jittered upwards. One success resets the policy.
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use crate::api::ApiError;
+
+/// Fraction of each delay that is randomized
+const JITTER: f64 = 0.2;
+
+/// Longest `Retry-After` honoured, so a bad header can't park the agent
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Exponential backoff with jitter and `Retry-After` support
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    /// Failures since the last success
+    failures: u32,
+    /// When the current wait ends
+    until: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            failures: 0,
+            until: None,
+        }
+    }
+
+    /// Record a failure and return how long to wait before trying again
+    pub fn fail(&mut self, retry_after: Option<Duration>) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+
+        // initial, 2 x initial, 4 x initial ... capped at max, minus up to 20%
+        let exponent = (self.failures - 1).min(31);
+        let delay = self.initial.saturating_mul(1 << exponent).min(self.max);
+        let mut delay = delay.mul_f64(1.0 - JITTER * random_fraction());
+
+        // The server's wait, plus up to 20% so clients don't return at once
+        if let Some(retry_after) = retry_after {
+            let retry_after = retry_after.min(MAX_RETRY_AFTER);
+            delay = delay.max(retry_after.mul_f64(1.0 + JITTER * random_fraction()));
+        }
+
+        self.until = Some(Instant::now() + delay);
+        delay
+    }
+
+    /// Record a success, ending any wait
+    pub fn succeed(&mut self) {
+        self.failures = 0;
+        self.until = None;
+    }
+
+    /// Failures since the last success
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Whether the wait after the last failure is still running
+    pub fn is_waiting(&self) -> bool {
+        self.until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Time until the next attempt for a loop that runs every `interval`:
+    /// the interval, or the rest of the backoff if that is longer
+    pub fn wait(&self, interval: Duration) -> Duration {
+        match self.until {
+            Some(until) => until
+                .saturating_duration_since(Instant::now())
+                .max(interval),
+            None => interval,
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, in delay-seconds or HTTP-date form
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = date.signed_duration_since(chrono::Utc::now()).num_seconds();
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// The `Retry-After` carried by a backend error anywhere in an error chain
+pub fn retry_after_of(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<ApiError>())
+        .and_then(ApiError::retry_after)
+}
+
+/// Random number in [0, 1) from the standard library's per-process hash keys
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_delays_grow_with_jitter_and_honour_retry_after() {
+        let mut backoff = Backoff::new(Duration::from_secs(30), Duration::from_secs(300));
+        let expected = [30, 60, 120, 240, 300, 300];
+        for secs in expected {
+            let delay = backoff.fail(None);
+            let full = Duration::from_secs(secs);
+            assert!(delay <= full && delay >= full.mul_f64(0.8), "{:?}", delay);
+        }
+        assert!(backoff.is_waiting());
+        assert!(backoff.wait(Duration::from_secs(10)) > Duration::from_secs(200));
+
+        let delay = backoff.fail(Some(Duration::from_secs(900)));
+        assert!(delay >= Duration::from_secs(900) && delay <= Duration::from_secs(1080));
+        let delay = backoff.fail(Some(Duration::from_secs(86400)));
+        assert!(delay <= MAX_RETRY_AFTER.mul_f64(1.2));
+
+        backoff.succeed();
+        assert_eq!(backoff.failures(), 0);
+        assert!(!backoff.is_waiting());
+        assert_eq!(
+            backoff.wait(Duration::from_secs(10)),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_retry_after_parsing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+        let in_a_minute = (chrono::Utc::now() + chrono::Duration::seconds(61)).to_rfc2822();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&in_a_minute).unwrap());
+        let parsed = parse_retry_after(&headers).unwrap();
+        assert!(parsed >= Duration::from_secs(55) && parsed <= Duration::from_secs(61));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+}
@@ -0,0 +1,578 @@
+//! Native actions - common operations handled in Rust instead of a shell
+//!
+//! Actions don't depend on PowerShell or bash being installed and report
+//! structured JSON rather than scraped text.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::Duration;
+use sysinfo::{Pid, System};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::privileges::RunAs;
+use super::shell::{ShellOptions, ShellSession};
+use super::transfer::{ExpectedFile, FileTransfer};
+use crate::api::ApiClient;
+use crate::config::{Config, AGENT_VERSION};
+use crate::metrics::MetricsCollector;
+use crate::sysinfo::SystemInfo;
+use crate::updater::Updater;
+
+/// Delay before a reboot or shutdown when the backend doesn't specify one
+const DEFAULT_POWER_DELAY_SECS: u64 = 60;
+
+/// A typed action sent in the command payload instead of a script
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NativeAction {
+    /// Reboot the machine
+    Reboot {
+        #[serde(default)]
+        delay_seconds: Option<u64>,
+    },
+    /// Power off the machine
+    Shutdown {
+        #[serde(default)]
+        delay_seconds: Option<u64>,
+    },
+    /// Restart the agent service
+    RestartAgent,
+    /// Kill a process by PID or by exact name
+    KillProcess {
+        #[serde(default)]
+        pid: Option<u32>,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Restart an OS service
+    RestartService { name: String },
+    /// Gather system information now
+    CollectSystemInfo,
+    /// Collect and submit metrics immediately
+    SubmitMetrics,
+    /// Check for (and download) an agent update now
+    CheckForUpdate,
+    /// Upload a file from this machine to the backend
+    FetchFile {
+        path: String,
+        #[serde(default)]
+        max_bytes: Option<u64>,
+    },
+    /// Download a file from the backend and place it on this machine
+    PutFile {
+        path: String,
+        sha256: String,
+        #[serde(default)]
+        size: Option<u64>,
+        /// Octal permissions such as "0644" (Unix only)
+        #[serde(default)]
+        mode: Option<String>,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    /// Open an interactive shell over an outbound WebSocket
+    OpenShell {
+        #[serde(default)]
+        cols: Option<u16>,
+        #[serde(default)]
+        rows: Option<u16>,
+        #[serde(default)]
+        idle_timeout_seconds: Option<u64>,
+        #[serde(default)]
+        max_duration_seconds: Option<u64>,
+    },
+    /// An action this agent doesn't know or couldn't parse
+    #[serde(skip)]
+    Unsupported { action_type: String, reason: String },
+}
+
+impl NativeAction {
+    /// Short name used in logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            NativeAction::Reboot { .. } => "reboot",
+            NativeAction::Shutdown { .. } => "shutdown",
+            NativeAction::RestartAgent => "restart_agent",
+            NativeAction::KillProcess { .. } => "kill_process",
+            NativeAction::RestartService { .. } => "restart_service",
+            NativeAction::CollectSystemInfo => "collect_system_info",
+            NativeAction::SubmitMetrics => "submit_metrics",
+            NativeAction::CheckForUpdate => "check_for_update",
+            NativeAction::FetchFile { .. } => "fetch_file",
+            NativeAction::PutFile { .. } => "put_file",
+            NativeAction::OpenShell { .. } => "open_shell",
+            NativeAction::Unsupported { .. } => "unsupported",
+        }
+    }
+
+    /// Whether the action reads or writes files or runs programs for the
+    /// server, and so runs as the script account unless `requires_admin`
+    pub fn acts_as_account(&self) -> bool {
+        matches!(
+            self,
+            NativeAction::FetchFile { .. }
+                | NativeAction::PutFile { .. }
+                | NativeAction::OpenShell { .. }
+        )
+    }
+
+    /// Parse the `action` field without failing the whole command
+    ///
+    /// The backend marks a command as sent once it is fetched, so a payload
+    /// that doesn't parse must still come back with a result.
+    pub fn deserialize_lenient<'de, D>(
+        deserializer: D,
+    ) -> std::result::Result<Option<Self>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+        Ok(value.filter(|v| !v.is_null()).map(|value| {
+            let action_type = value
+                .get("type")
+                .and_then(|t| t.as_str())
+                .unwrap_or("unknown")
+                .to_string();
+            serde_json::from_value(value).unwrap_or_else(|e| NativeAction::Unsupported {
+                action_type,
+                reason: e.to_string(),
+            })
+        }))
+    }
+}
+
+/// Work to do once the result has been delivered (it would be lost otherwise)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowUp {
+    RestartAgent,
+    Reboot { delay_secs: u64 },
+    Shutdown { delay_secs: u64 },
+}
+
+/// The command an action runs for
+#[derive(Debug, Clone)]
+pub struct ActionContext {
+    pub command_id: u64,
+    /// Bounds actions that can take a while (file transfers)
+    pub timeout: Duration,
+    /// Fired when the agent shuts down
+    pub shutdown: CancellationToken,
+    /// Account file transfers and shells act as (the agent's own when absent)
+    pub run_as: Option<RunAs>,
+    /// Directories file transfers are confined to (from the local policy)
+    pub transfer_roots: Option<Vec<PathBuf>>,
+}
+
+/// Result of a native action
+#[derive(Debug)]
+pub struct ActionOutcome {
+    /// Structured result reported to the backend
+    pub data: serde_json::Value,
+    /// Action to take after reporting
+    pub follow_up: Option<FollowUp>,
+}
+
+impl ActionOutcome {
+    fn done(data: serde_json::Value) -> Self {
+        Self {
+            data,
+            follow_up: None,
+        }
+    }
+}
+
+/// Runs native actions
+pub struct ActionRunner {
+    config: Config,
+    hostname: String,
+    api: ApiClient,
+}
+
+impl ActionRunner {
+    /// Create a new action runner
+    pub fn new(config: Config, hostname: String, api: ApiClient) -> Self {
+        Self {
+            config,
+            hostname,
+            api,
+        }
+    }
+
+    fn file_transfer(&self) -> FileTransfer {
+        FileTransfer::new(self.config.clone(), self.api.clone())
+    }
+
+    /// Run an action and describe what happened
+    pub async fn run(
+        &self,
+        context: &ActionContext,
+        action: &NativeAction,
+        api_key: &str,
+    ) -> Result<ActionOutcome> {
+        match action {
+            NativeAction::Reboot { delay_seconds } => {
+                let delay_secs = delay_seconds.unwrap_or(DEFAULT_POWER_DELAY_SECS);
+                Ok(ActionOutcome {
+                    data: json!({ "scheduled": "reboot", "delay_seconds": delay_secs }),
+                    follow_up: Some(FollowUp::Reboot { delay_secs }),
+                })
+            }
+            NativeAction::Shutdown { delay_seconds } => {
+                let delay_secs = delay_seconds.unwrap_or(DEFAULT_POWER_DELAY_SECS);
+                Ok(ActionOutcome {
+                    data: json!({ "scheduled": "shutdown", "delay_seconds": delay_secs }),
+                    follow_up: Some(FollowUp::Shutdown { delay_secs }),
+                })
+            }
+            NativeAction::RestartAgent => {
+                if !cfg!(windows) {
+                    anyhow::bail!("Agent restart is only supported when running as a Windows service");
+                }
+                Ok(ActionOutcome {
+                    data: json!({ "scheduled": "restart_agent", "agent_version": AGENT_VERSION }),
+                    follow_up: Some(FollowUp::RestartAgent),
+                })
+            }
+            NativeAction::KillProcess { pid, name } => {
+                kill_process(*pid, name.as_deref()).map(ActionOutcome::done)
+            }
+            NativeAction::RestartService { name } => {
+                restart_service(name).await.map(ActionOutcome::done)
+            }
+            NativeAction::CollectSystemInfo => {
+                let info = tokio::task::spawn_blocking(SystemInfo::gather)
+                    .await
+                    .context("System info task failed")??;
+                Ok(ActionOutcome::done(serde_json::to_value(info)?))
+            }
+            NativeAction::SubmitMetrics => self.submit_metrics(api_key).await.map(ActionOutcome::done),
+            NativeAction::CheckForUpdate => self.check_for_update().await,
+            NativeAction::FetchFile { path, max_bytes } => self
+                .file_transfer()
+                .fetch(context, path, *max_bytes, api_key)
+                .await
+                .map(ActionOutcome::done),
+            NativeAction::PutFile {
+                path,
+                sha256,
+                size,
+                mode,
+                overwrite,
+            } => {
+                let expected = ExpectedFile {
+                    sha256,
+                    size: *size,
+                    mode: mode.as_deref(),
+                    overwrite: *overwrite,
+                };
+                self.file_transfer()
+                    .put(context, path, &expected, api_key)
+                    .await
+                    .map(ActionOutcome::done)
+            }
+            NativeAction::OpenShell {
+                cols,
+                rows,
+                idle_timeout_seconds,
+                max_duration_seconds,
+            } => {
+                let options = ShellOptions::new(
+                    &self.config,
+                    *cols,
+                    *rows,
+                    *idle_timeout_seconds,
+                    *max_duration_seconds,
+                );
+                self.open_shell(context, options, api_key)
+                    .await
+                    .map(ActionOutcome::done)
+            }
+            NativeAction::Unsupported {
+                action_type,
+                reason,
+            } => anyhow::bail!("Unsupported action '{}': {}", action_type, reason),
+        }
+    }
+
+    /// Carry out a follow-up once the result has been reported
+    pub fn run_follow_up(&self, follow_up: FollowUp) -> Result<()> {
+        info!("Running follow-up action: {:?}", follow_up);
+
+        match follow_up {
+            FollowUp::RestartAgent => Updater::new(self.config.clone(), self.api.clone()).trigger_restart(),
+            FollowUp::Reboot { delay_secs } => run_power_command(true, delay_secs),
+            FollowUp::Shutdown { delay_secs } => run_power_command(false, delay_secs),
+        }
+    }
+
+    async fn submit_metrics(&self, api_key: &str) -> Result<serde_json::Value> {
+        let collector = MetricsCollector::new(self.config.clone(), self.hostname.clone(), self.api.clone())?;
+        let metrics = collector.collect_metrics(&self.config.netdata_contexts).await;
+        collector.submit_metrics(&metrics, api_key).await?;
+
+        Ok(json!({
+            "submitted": true,
+            "timestamp": metrics.timestamp,
+            "netdata_available": metrics.netdata_info.is_some(),
+        }))
+    }
+
+    /// Connect a shell session and leave it running in the background
+    ///
+    /// The command's result only says the session opened, so the command
+    /// loop isn't held up for the length of the session.
+    async fn open_shell(
+        &self,
+        context: &ActionContext,
+        options: ShellOptions,
+        api_key: &str,
+    ) -> Result<serde_json::Value> {
+        let session = ShellSession::connect(
+            &self.config,
+            &self.api,
+            context.command_id,
+            options,
+            context.run_as.clone(),
+            api_key,
+        )
+        .await?;
+        let transcript = session.transcript_path().display().to_string();
+
+        let command_id = context.command_id;
+        let shutdown = context.shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = session.run(shutdown).await {
+                warn!("Shell session for command {} failed: {:#}", command_id, e);
+            }
+        });
+
+        Ok(json!({
+            "session": "opened",
+            "transcript": transcript,
+            "idle_timeout_seconds": options.idle_timeout.as_secs(),
+            "max_duration_seconds": options.max_duration.as_secs(),
+        }))
+    }
+
+    async fn check_for_update(&self) -> Result<ActionOutcome> {
+        let updater = Updater::new(self.config.clone(), self.api.clone());
+
+        match updater.check_only().await? {
+            Some(update) => {
+                let path = updater.download_update(&update).await?;
+                Ok(ActionOutcome {
+                    data: json!({
+                        "current_version": AGENT_VERSION,
+                        "update_available": true,
+                        "version": update.version,
+                        "downloaded_to": path.display().to_string(),
+                    }),
+                    follow_up: Some(FollowUp::RestartAgent),
+                })
+            }
+            None => Ok(ActionOutcome::done(json!({
+                "current_version": AGENT_VERSION,
+                "update_available": false,
+            }))),
+        }
+    }
+}
+
+/// Why a process must not be killed remotely, if it mustn't
+fn protected_process(pid: u32) -> Option<&'static str> {
+    match pid {
+        0 => Some("it is the kernel's idle process"),
+        1 => Some("it is the init process"),
+        pid if pid == std::process::id() => Some("it is the agent itself"),
+        _ => None,
+    }
+}
+
+/// Kill a process by PID or every process with the given exact name
+///
+/// The kernel, init and the agent are never killed: losing any of them
+/// would take the device offline.
+fn kill_process(pid: Option<u32>, name: Option<&str>) -> Result<serde_json::Value> {
+    if let Some(reason) = pid.and_then(protected_process) {
+        anyhow::bail!("Refusing to kill process {}: {}", pid.unwrap_or_default(), reason);
+    }
+
+    let mut sys = System::new();
+    sys.refresh_processes();
+
+    let targets: Vec<(u32, String)> = match (pid, name) {
+        (Some(pid), _) => sys
+            .process(Pid::from_u32(pid))
+            .map(|p| vec![(pid, p.name().to_string())])
+            .unwrap_or_default(),
+        (None, Some(name)) => {
+            let (protected, targets): (Vec<_>, Vec<_>) = sys
+                .processes_by_exact_name(name)
+                .map(|p| (p.pid().as_u32(), p.name().to_string()))
+                .partition(|(pid, _)| protected_process(*pid).is_some());
+            if targets.is_empty() && !protected.is_empty() {
+                anyhow::bail!("Refusing to kill '{}': it is the agent or a system process", name);
+            }
+            targets
+        }
+        (None, None) => anyhow::bail!("kill_process requires a pid or a name"),
+    };
+
+    if targets.is_empty() {
+        anyhow::bail!("No matching process found");
+    }
+
+    let mut killed = Vec::new();
+    let mut failed = Vec::new();
+    for (pid, name) in targets {
+        let ok = sys.process(Pid::from_u32(pid)).map(|p| p.kill()).unwrap_or(false);
+        if ok {
+            killed.push(json!({ "pid": pid, "name": name }));
+        } else {
+            warn!("Failed to kill process {} ({})", pid, name);
+            failed.push(json!({ "pid": pid, "name": name }));
+        }
+    }
+
+    if killed.is_empty() {
+        anyhow::bail!("Failed to kill {} matching process(es)", failed.len());
+    }
+
+    Ok(json!({ "killed": killed, "failed": failed }))
+}
+
+/// Restart an OS service using the platform service manager
+async fn restart_service(name: &str) -> Result<serde_json::Value> {
+    if name.is_empty() || name.starts_with('-') {
+        anyhow::bail!("Invalid service name: {:?}", name);
+    }
+
+    let steps: Vec<(&str, Vec<String>)> = if cfg!(windows) {
+        vec![
+            ("net", vec!["stop".to_string(), name.to_string()]),
+            ("net", vec!["start".to_string(), name.to_string()]),
+        ]
+    } else if cfg!(target_os = "macos") {
+        vec![(
+            "launchctl",
+            vec!["kickstart".to_string(), "-k".to_string(), format!("system/{}", name)],
+        )]
+    } else {
+        vec![("systemctl", vec!["restart".to_string(), name.to_string()])]
+    };
+
+    for (program, args) in steps {
+        // Dropped (and killed) if the command times out or is cancelled
+        let output = tokio::process::Command::new(program)
+            .args(&args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {}", program))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("{} {} failed: {}", program, args.join(" "), stderr.trim());
+        }
+    }
+
+    Ok(json!({ "service": name, "restarted": true }))
+}
+
+/// Program and arguments for a (delayed) reboot or power off
+fn power_command(reboot: bool, delay_secs: u64) -> (String, Vec<String>) {
+    if cfg!(windows) {
+        let mode = if reboot { "/r" } else { "/s" };
+        (
+            "shutdown".to_string(),
+            vec![mode.to_string(), "/t".to_string(), delay_secs.to_string()],
+        )
+    } else {
+        let mode = if reboot { "-r" } else { "-h" };
+        // Unix shutdown only takes whole minutes
+        let when = match delay_secs.div_ceil(60) {
+            0 => "now".to_string(),
+            minutes => format!("+{}", minutes),
+        };
+        ("shutdown".to_string(), vec![mode.to_string(), when])
+    }
+}
+
+fn run_power_command(reboot: bool, delay_secs: u64) -> Result<()> {
+    let (program, args) = power_command(reboot, delay_secs);
+    let output = std::process::Command::new(&program)
+        .args(&args)
+        .output()
+        .with_context(|| format!("Failed to execute {}", program))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} {} failed: {}", program, args.join(" "), stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kill_process_spares_init_and_the_agent() {
+        for pid in [0, 1, std::process::id()] {
+            let error = kill_process(Some(pid), None).unwrap_err();
+            assert!(error.to_string().starts_with("Refusing to kill"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_action_deserialization() {
+        let action: NativeAction =
+            serde_json::from_str(r#"{"type":"kill_process","name":"notepad.exe"}"#).unwrap();
+        assert_eq!(
+            action,
+            NativeAction::KillProcess {
+                pid: None,
+                name: Some("notepad.exe".to_string())
+            }
+        );
+
+        let action: NativeAction = serde_json::from_str(r#"{"type":"reboot"}"#).unwrap();
+        assert_eq!(action, NativeAction::Reboot { delay_seconds: None });
+        assert_eq!(action.name(), "reboot");
+
+        let action: NativeAction = serde_json::from_str(
+            r#"{"type":"put_file","path":"/etc/app.conf","sha256":"ab","mode":"0600"}"#,
+        )
+        .unwrap();
+        assert_eq!(action.name(), "put_file");
+        assert!(matches!(action, NativeAction::PutFile { overwrite: false, .. }));
+
+        assert!(serde_json::from_str::<NativeAction>(r#"{"type":"format_disk"}"#).is_err());
+
+        // Unknown and malformed actions still parse, so they can be reported
+        let action = NativeAction::deserialize_lenient(serde_json::json!({"type":"format_disk"})).unwrap();
+        assert!(matches!(
+            action,
+            Some(NativeAction::Unsupported { ref action_type, .. }) if action_type == "format_disk"
+        ));
+        let action = NativeAction::deserialize_lenient(serde_json::json!({"type":"restart_service"})).unwrap();
+        assert!(matches!(action, Some(NativeAction::Unsupported { .. })));
+        assert_eq!(NativeAction::deserialize_lenient(serde_json::Value::Null).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_power_command_rounds_to_minutes() {
+        assert_eq!(
+            power_command(true, 90),
+            ("shutdown".to_string(), vec!["-r".to_string(), "+2".to_string()])
+        );
+        assert_eq!(
+            power_command(false, 0),
+            ("shutdown".to_string(), vec!["-h".to_string(), "now".to_string()])
+        );
+    }
+}
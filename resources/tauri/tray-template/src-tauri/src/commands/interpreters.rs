@@ -0,0 +1,386 @@
+//! Script interpreter discovery
+//!
+//! Hosts differ in what they have installed (pwsh vs powershell, no bash on
+//! Alpine, python on some machines only), so interpreters are looked up on
+//! `PATH` when a script runs rather than assumed. A `#!` line in the script
+//! takes precedence over `script_type`; when it names an interpreter the
+//! agent knows, the script is also written, invoked and has its
+//! placeholders rendered as that type. Installed interpreters and their
+//! versions are reported to the backend as capabilities in the heartbeat.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+use super::ScriptType;
+
+/// How long a `--version` probe may take
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// All script types, in the order they are reported
+const ALL_SCRIPT_TYPES: [ScriptType; 5] = [
+    ScriptType::PowerShell,
+    ScriptType::Bash,
+    ScriptType::Sh,
+    ScriptType::Cmd,
+    ScriptType::Python,
+];
+
+/// Interpreters found at startup, reported as capabilities
+static DISCOVERED: OnceCell<Vec<InterpreterInfo>> = OnceCell::const_new();
+
+/// A resolved interpreter and the arguments that go before the script path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Type the script runs as (its `#!` interpreter's, if known)
+    pub script_type: ScriptType,
+}
+
+/// An installed interpreter, as reported to the backend
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpreterInfo {
+    /// Script type it serves (`powershell`, `bash`, ...)
+    pub script_type: &'static str,
+    /// Full path of the interpreter
+    pub path: String,
+    /// First line of its version output, if it has one
+    pub version: Option<String>,
+}
+
+/// Interpreter named on a script's `#!` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shebang {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Shebang {
+    /// Parse the first line of a script (`#!/usr/bin/env -S python3 -u` etc.)
+    pub fn parse(script: &str) -> Option<Self> {
+        let line = script.lines().next()?.strip_prefix("#!")?;
+        let mut parts = line.split_whitespace().map(str::to_string);
+        let mut program = parts.next()?;
+        let mut args: Vec<String> = parts.collect();
+
+        if file_name(&program) == "env" {
+            // Skip env's own flags (-S, -i, ...) to get at the real interpreter
+            let start = args.iter().position(|a| !a.starts_with('-'))?;
+            args.drain(..start);
+            program = args.remove(0);
+        }
+
+        Some(Self { program, args })
+    }
+
+    /// Interpreter name without its directory (`python3`, `bash`, ...)
+    pub fn name(&self) -> &str {
+        file_name(&self.program)
+    }
+
+    /// Script type of the interpreter, if the agent knows it
+    pub fn script_type(&self) -> Option<ScriptType> {
+        let name = self.name();
+        ScriptType::parse(name.strip_suffix(".exe").unwrap_or(name))
+    }
+
+    /// Script type and arguments for running a script with this interpreter
+    ///
+    /// A known interpreter keeps the arguments the agent always passes it
+    /// (PowerShell's `-NoProfile ... -File`); the script's own come first.
+    fn invocation(self, declared: ScriptType) -> (ScriptType, Vec<String>) {
+        match self.script_type() {
+            Some(script_type) => {
+                let mut args = self.args;
+                args.extend(script_type.script_args());
+                (script_type, args)
+            }
+            None => (declared, self.args),
+        }
+    }
+}
+
+/// The type a script really runs as: its `#!` interpreter's, else `script_type`
+pub fn effective_type(script_type: ScriptType, script: &str) -> ScriptType {
+    Shebang::parse(script)
+        .and_then(|shebang| shebang.script_type())
+        .unwrap_or(script_type)
+}
+
+impl ScriptType {
+    /// Programs that can run this script type, in order of preference
+    pub fn candidates(&self) -> &'static [&'static str] {
+        match self {
+            #[cfg(windows)]
+            ScriptType::PowerShell => &["powershell", "pwsh"],
+            #[cfg(not(windows))]
+            ScriptType::PowerShell => &["pwsh", "powershell"],
+            ScriptType::Bash => &["bash"],
+            ScriptType::Sh => &["sh"],
+            #[cfg(windows)]
+            ScriptType::Cmd => &["cmd"],
+            #[cfg(not(windows))]
+            ScriptType::Cmd => &[],
+            #[cfg(windows)]
+            ScriptType::Python => &["python", "py", "python3"],
+            #[cfg(not(windows))]
+            ScriptType::Python => &["python3", "python"],
+        }
+    }
+
+    /// Arguments placed before the script path
+    fn script_args(&self) -> Vec<String> {
+        match self {
+            ScriptType::PowerShell => [
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ]
+            .iter()
+            .map(|a| a.to_string())
+            .collect(),
+            ScriptType::Cmd => vec!["/C".to_string()],
+            ScriptType::Bash | ScriptType::Sh | ScriptType::Python => Vec::new(),
+        }
+    }
+
+    /// Arguments that print the interpreter's version
+    fn version_args(&self) -> Option<&'static [&'static str]> {
+        match self {
+            ScriptType::PowerShell => Some(&[
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                "$PSVersionTable.PSVersion.ToString()",
+            ]),
+            ScriptType::Bash | ScriptType::Python => Some(&["--version"]),
+            ScriptType::Sh | ScriptType::Cmd => None,
+        }
+    }
+}
+
+/// Work out how to run a script, failing if its interpreter isn't installed
+pub fn resolve(script_type: ScriptType, script: &str) -> Result<Invocation> {
+    if let Some(shebang) = Shebang::parse(script) {
+        // Fall back to a PATH lookup when the shebang's path doesn't exist here
+        let program = find_program(&shebang.program)
+            .or_else(|| find_program(shebang.name()))
+            .ok_or_else(|| anyhow::anyhow!("Interpreter not available: {}", shebang.program))?;
+        let (script_type, args) = shebang.invocation(script_type);
+        return Ok(Invocation {
+            program,
+            args,
+            script_type,
+        });
+    }
+
+    let candidates = script_type.candidates();
+    let program = candidates
+        .iter()
+        .find_map(|name| find_program(name))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Interpreter not available: {} (looked for {})",
+                script_type.as_str(),
+                if candidates.is_empty() {
+                    "nothing on this platform".to_string()
+                } else {
+                    candidates.join(", ")
+                }
+            )
+        })?;
+
+    Ok(Invocation {
+        program,
+        args: script_type.script_args(),
+        script_type,
+    })
+}
+
+/// Interpreters available on this machine (discovered once, then cached)
+pub async fn capabilities() -> &'static [InterpreterInfo] {
+    DISCOVERED.get_or_init(discover).await
+}
+
+/// Find every supported interpreter and its version
+pub async fn discover() -> Vec<InterpreterInfo> {
+    let mut found = Vec::new();
+
+    for script_type in ALL_SCRIPT_TYPES {
+        let Some(path) = script_type
+            .candidates()
+            .iter()
+            .find_map(|name| find_program(name))
+        else {
+            continue;
+        };
+        let version = match script_type.version_args() {
+            Some(args) => probe_version(&path, args).await,
+            None => None,
+        };
+        debug!(
+            "Found {} interpreter at {:?} ({})",
+            script_type.as_str(),
+            path,
+            version.as_deref().unwrap_or("unknown version")
+        );
+        found.push(InterpreterInfo {
+            script_type: script_type.as_str(),
+            path: path.display().to_string(),
+            version,
+        });
+    }
+
+    found
+}
+
+async fn probe_version(program: &Path, args: &[&str]) -> Option<String> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(VERSION_PROBE_TIMEOUT, output)
+        .await
+        .ok()?
+        .ok()?;
+
+    // Python 2 prints its version to stderr
+    [&output.stdout, &output.stderr].iter().find_map(|bytes| {
+        String::from_utf8_lossy(bytes)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// Resolve a program name or path to an executable file
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    if name.contains('/') || name.contains('\\') {
+        let path = PathBuf::from(name);
+        return is_executable(&path).then_some(path);
+    }
+
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var).find_map(|dir| {
+        executable_names(name)
+            .into_iter()
+            .map(|candidate| dir.join(candidate))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// File names to try for a program (Windows adds the PATHEXT extensions)
+fn executable_names(name: &str) -> Vec<String> {
+    #[cfg(windows)]
+    {
+        if Path::new(name).extension().is_some() {
+            return vec![name.to_string()];
+        }
+        let exts = std::env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".to_string());
+        exts.split(';')
+            .filter(|e| !e.is_empty())
+            .map(|e| format!("{}{}", name, e.to_lowercase()))
+            .collect()
+    }
+
+    #[cfg(not(windows))]
+    {
+        vec![name.to_string()]
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    if !metadata.is_file() {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o111 != 0
+    }
+
+    #[cfg(not(unix))]
+    {
+        true
+    }
+}
+
+fn file_name(program: &str) -> &str {
+    program.rsplit(['/', '\\']).next().unwrap_or(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shebang() {
+        assert_eq!(
+            Shebang::parse("#!/usr/bin/env -S python3 -u\nprint(1)\n"),
+            Some(Shebang {
+                program: "python3".to_string(),
+                args: vec!["-u".to_string()],
+            })
+        );
+
+        let shebang = Shebang::parse("#!/bin/bash -e\r\necho hi").unwrap();
+        assert_eq!(shebang.program, "/bin/bash");
+        assert_eq!(shebang.args, vec!["-e".to_string()]);
+        assert_eq!(shebang.name(), "bash");
+
+        assert_eq!(Shebang::parse("echo hi\n#!/bin/sh"), None);
+        assert_eq!(Shebang::parse("#!/usr/bin/env\n"), None);
+    }
+
+    #[test]
+    fn test_shebang_decides_script_type() {
+        assert_eq!(
+            effective_type(ScriptType::PowerShell, "#!/bin/bash\necho $HOME"),
+            ScriptType::Bash
+        );
+        assert_eq!(
+            effective_type(ScriptType::Bash, "#!/usr/bin/env perl\n"),
+            ScriptType::Bash
+        );
+        assert_eq!(effective_type(ScriptType::Sh, "echo hi"), ScriptType::Sh);
+
+        // pwsh named on a #! line still gets the flags the agent relies on
+        let shebang = Shebang::parse("#!C:\\Tools\\pwsh.exe -NoLogo\n").unwrap();
+        let (script_type, args) = shebang.invocation(ScriptType::Cmd);
+        assert_eq!(script_type, ScriptType::PowerShell);
+        assert_eq!(args.first().map(String::as_str), Some("-NoLogo"));
+        assert_eq!(args.last().map(String::as_str), Some("-File"));
+        assert!(args.iter().any(|a| a == "Bypass"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_interpreters() {
+        let invocation = resolve(ScriptType::Sh, "echo hi").unwrap();
+        assert!(invocation.program.is_absolute());
+        assert!(invocation.args.is_empty());
+
+        // A shebang path that doesn't exist falls back to the PATH
+        let invocation = resolve(ScriptType::Bash, "#!/no/such/dir/sh\necho hi").unwrap();
+        assert!(invocation.program.ends_with("sh"));
+
+        let err = resolve(ScriptType::Sh, "#!/usr/bin/env no-such-interpreter\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Interpreter not available: no-such-interpreter"
+        );
+        assert!(resolve(ScriptType::Cmd, "dir").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Durable command journal
+//!
+//! Records every command the agent receives, when it starts and its result
+//! until the backend has acknowledged it. After a crash or restart the
+//! journal is replayed: unsent results are re-posted and commands that were
+//! interrupted mid-run are reported as failed.
+//!
+//! Results include the output of scripts run as root, so on Unix the file is
+//! only readable by the agent (0600).
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use super::CommandResult;
+
+/// Reason reported for commands interrupted by an agent restart
+pub const AGENT_RESTARTED_MESSAGE: &str =
+    "Command interrupted: agent restarted before the command finished";
+
+/// Lifecycle stage of a journaled command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalState {
+    /// Fetched from the backend, not yet started
+    Received,
+    /// Script is running
+    Started,
+    /// Finished; the result has not been acknowledged by the backend yet
+    Finished,
+}
+
+/// A single journaled command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub command_id: u64,
+    pub state: JournalState,
+    /// RFC 3339 timestamp of the last state change
+    pub updated_at: String,
+    /// Result waiting to be delivered (only when finished)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<CommandResult>,
+}
+
+/// Persistent journal of in-flight commands and unsent results
+pub struct CommandJournal {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<u64, JournalEntry>>,
+}
+
+impl CommandJournal {
+    /// Move a journal from where older agents kept it, if there is one
+    pub fn adopt_legacy(legacy: &Path, path: &Path) {
+        if path.exists() || !legacy.exists() {
+            return;
+        }
+        match std::fs::rename(legacy, path) {
+            Ok(()) => {
+                restrict_permissions(path);
+                debug!("Moved command journal from {:?} to {:?}", legacy, path);
+            }
+            Err(e) => warn!("Failed to move command journal from {:?}: {}", legacy, e),
+        }
+    }
+
+    /// Load the journal from disk (an unreadable journal starts empty)
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Vec<JournalEntry>>(&content) {
+                Ok(entries) => entries.into_iter().map(|e| (e.command_id, e)).collect(),
+                Err(e) => {
+                    warn!("Command journal {:?} is corrupted, starting fresh: {}", path, e);
+                    BTreeMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("Failed to read command journal {:?}: {}", path, e);
+                BTreeMap::new()
+            }
+        };
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Record that a command was received from the backend
+    pub async fn record_received(&self, command_id: u64) -> Result<()> {
+        self.update(command_id, JournalState::Received, None).await
+    }
+
+    /// Record that a command has started running
+    pub async fn record_started(&self, command_id: u64) -> Result<()> {
+        self.update(command_id, JournalState::Started, None).await
+    }
+
+    /// Record a command's result before it is sent
+    pub async fn record_finished(&self, command_id: u64, result: &CommandResult) -> Result<()> {
+        self.update(command_id, JournalState::Finished, Some(result.clone()))
+            .await
+    }
+
+    /// Forget a command once the backend has its result
+    pub async fn complete(&self, command_id: u64) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        if entries.remove(&command_id).is_some() {
+            self.persist(&entries).await?;
+        }
+        Ok(())
+    }
+
+    /// Turn commands left running by a previous agent process into failed results
+    ///
+    /// Returns the IDs of the interrupted commands
+    pub async fn recover_interrupted(&self) -> Result<Vec<u64>> {
+        let mut entries = self.entries.lock().await;
+        let mut recovered = Vec::new();
+
+        for entry in entries.values_mut() {
+            if entry.state != JournalState::Finished {
+                entry.state = JournalState::Finished;
+                entry.updated_at = Utc::now().to_rfc3339();
+                entry.result = Some(CommandResult::agent_error(AGENT_RESTARTED_MESSAGE));
+                recovered.push(entry.command_id);
+            }
+        }
+
+        if !recovered.is_empty() {
+            self.persist(&entries).await?;
+        }
+
+        Ok(recovered)
+    }
+
+    /// Results that still need to be delivered, oldest command first
+    pub async fn unsent_results(&self) -> Vec<(u64, CommandResult)> {
+        self.entries
+            .lock()
+            .await
+            .values()
+            .filter(|e| e.state == JournalState::Finished)
+            .filter_map(|e| e.result.clone().map(|r| (e.command_id, r)))
+            .collect()
+    }
+
+    async fn update(
+        &self,
+        command_id: u64,
+        state: JournalState,
+        result: Option<CommandResult>,
+    ) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        entries.insert(
+            command_id,
+            JournalEntry {
+                command_id,
+                state,
+                updated_at: Utc::now().to_rfc3339(),
+                result,
+            },
+        );
+        self.persist(&entries).await
+    }
+
+    /// Write the journal atomically (temp file + rename)
+    async fn persist(&self, entries: &BTreeMap<u64, JournalEntry>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context("Failed to create command journal directory")?;
+        }
+
+        let list: Vec<&JournalEntry> = entries.values().collect();
+        let content =
+            serde_json::to_string_pretty(&list).context("Failed to serialize command journal")?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        // A leftover temp file would keep its old permissions
+        let _ = fs::remove_file(&tmp_path).await;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(&tmp_path)
+            .await
+            .context("Failed to create command journal")?;
+        file.write_all(content.as_bytes())
+            .await
+            .context("Failed to write command journal")?;
+        file.flush()
+            .await
+            .context("Failed to write command journal")?;
+        drop(file);
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .context("Failed to replace command journal")?;
+
+        debug!("Command journal saved ({} entries)", entries.len());
+        Ok(())
+    }
+}
+
+/// Make a journal file readable only by the agent
+fn restrict_permissions(path: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Err(e) = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+            warn!("Failed to restrict permissions on {:?}: {}", path, e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::CommandStatus;
+
+    #[tokio::test]
+    async fn test_journal_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.json");
+
+        let journal = CommandJournal::load(&path);
+        journal.record_received(1).await.unwrap();
+        journal.record_started(1).await.unwrap();
+        journal.record_received(2).await.unwrap();
+        journal
+            .record_finished(2, &CommandResult::agent_error("boom"))
+            .await
+            .unwrap();
+        journal.record_received(3).await.unwrap();
+        journal
+            .record_finished(3, &CommandResult::agent_error("sent"))
+            .await
+            .unwrap();
+        journal.complete(3).await.unwrap();
+        drop(journal);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Simulate the agent coming back up
+        let journal = CommandJournal::load(&path);
+        assert_eq!(journal.recover_interrupted().await.unwrap(), vec![1]);
+
+        let unsent = journal.unsent_results().await;
+        assert_eq!(unsent.len(), 2);
+        assert_eq!(unsent[0].0, 1);
+        assert_eq!(unsent[0].1.status, CommandStatus::Failed);
+        assert_eq!(
+            unsent[0].1.error_message.as_deref(),
+            Some(AGENT_RESTARTED_MESSAGE)
+        );
+        assert_eq!(unsent[1].0, 2);
+        assert_eq!(unsent[1].1.error_message.as_deref(), Some("boom"));
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_journal_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        let journal = CommandJournal::load(&path);
+        assert!(journal.unsent_results().await.is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+//! Resource limits for executed scripts
+//!
+//! Limits come from the command payload (`limits`) and from the local
+//! execution policy. The policy's values are ceilings: a command can only
+//! tighten them. On Linux:
+//! - `cpu_seconds`, `address_space_bytes` and `open_files` become rlimits
+//! - `memory_max_bytes` and `cpu_max_percent` put the script in a transient
+//!   cgroup (cgroup v2) that is removed when it finishes
+//!
+//! Per-command cgroups live inside the agent's own cgroup, which systemd
+//! only hands over with `Delegate=yes` in the service unit. The agent moves
+//! itself into an `agent` leaf there (a cgroup with controllers enabled for
+//! its children can't hold processes) and creates `command-<id>` siblings.
+//!
+//! `output_bytes` is enforced by the agent itself on every platform.
+
+use serde::{Deserialize, Serialize};
+
+/// Where the cgroup v2 hierarchy is mounted
+#[cfg(target_os = "linux")]
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+/// Leaf the agent moves itself into inside its delegated cgroup
+#[cfg(target_os = "linux")]
+const AGENT_CGROUP_LEAF: &str = "agent";
+
+/// Extra CPU seconds between SIGXCPU and the hard limit's SIGKILL
+#[cfg(target_os = "linux")]
+const CPU_HARD_LIMIT_GRACE_SECS: u64 = 5;
+
+/// cgroup `cpu.max` period in microseconds
+#[cfg(target_os = "linux")]
+const CPU_PERIOD_USECS: u64 = 100_000;
+
+/// Per-command resource limits (absent fields are unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// CPU time in seconds (RLIMIT_CPU)
+    #[serde(default)]
+    pub cpu_seconds: Option<u64>,
+    /// Virtual address space per process in bytes (RLIMIT_AS)
+    #[serde(default)]
+    pub address_space_bytes: Option<u64>,
+    /// Open file descriptors per process (RLIMIT_NOFILE)
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// Total stdout + stderr the script may produce before it is killed
+    #[serde(default)]
+    pub output_bytes: Option<u64>,
+    /// Memory cap for the whole process tree (cgroup `memory.max`)
+    #[serde(default)]
+    pub memory_max_bytes: Option<u64>,
+    /// CPU cap for the whole process tree, 100 = one core (cgroup `cpu.max`)
+    #[serde(default)]
+    pub cpu_max_percent: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// These limits, tightened so none exceeds the corresponding `ceiling`
+    pub fn capped_by(&self, ceiling: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            cpu_seconds: min_limit(self.cpu_seconds, ceiling.cpu_seconds),
+            address_space_bytes: min_limit(self.address_space_bytes, ceiling.address_space_bytes),
+            open_files: min_limit(self.open_files, ceiling.open_files),
+            output_bytes: min_limit(self.output_bytes, ceiling.output_bytes),
+            memory_max_bytes: min_limit(self.memory_max_bytes, ceiling.memory_max_bytes),
+            cpu_max_percent: min_limit(self.cpu_max_percent, ceiling.cpu_max_percent),
+        }
+    }
+
+    /// Whether the limits need a cgroup
+    pub fn needs_cgroup(&self) -> bool {
+        self.memory_max_bytes.is_some() || self.cpu_max_percent.is_some()
+    }
+
+    /// Whether the limits need rlimits set in the child
+    pub fn needs_rlimits(&self) -> bool {
+        self.cpu_seconds.is_some() || self.address_space_bytes.is_some() || self.open_files.is_some()
+    }
+}
+
+fn min_limit<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Which limit stopped a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    CpuTime,
+    Memory,
+    Output,
+}
+
+impl LimitKind {
+    /// Human-readable description for the result's error message
+    pub fn describe(&self) -> &'static str {
+        match self {
+            LimitKind::CpuTime => "Command exceeded its CPU time limit",
+            LimitKind::Memory => "Command exceeded its memory limit",
+            LimitKind::Output => "Command exceeded its output size limit",
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{apply_rlimits, killed_by_cpu_limit, CommandCgroup};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use anyhow::{Context, Result};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::process::Command;
+    use tracing::{debug, warn};
+
+    #[cfg(target_env = "gnu")]
+    type RlimitResource = libc::__rlimit_resource_t;
+    #[cfg(not(target_env = "gnu"))]
+    type RlimitResource = libc::c_int;
+
+    /// Set rlimits in the child before it execs the interpreter
+    ///
+    /// The hook may run after the switch to the script's account (see
+    /// `RunAs::apply`), so it only ever lowers the inherited limits.
+    pub fn apply_rlimits(command: &mut Command, limits: &ResourceLimits) {
+        if !limits.needs_rlimits() {
+            return;
+        }
+
+        let limits = *limits;
+        // SAFETY: the closure only calls setrlimit, which is async-signal-safe
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(secs) = limits.cpu_seconds {
+                    set_rlimit(
+                        libc::RLIMIT_CPU,
+                        secs,
+                        secs.saturating_add(CPU_HARD_LIMIT_GRACE_SECS),
+                    )?;
+                }
+                if let Some(bytes) = limits.address_space_bytes {
+                    set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
+                }
+                if let Some(files) = limits.open_files {
+                    set_rlimit(libc::RLIMIT_NOFILE, files, files)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn set_rlimit(resource: RlimitResource, soft: u64, hard: u64) -> std::io::Result<()> {
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(resource, &mut current) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // Raising a hard limit needs root, which the child may no longer have
+        let hard = (hard as libc::rlim_t).min(current.rlim_max);
+        let limit = libc::rlimit {
+            rlim_cur: (soft as libc::rlim_t).min(hard),
+            rlim_max: hard,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Whether the script (or the child it waited on) died from RLIMIT_CPU
+    ///
+    /// Shells report a child killed by SIGXCPU as exit code 152, but so does
+    /// a script that just runs `exit 152`. That code only counts when the
+    /// script's cgroup really used `cpu_seconds` of CPU.
+    pub fn killed_by_cpu_limit(
+        status: &std::process::ExitStatus,
+        cpu_seconds: u64,
+        cgroup: Option<&CommandCgroup>,
+    ) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+
+        if status.signal() == Some(libc::SIGXCPU) {
+            return true;
+        }
+        status.code() == Some(128 + libc::SIGXCPU)
+            && cgroup
+                .and_then(|c| c.cpu_usage())
+                .is_some_and(|used| used >= Duration::from_secs(cpu_seconds))
+    }
+
+    /// The agent's delegated cgroup, with the agent moved into its own leaf
+    fn delegated_root() -> Result<PathBuf> {
+        let own = std::fs::read_to_string("/proc/self/cgroup")
+            .context("Failed to read /proc/self/cgroup")?
+            .lines()
+            .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+            .context("The agent is not in a cgroup v2 hierarchy")?;
+        let own = Path::new(CGROUP_MOUNT).join(own.trim().trim_start_matches('/'));
+
+        if own.file_name().is_some_and(|name| name == AGENT_CGROUP_LEAF) {
+            if let Some(root) = own.parent() {
+                return Ok(root.to_path_buf());
+            }
+        }
+        if own == Path::new(CGROUP_MOUNT) {
+            anyhow::bail!(
+                "The agent runs in the root cgroup; set Delegate=yes in its service unit"
+            );
+        }
+
+        let leaf = own.join(AGENT_CGROUP_LEAF);
+        std::fs::create_dir_all(&leaf)
+            .and_then(|_| std::fs::write(leaf.join("cgroup.procs"), std::process::id().to_string()))
+            .with_context(|| {
+                format!(
+                    "Failed to move the agent into {:?} (is Delegate=yes set in its service unit?)",
+                    leaf
+                )
+            })?;
+        debug!("Moved the agent into cgroup {:?}", leaf);
+        Ok(own)
+    }
+
+    /// A transient cgroup holding one script's process tree
+    pub struct CommandCgroup {
+        path: PathBuf,
+    }
+
+    impl CommandCgroup {
+        /// Create the cgroup in the agent's delegated subtree and apply the
+        /// memory and CPU caps
+        pub fn create(name: &str, limits: &ResourceLimits) -> Result<Self> {
+            let root = delegated_root()?;
+
+            let mut controllers = Vec::new();
+            if limits.memory_max_bytes.is_some() {
+                controllers.push("+memory");
+            }
+            if limits.cpu_max_percent.is_some() {
+                controllers.push("+cpu");
+            }
+            std::fs::write(root.join("cgroup.subtree_control"), controllers.join(" "))
+                .with_context(|| {
+                    format!(
+                        "Failed to enable cgroup controllers in {:?} (are they delegated?)",
+                        root
+                    )
+                })?;
+
+            let path = root.join(name);
+            if path.exists() {
+                // Left behind by an interrupted run
+                let _ = std::fs::remove_dir(&path);
+            }
+            std::fs::create_dir(&path)
+                .with_context(|| format!("Failed to create cgroup {:?}", path))?;
+
+            let cgroup = Self { path };
+
+            if let Some(bytes) = limits.memory_max_bytes {
+                cgroup.write("memory.max", &bytes.to_string())?;
+                // Without swap the tree is OOM-killed at the cap instead of thrashing
+                if let Err(e) = cgroup.write("memory.swap.max", "0") {
+                    debug!("Could not disable swap for cgroup: {:#}", e);
+                }
+            }
+            if let Some(percent) = limits.cpu_max_percent {
+                let quota = CPU_PERIOD_USECS * u64::from(percent.max(1)) / 100;
+                cgroup.write("cpu.max", &format!("{} {}", quota.max(1000), CPU_PERIOD_USECS))?;
+            }
+
+            debug!("Created cgroup {:?}", cgroup.path);
+            Ok(cgroup)
+        }
+
+        /// Move a freshly spawned script into the cgroup
+        ///
+        /// Done by the agent rather than in the child: `pre_exec` hooks run
+        /// after the privilege drop, and moving a process needs write access
+        /// to the agent's own cgroup.
+        pub fn add_process(&self, pid: u32) -> Result<()> {
+            self.write("cgroup.procs", &pid.to_string())
+        }
+
+        /// CPU time used by everything that ran in the cgroup
+        pub fn cpu_usage(&self) -> Option<Duration> {
+            std::fs::read_to_string(self.path.join("cpu.stat"))
+                .ok()?
+                .lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|usec| usec.trim().parse().ok())
+                .map(Duration::from_micros)
+        }
+
+        /// Whether the kernel OOM-killed anything in the cgroup
+        pub fn oom_killed(&self) -> bool {
+            std::fs::read_to_string(self.path.join("memory.events"))
+                .map(|events| {
+                    events.lines().any(|line| {
+                        line.strip_prefix("oom_kill ")
+                            .and_then(|n| n.trim().parse::<u64>().ok())
+                            .is_some_and(|n| n > 0)
+                    })
+                })
+                .unwrap_or(false)
+        }
+
+        /// Kill anything left in the cgroup and remove it
+        pub async fn remove(self) {
+            // cgroup.kill needs Linux 5.14; the process group kill covers older kernels
+            let _ = self.write("cgroup.kill", "1");
+
+            for _ in 0..20 {
+                match std::fs::remove_dir(&self.path) {
+                    Ok(()) => return,
+                    Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                    Err(e) => {
+                        warn!("Failed to remove cgroup {:?}: {}", self.path, e);
+                        return;
+                    }
+                }
+            }
+            warn!("Cgroup {:?} still busy, leaving it behind", self.path);
+        }
+
+        fn write(&self, file: &str, value: &str) -> Result<()> {
+            std::fs::write(self.path.join(file), value)
+                .with_context(|| format!("Failed to write {} for cgroup {:?}", file, self.path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_limits_are_ceilings() {
+        let requested = ResourceLimits {
+            cpu_seconds: Some(600),
+            output_bytes: Some(1024),
+            ..Default::default()
+        };
+        let ceiling = ResourceLimits {
+            cpu_seconds: Some(60),
+            memory_max_bytes: Some(512 * 1024 * 1024),
+            ..Default::default()
+        };
+
+        let effective = requested.capped_by(&ceiling);
+        assert_eq!(effective.cpu_seconds, Some(60));
+        assert_eq!(effective.output_bytes, Some(1024));
+        assert_eq!(effective.memory_max_bytes, Some(512 * 1024 * 1024));
+        assert_eq!(effective.open_files, None);
+        assert!(effective.needs_cgroup());
+        assert!(effective.needs_rlimits());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_exit_code_alone_is_not_a_cpu_limit() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        assert!(killed_by_cpu_limit(&ExitStatus::from_raw(libc::SIGXCPU), 1, None));
+        // `exit 152` looks like a shell reporting SIGXCPU
+        let exited = ExitStatus::from_raw((128 + libc::SIGXCPU) << 8);
+        assert_eq!(exited.code(), Some(152));
+        assert!(!killed_by_cpu_limit(&exited, 1, None));
+    }
+}
@@ -0,0 +1,1093 @@
+//! Remote command execution
+//!
+//! The agent polls the backend for queued commands and runs them:
+//! 1. Fetch the next command from `GET /api/commands/pending`
+//! 2. Mark it started via `POST /api/commands/{id}/started`
+//! 3. Run the script with the interpreter named on its `#!` line or matching
+//!    `script_type`, or the typed native `action` (reboot, kill process, ...)
+//!    handled in Rust
+//! 4. Report the outcome via `POST /api/commands/{id}/result`, with stdout
+//!    and stderr capped separately (full gzipped output can go to
+//!    `POST /api/commands/{id}/attachments`)
+//!
+//! The `fetch_file` and `put_file` actions stream files to and from
+//! `/api/commands/{id}/files`, reporting the path and SHA-256, and
+//! `open_shell` starts an interactive shell over an outbound WebSocket.
+//!
+//! Scripts run in their own process group and the whole tree is killed when
+//! `timeout_seconds` elapses or the agent shuts down. While a script runs its
+//! output is streamed to `POST /api/commands/{id}/output`, and
+//! `GET /api/commands/{id}/status` is checked so a command cancelled from the
+//! panel is stopped (gracefully, then forcefully). On Unix, scripts that
+//! don't set `requires_admin` run as an unprivileged account.
+//!
+//! Every command is tracked in a private journal in the data dir so results
+//! survive agent crashes and restarts. When a publisher key is pinned,
+//! commands without a valid Ed25519 signature are refused, and the local
+//! execution policy in the data dir can refuse anything the machine's
+//! owner doesn't want run.
+
+mod actions;
+mod interpreters;
+mod journal;
+mod limits;
+mod output;
+mod policy;
+mod privileges;
+mod process;
+mod script;
+mod shell;
+mod signature;
+mod stream;
+mod transfer;
+mod variables;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::api::{ApiClient, ApiError, ResultDelivery};
+use crate::backoff::{retry_after_of, Backoff};
+use crate::config::Config;
+use crate::push::{PushSignals, PUSH_CONNECTED_POLL_INTERVAL};
+use crate::server_config::LiveSettings;
+use privileges::{can_drop_privileges, RunAs};
+
+pub use script::{
+    CapturedOutput, ExecutionControl, ExecutionOutcome, RunOptions, ScriptRunner, ScriptType,
+    Termination,
+};
+pub use actions::{ActionContext, ActionRunner, FollowUp, NativeAction};
+pub use interpreters::{capabilities as interpreter_capabilities, InterpreterInfo};
+pub use journal::CommandJournal;
+pub use limits::{LimitKind, ResourceLimits};
+pub use policy::ExecutionPolicy;
+pub use signature::{parse_public_key, CommandVerifier, SIGNATURE_VERSION};
+pub use stream::{OutputChunk, OutputCursor};
+pub use variables::{Redactor, SecretValue};
+
+/// Exit code reported when the agent could not run the command at all
+const AGENT_ERROR_EXIT_CODE: i32 = -1;
+
+/// Longest wait between command polls while the backend is failing
+const COMMAND_POLL_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+// ============================================================================
+// Protocol Types
+// ============================================================================
+
+/// A command queued for this device
+#[derive(Debug, Clone, Deserialize)]
+pub struct PendingCommand {
+    /// Backend command ID
+    pub id: u64,
+    /// Script body to execute
+    #[serde(default)]
+    pub script_content: String,
+    /// Interpreter name (powershell, bash, cmd, sh)
+    #[serde(default)]
+    pub script_type: String,
+    /// Native action to run instead of a script
+    #[serde(default, deserialize_with = "NativeAction::deserialize_lenient")]
+    pub action: Option<NativeAction>,
+    /// Maximum run time in seconds
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    /// Run with the agent's full privileges instead of the unprivileged account
+    #[serde(default)]
+    pub requires_admin: bool,
+    /// Resource limits for a script (capped by the local policy)
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    /// Blueprint variable values, passed as `RMM_VAR_<NAME>`
+    #[serde(default)]
+    pub variables: BTreeMap<String, serde_json::Value>,
+    /// Secret values, passed as `RMM_SECRET_<NAME>` and redacted from output
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SecretValue>,
+    /// Base64 Ed25519 signature from the script publisher
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl PendingCommand {
+    /// Short description of what the command runs, for logs
+    pub fn kind(&self) -> &str {
+        match &self.action {
+            Some(action) => action.name(),
+            None => &self.script_type,
+        }
+    }
+}
+
+/// Final command status reported to the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Completed,
+    Failed,
+    TimedOut,
+    Cancelled,
+    Rejected,
+    LimitExceeded,
+}
+
+/// Payload for `POST /api/commands/{id}/result`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResult {
+    /// Final command status
+    pub status: CommandStatus,
+    /// Process exit code
+    pub exit_code: i32,
+    /// Combined stdout and stderr (each capped, see `output_truncated`)
+    pub output: String,
+    /// Whether the middle of stdout or stderr was cut to fit the size limit
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub output_truncated: bool,
+    /// Whether the full output was uploaded as gzipped attachments
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub full_output_attached: bool,
+    /// Agent-side failure description (marks the command as failed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+    /// Structured result of a native action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    /// Resource limit that stopped the script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exceeded_limit: Option<LimitKind>,
+}
+
+impl CommandResult {
+    fn new(status: CommandStatus, exit_code: i32, output: String) -> Self {
+        Self {
+            status,
+            exit_code,
+            output,
+            output_truncated: false,
+            full_output_attached: false,
+            error_message: None,
+            data: None,
+            exceeded_limit: None,
+        }
+    }
+
+    /// Build a result from a finished script, capping each stream at `output_limit` bytes
+    pub fn from_outcome(outcome: &ExecutionOutcome, output_limit: usize) -> Self {
+        let (status, error_message) = termination_status(outcome.termination, outcome.exit_code);
+
+        let exceeded_limit = match outcome.termination {
+            Termination::LimitExceeded { limit } => Some(limit),
+            _ => None,
+        };
+
+        let stdout = output::cap_output(&outcome.stdout, output_limit);
+        let stderr = output::cap_output(&outcome.stderr, output_limit);
+
+        // Only the combined form is sent; separate copies would double the payload
+        Self {
+            output_truncated: stdout.truncated || stderr.truncated,
+            error_message,
+            exceeded_limit,
+            ..Self::new(
+                status,
+                outcome.exit_code,
+                output::combine(&stdout.text, &stderr.text),
+            )
+        }
+    }
+
+    /// Build a result from a completed native action
+    pub fn from_action(data: serde_json::Value) -> Self {
+        Self {
+            data: Some(data.clone()),
+            ..Self::new(
+                CommandStatus::Completed,
+                0,
+                serde_json::to_string_pretty(&data).unwrap_or_default(),
+            )
+        }
+    }
+
+    /// Build a result for an action stopped before it finished
+    pub fn interrupted(termination: Termination) -> Self {
+        let (status, error_message) = termination_status(termination, AGENT_ERROR_EXIT_CODE);
+        Self {
+            error_message,
+            ..Self::new(status, AGENT_ERROR_EXIT_CODE, String::new())
+        }
+    }
+
+    /// Build a result for a command the agent refused to run
+    pub fn rejected(message: impl Into<String>) -> Self {
+        Self {
+            status: CommandStatus::Rejected,
+            ..Self::agent_error(message)
+        }
+    }
+
+    /// Build a result for a command the agent failed to run
+    pub fn agent_error(message: impl Into<String>) -> Self {
+        Self {
+            error_message: Some(message.into()),
+            ..Self::new(CommandStatus::Failed, AGENT_ERROR_EXIT_CODE, String::new())
+        }
+    }
+}
+
+/// Whether a failed request is worth retrying later rather than moving on
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|e| e.downcast_ref::<ApiError>().is_some_and(ApiError::is_transient))
+}
+
+/// Status and error message for how a command ended
+fn termination_status(termination: Termination, exit_code: i32) -> (CommandStatus, Option<String>) {
+    match termination {
+        Termination::Exited if exit_code == 0 => (CommandStatus::Completed, None),
+        Termination::Exited => (CommandStatus::Failed, None),
+        Termination::TimedOut { after_secs } => (
+            CommandStatus::TimedOut,
+            Some(format!("Command timed out after {} seconds", after_secs)),
+        ),
+        Termination::Cancelled => (
+            CommandStatus::Cancelled,
+            Some("Command cancelled by server".to_string()),
+        ),
+        Termination::Shutdown => (
+            CommandStatus::Failed,
+            Some("Command interrupted by agent shutdown".to_string()),
+        ),
+        Termination::LimitExceeded { limit } => {
+            (CommandStatus::LimitExceeded, Some(limit.describe().to_string()))
+        }
+    }
+}
+
+// ============================================================================
+// Command Executor
+// ============================================================================
+
+/// Polls for pending commands, runs them and reports results
+pub struct CommandExecutor {
+    config: Config,
+    api: ApiClient,
+    runner: ScriptRunner,
+    actions: ActionRunner,
+    journal: CommandJournal,
+    verifier: CommandVerifier,
+    /// Settings the server can change while commands run
+    settings: watch::Receiver<LiveSettings>,
+}
+
+impl CommandExecutor {
+    /// Create a new command executor
+    pub fn new(
+        config: Config,
+        hostname: String,
+        api: ApiClient,
+        settings: watch::Receiver<LiveSettings>,
+    ) -> Result<Self> {
+        let runner = ScriptRunner::new(config.data_dir.join("commands"));
+        let actions = ActionRunner::new(config.clone(), hostname, api.clone());
+        CommandJournal::adopt_legacy(
+            &config.data_dir.join("commands").join("journal.json"),
+            &config.command_journal_file,
+        );
+        let journal = CommandJournal::load(&config.command_journal_file);
+        let verifier = CommandVerifier::new(config.command_signing_key.as_deref())
+            .context("Invalid command signing key")?;
+
+        Ok(Self {
+            config,
+            api,
+            runner,
+            actions,
+            journal,
+            verifier,
+            settings,
+        })
+    }
+
+    /// Fetch the next pending command, if any
+    pub async fn fetch_pending(&self, api_key: &str) -> Result<Option<PendingCommand>> {
+        debug!("Polling for pending commands");
+
+        self.api
+            .pending_command(api_key)
+            .await
+            .context("Failed to poll for pending commands")
+    }
+
+    /// Tell the backend the command has started running
+    pub async fn mark_started(&self, command_id: u64, api_key: &str) -> Result<()> {
+        self.api
+            .mark_command_started(api_key, command_id)
+            .await
+            .context("Failed to mark command as started")
+    }
+
+    /// Report the command result to the backend
+    ///
+    /// Submitting the same result twice is harmless, so journaled results can
+    /// be re-posted after a restart.
+    pub async fn submit_result(
+        &self,
+        command_id: u64,
+        result: &CommandResult,
+        api_key: &str,
+    ) -> Result<ResultDelivery> {
+        let delivery = self
+            .api
+            .submit_command_result(api_key, command_id, result)
+            .await
+            .context("Failed to submit command result")?;
+
+        if let ResultDelivery::Rejected(status) = delivery {
+            warn!("Command {} result rejected ({})", command_id, status);
+        }
+        Ok(delivery)
+    }
+
+    /// Submit a result and drop it from the journal once the backend is done with it
+    async fn deliver_result(
+        &self,
+        command_id: u64,
+        result: &CommandResult,
+        api_key: &str,
+    ) -> Result<()> {
+        self.submit_result(command_id, result, api_key).await?;
+
+        if let Err(e) = self.journal.complete(command_id).await {
+            warn!("Failed to update command journal: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Report commands interrupted by a previous agent run and re-post any
+    /// results that never reached the backend
+    pub async fn replay_journal(&self, api_key: &str) {
+        match self.journal.recover_interrupted().await {
+            Ok(interrupted) => {
+                for command_id in interrupted {
+                    warn!("Command {} was interrupted by an agent restart", command_id);
+                }
+            }
+            Err(e) => warn!("Failed to recover interrupted commands: {}", e),
+        }
+
+        self.resend_unsent_results(api_key).await;
+    }
+
+    /// Re-post results the backend hasn't acknowledged yet
+    async fn resend_unsent_results(&self, api_key: &str) {
+        for (command_id, result) in self.journal.unsent_results().await {
+            info!("Re-sending journaled result for command {}", command_id);
+            if let Err(e) = self.deliver_result(command_id, &result, api_key).await {
+                warn!("Failed to re-send result for command {}: {}", command_id, e);
+                // Backend is unreachable or overloaded - try again next interval
+                if is_transient(&e) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Check whether the backend has cancelled a command
+    pub async fn is_cancelled(&self, command_id: u64, api_key: &str) -> Result<bool> {
+        let status = self
+            .api
+            .command_status(api_key, command_id)
+            .await
+            .context("Failed to check command status")?;
+
+        Ok(status.status == "cancelled")
+    }
+
+    /// Poll the command status until it is cancelled or `finished` fires
+    async fn watch_for_cancellation(
+        &self,
+        command_id: u64,
+        api_key: &str,
+        cancel: CancellationToken,
+        finished: CancellationToken,
+    ) {
+        if self.config.command_cancel_check_interval == 0 {
+            return;
+        }
+
+        let interval = Duration::from_secs(self.config.command_cancel_check_interval);
+
+        loop {
+            tokio::select! {
+                _ = finished.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {
+                    match self.is_cancelled(command_id, api_key).await {
+                        Ok(true) => {
+                            info!("Command {} was cancelled by the server", command_id);
+                            cancel.cancel();
+                            break;
+                        }
+                        Ok(false) => {}
+                        Err(e) => debug!("Failed to check status of command {}: {}", command_id, e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a chunk of output from a running command
+    pub async fn submit_output_chunk(
+        &self,
+        command_id: u64,
+        chunk: &OutputChunk,
+        api_key: &str,
+    ) -> Result<()> {
+        self.api
+            .submit_command_output(api_key, command_id, chunk)
+            .await
+            .context("Failed to submit command output")
+    }
+
+    /// Upload the complete stdout and stderr, gzipped, when the result had to be capped
+    ///
+    /// Returns whether every non-empty stream was uploaded.
+    async fn upload_full_output(
+        &self,
+        command_id: u64,
+        outcome: &ExecutionOutcome,
+        api_key: &str,
+    ) -> bool {
+        let mut all_uploaded = true;
+
+        for (stream, bytes) in [("stdout", &outcome.stdout), ("stderr", &outcome.stderr)] {
+            if bytes.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.submit_attachment(command_id, stream, bytes, api_key).await {
+                warn!("Failed to upload full {} for command {}: {:#}", stream, command_id, e);
+                all_uploaded = false;
+            }
+        }
+
+        all_uploaded
+    }
+
+    /// Submit one gzipped output stream to `POST /api/commands/{id}/attachments`
+    pub async fn submit_attachment(
+        &self,
+        command_id: u64,
+        stream: &str,
+        bytes: &[u8],
+        api_key: &str,
+    ) -> Result<()> {
+        let compressed = output::compress(bytes)?;
+        debug!(
+            "Uploading {} for command {} ({} bytes, {} compressed)",
+            stream,
+            command_id,
+            bytes.len(),
+            compressed.len()
+        );
+
+        self.api
+            .upload_command_attachment(api_key, command_id, stream, compressed)
+            .await
+            .context("Failed to upload command output")
+    }
+
+    /// Send all unsent output; stops at the first failure and retries next time
+    async fn flush_output(
+        &self,
+        command_id: u64,
+        output: &CapturedOutput,
+        cursor: &mut OutputCursor,
+        is_final: bool,
+        api_key: &str,
+    ) {
+        while let Some(pending) = cursor.next_chunk(output, is_final) {
+            match self.submit_output_chunk(command_id, &pending.chunk, api_key).await {
+                Ok(()) => cursor.commit(&pending),
+                Err(e) => {
+                    debug!("Failed to stream output for command {}: {}", command_id, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Stream output at the configured cadence until `finished` fires
+    async fn stream_output(
+        &self,
+        command_id: u64,
+        output: &CapturedOutput,
+        api_key: &str,
+        finished: CancellationToken,
+    ) {
+        if self.config.command_output_interval == 0 {
+            return;
+        }
+
+        let interval = Duration::from_secs(self.config.command_output_interval);
+        let mut cursor = OutputCursor::default();
+
+        loop {
+            let is_final = tokio::select! {
+                _ = finished.cancelled() => true,
+                _ = tokio::time::sleep(interval) => false,
+            };
+
+            self.flush_output(command_id, output, &mut cursor, is_final, api_key)
+                .await;
+
+            if is_final {
+                break;
+            }
+        }
+    }
+
+    /// Run a native action, returning the result and any follow-up
+    ///
+    /// Actions are bound by the command timeout, server cancellation and
+    /// agent shutdown just like scripts; a stopped action is dropped.
+    async fn run_action(
+        &self,
+        command: &PendingCommand,
+        action: &NativeAction,
+        policy: &ExecutionPolicy,
+        api_key: &str,
+        cancellation_token: CancellationToken,
+    ) -> (CommandResult, Option<FollowUp>) {
+        // Files and shells are used with the script account's rights
+        let run_as = if action.acts_as_account() {
+            match self.account_for(command) {
+                Ok(run_as) => run_as,
+                Err(e) => return (CommandResult::agent_error(format!("{:#}", e)), None),
+            }
+        } else {
+            None
+        };
+
+        let control = ExecutionControl {
+            timeout: Duration::from_secs(
+                command
+                    .timeout_seconds
+                    .unwrap_or(self.config.command_timeout),
+            ),
+            cancel: CancellationToken::new(),
+            shutdown: cancellation_token,
+        };
+        let context = ActionContext {
+            command_id: command.id,
+            timeout: control.timeout,
+            shutdown: control.shutdown.clone(),
+            run_as,
+            transfer_roots: policy.file_transfer_roots.clone(),
+        };
+        let finished = CancellationToken::new();
+
+        let execution = async {
+            let outcome = tokio::select! {
+                outcome = self.actions.run(&context, action, api_key) => Ok(outcome),
+                _ = tokio::time::sleep(control.timeout) => Err(Termination::TimedOut {
+                    after_secs: control.timeout.as_secs(),
+                }),
+                _ = control.cancel.cancelled() => Err(Termination::Cancelled),
+                _ = control.shutdown.cancelled() => Err(Termination::Shutdown),
+            };
+            finished.cancel();
+            outcome
+        };
+        let watching = self.watch_for_cancellation(
+            command.id,
+            api_key,
+            control.cancel.clone(),
+            finished.clone(),
+        );
+
+        let (outcome, _) = tokio::join!(execution, watching);
+
+        match outcome {
+            Ok(Ok(outcome)) => (CommandResult::from_action(outcome.data), outcome.follow_up),
+            Ok(Err(e)) => (CommandResult::agent_error(format!("{:#}", e)), None),
+            Err(termination) => (CommandResult::interrupted(termination), None),
+        }
+    }
+
+    /// The unprivileged account a command acts as, unless it `requires_admin`
+    /// or the agent can't switch accounts
+    fn account_for(&self, command: &PendingCommand) -> Result<Option<RunAs>> {
+        if command.requires_admin || !can_drop_privileges() {
+            return Ok(None);
+        }
+
+        let run_as = RunAs::lookup(&self.config.script_user).with_context(|| {
+            format!(
+                "Cannot run without admin rights as '{}'",
+                self.config.script_user
+            )
+        })?;
+        Ok(Some(run_as))
+    }
+
+    /// Decide which account a script runs as, under what limits and with which variables
+    fn run_options(&self, command: &PendingCommand, policy: &ExecutionPolicy) -> Result<RunOptions> {
+        let limits = command.limits.unwrap_or_default().capped_by(&policy.limits);
+        let env = variables::environment(&command.variables, &command.secrets)?;
+        let redactor = Redactor::new(command.secrets.values());
+
+        Ok(RunOptions {
+            run_as: self.account_for(command)?,
+            limits,
+            env,
+            redactor,
+        })
+    }
+
+    /// Run a script and produce the result to report
+    async fn run_script(
+        &self,
+        command: &PendingCommand,
+        policy: &ExecutionPolicy,
+        api_key: &str,
+        cancellation_token: CancellationToken,
+    ) -> CommandResult {
+        let script_type = match ScriptType::parse(&command.script_type) {
+            Some(script_type) => script_type,
+            None => {
+                return CommandResult::agent_error(format!(
+                    "Unsupported script type: {}",
+                    command.script_type
+                ));
+            }
+        };
+
+        let options = match self.run_options(command, policy) {
+            Ok(options) => options,
+            Err(e) => return CommandResult::agent_error(format!("{:#}", e)),
+        };
+        // References must suit the interpreter a #! line picks, not the declared type
+        let script = variables::render_placeholders(
+            &command.script_content,
+            interpreters::effective_type(script_type, &command.script_content),
+            &command.variables,
+            &command.secrets,
+        );
+
+        let control = ExecutionControl {
+            timeout: Duration::from_secs(
+                command
+                    .timeout_seconds
+                    .unwrap_or(self.config.command_timeout),
+            ),
+            cancel: CancellationToken::new(),
+            shutdown: cancellation_token,
+        };
+
+        let output = CapturedOutput::default();
+        let finished = CancellationToken::new();
+
+        let execution = async {
+            let outcome = self
+                .runner
+                .execute(
+                    command.id,
+                    script_type,
+                    &script,
+                    &options,
+                    &output,
+                    &control,
+                )
+                .await;
+            finished.cancel();
+            outcome
+        };
+        let streaming = self.stream_output(command.id, &output, api_key, finished.clone());
+        let watching = self.watch_for_cancellation(
+            command.id,
+            api_key,
+            control.cancel.clone(),
+            finished.clone(),
+        );
+
+        let (outcome, _, _) = tokio::join!(execution, streaming, watching);
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            // Errors end up in the log and the journal, so scrub them too
+            Err(e) => {
+                return CommandResult::agent_error(options.redactor.redact_str(&format!("{:#}", e)))
+            }
+        };
+
+        let mut result = CommandResult::from_outcome(&outcome, self.config.command_output_limit);
+        let attachments = self.settings.borrow().command_output_attachments;
+        if result.output_truncated && attachments {
+            result.full_output_attached = self.upload_full_output(command.id, &outcome, api_key).await;
+        }
+        result
+    }
+
+    /// Report a command as refused without running it
+    async fn reject(&self, command_id: u64, message: String, api_key: &str) -> Result<()> {
+        let result = CommandResult::rejected(message);
+        if let Err(e) = self.journal.record_finished(command_id, &result).await {
+            warn!("Failed to update command journal: {}", e);
+        }
+        self.deliver_result(command_id, &result, api_key).await
+    }
+
+    /// Execute a single command end to end
+    pub async fn process_command(
+        &self,
+        command: PendingCommand,
+        api_key: &str,
+        cancellation_token: CancellationToken,
+    ) -> Result<()> {
+        info!(
+            "Executing command {} ({}, timeout: {:?}s)",
+            command.id,
+            command.kind(),
+            command.timeout_seconds
+        );
+
+        if let Err(e) = self.journal.record_received(command.id).await {
+            warn!("Failed to update command journal: {}", e);
+        }
+
+        if let Err(e) = self.verifier.verify(&command) {
+            warn!("Refusing command {}: {:#}", command.id, e);
+            return self
+                .reject(command.id, format!("Command rejected: {:#}", e), api_key)
+                .await;
+        }
+
+        // Re-read on every command so local edits apply without a restart
+        let policy = match ExecutionPolicy::load(&self.config.policy_file)
+            .and_then(|policy| policy.check(&command).map(|_| policy))
+        {
+            Ok(policy) => policy,
+            Err(e) => {
+                warn!("Command {} refused by local execution policy: {:#}", command.id, e);
+                return self
+                    .reject(
+                        command.id,
+                        format!("Refused by local execution policy: {:#}", e),
+                        api_key,
+                    )
+                    .await;
+            }
+        };
+
+        // Verified and allowed, but nothing can run; the backend still waits for a result
+        if let Some(NativeAction::Unsupported {
+            action_type,
+            reason,
+        }) = &command.action
+        {
+            warn!("Command {} has an unsupported action '{}': {}", command.id, action_type, reason);
+            let result = CommandResult::agent_error(format!(
+                "Unsupported action '{}': {}",
+                action_type, reason
+            ));
+            if let Err(e) = self.journal.record_finished(command.id, &result).await {
+                warn!("Failed to update command journal: {}", e);
+            }
+            return self.deliver_result(command.id, &result, api_key).await;
+        }
+
+        if let Err(e) = self.mark_started(command.id, api_key).await {
+            warn!("Failed to mark command {} as started: {}", command.id, e);
+        }
+
+        if let Err(e) = self.journal.record_started(command.id).await {
+            warn!("Failed to update command journal: {}", e);
+        }
+
+        let (result, follow_up) = match &command.action {
+            Some(action) => {
+                self.run_action(&command, action, &policy, api_key, cancellation_token)
+                    .await
+            }
+            None => (
+                self.run_script(&command, &policy, api_key, cancellation_token)
+                    .await,
+                None,
+            ),
+        };
+
+        match &result.error_message {
+            Some(message) => warn!("Command {} failed: {}", command.id, message),
+            None => info!("Command {} finished with exit code {}", command.id, result.exit_code),
+        }
+
+        if let Err(e) = self.journal.record_finished(command.id, &result).await {
+            warn!("Failed to update command journal: {}", e);
+        }
+
+        let delivered = self.deliver_result(command.id, &result, api_key).await;
+
+        if let Some(follow_up) = follow_up {
+            if let Err(e) = self.actions.run_follow_up(follow_up) {
+                error!("Follow-up for command {} failed: {}", command.id, e);
+            }
+        }
+
+        delivered
+    }
+
+    /// Poll once and run the pending command if there is one
+    ///
+    /// Returns true if a command was processed
+    pub async fn poll_and_execute(
+        &self,
+        api_key: &str,
+        cancellation_token: &CancellationToken,
+    ) -> Result<bool> {
+        match self.fetch_pending(api_key).await? {
+            Some(command) => {
+                let command_id = command.id;
+                if let Err(e) = self
+                    .process_command(command, api_key, cancellation_token.clone())
+                    .await
+                {
+                    error!("Failed to report result for command {}: {}", command_id, e);
+                }
+                Ok(true)
+            }
+            None => {
+                debug!("No pending commands");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Start the command polling loop
+    ///
+    /// A `command_available` push event polls at once; while the push
+    /// channel is up, interval polling only runs as a slow safety net.
+    pub async fn start_command_loop(
+        &self,
+        api_key: String,
+        push: &PushSignals,
+        cancellation_token: CancellationToken,
+    ) {
+        info!(
+            "Starting command poll loop (interval: {}s)",
+            self.config.command_poll_interval
+        );
+
+        if !self.verifier.is_enforcing() {
+            warn!("No command signing key pinned - unsigned commands will run (reported in heartbeats)");
+        }
+
+        self.replay_journal(&api_key).await;
+        let mut last_poll = Instant::now();
+        let poll_interval = Duration::from_secs(self.config.command_poll_interval);
+        let mut backoff = Backoff::new(poll_interval, COMMAND_POLL_BACKOFF_MAX);
+
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    info!("Command poll loop cancelled");
+                    break;
+                }
+                _ = push.command_available.notified() => {
+                    debug!("Push channel announced a command");
+                }
+                _ = tokio::time::sleep(backoff.wait(poll_interval)) => {
+                    if push.is_connected() && last_poll.elapsed() < PUSH_CONNECTED_POLL_INTERVAL {
+                        continue;
+                    }
+                }
+            }
+            last_poll = Instant::now();
+
+            self.resend_unsent_results(&api_key).await;
+
+            // Drain the queue before waiting for the next interval
+            loop {
+                match self.poll_and_execute(&api_key, &cancellation_token).await {
+                    Ok(true) if !cancellation_token.is_cancelled() => continue,
+                    Ok(_) => {
+                        backoff.succeed();
+                        break;
+                    }
+                    Err(e) => {
+                        let delay = backoff.fail(retry_after_of(&e));
+                        warn!("Command poll failed, next in {}s: {}", delay.as_secs(), e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        info!("Command poll loop stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::PendingCommandResponse;
+
+    #[test]
+    fn test_pending_command_deserialization() {
+        let json = r#"{"command":{"id":42,"script_content":"Get-Process","script_type":"powershell","timeout_seconds":300}}"#;
+        let response: PendingCommandResponse = serde_json::from_str(json).unwrap();
+        let command = response.command.unwrap();
+        assert_eq!(command.id, 42);
+        assert_eq!(command.script_type, "powershell");
+        assert_eq!(command.timeout_seconds, Some(300));
+        assert!(!command.requires_admin);
+
+        let empty: PendingCommandResponse = serde_json::from_str(r#"{"command":null}"#).unwrap();
+        assert!(empty.command.is_none());
+    }
+
+    #[test]
+    fn test_action_command_deserialization() {
+        let json = r#"{"command":{"id":7,"action":{"type":"restart_service","name":"nginx"},"timeout_seconds":60}}"#;
+        let command = serde_json::from_str::<PendingCommandResponse>(json)
+            .unwrap()
+            .command
+            .unwrap();
+        assert!(command.script_content.is_empty());
+        assert_eq!(
+            command.action,
+            Some(NativeAction::RestartService {
+                name: "nginx".to_string()
+            })
+        );
+        assert_eq!(command.kind(), "restart_service");
+
+        // An action this agent doesn't know mustn't hide the rest of the command
+        let json = r#"{"command":{"id":8,"action":{"type":"format_disk","disk":0}}}"#;
+        let command = serde_json::from_str::<PendingCommandResponse>(json)
+            .unwrap()
+            .command
+            .unwrap();
+        assert_eq!(command.id, 8);
+        assert_eq!(command.kind(), "unsupported");
+
+        let result = CommandResult::from_action(serde_json::json!({ "restarted": true }));
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("\"data\":{\"restarted\":true}"));
+    }
+
+    #[test]
+    fn test_result_serialization() {
+        let ok = CommandResult::new(CommandStatus::Completed, 0, "done".to_string());
+        let json = serde_json::to_string(&ok).unwrap();
+        assert!(!json.contains("error_message"));
+        assert!(!json.contains("exceeded_limit"));
+        assert!(json.contains("\"status\":\"completed\""));
+
+        let failed = CommandResult::agent_error("Unsupported script type: python");
+        let json = serde_json::to_string(&failed).unwrap();
+        assert!(json.contains("\"exit_code\":-1"));
+        assert!(json.contains("Unsupported script type"));
+    }
+
+    #[test]
+    fn test_timed_out_result() {
+        let outcome = ExecutionOutcome {
+            termination: Termination::TimedOut { after_secs: 300 },
+            exit_code: -1,
+            stdout: b"partial".to_vec(),
+            stderr: Vec::new(),
+        };
+
+        let result = CommandResult::from_outcome(&outcome, 1000);
+        assert_eq!(result.status, CommandStatus::TimedOut);
+        assert_eq!(result.output, "partial");
+        assert_eq!(
+            result.error_message.as_deref(),
+            Some("Command timed out after 300 seconds")
+        );
+
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("\"status\":\"timed_out\""));
+    }
+
+    #[test]
+    fn test_cancelled_result() {
+        let outcome = ExecutionOutcome {
+            termination: Termination::Cancelled,
+            exit_code: 143,
+            stdout: b"cleaning up".to_vec(),
+            stderr: Vec::new(),
+        };
+
+        let result = CommandResult::from_outcome(&outcome, 1000);
+        assert_eq!(result.status, CommandStatus::Cancelled);
+        assert_eq!(result.output, "cleaning up");
+
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("\"status\":\"cancelled\""));
+    }
+
+    #[test]
+    fn test_large_output_is_capped() {
+        let outcome = ExecutionOutcome {
+            termination: Termination::Exited,
+            exit_code: 0,
+            stdout: vec![b'x'; 10_000],
+            stderr: b"warning\n".to_vec(),
+        };
+
+        let result = CommandResult::from_outcome(&outcome, 1000);
+        assert!(result.output_truncated);
+        assert!(result.output.len() < 1200);
+        assert!(result.output.ends_with("--- stderr ---\nwarning\n"));
+
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("\"output_truncated\":true"));
+        assert!(!json.contains("\"stdout\""));
+        assert!(!json.contains("full_output_attached"));
+    }
+
+    #[test]
+    fn test_interrupted_action_result() {
+        let result = CommandResult::interrupted(Termination::TimedOut { after_secs: 60 });
+        assert_eq!(result.status, CommandStatus::TimedOut);
+        assert_eq!(result.exit_code, AGENT_ERROR_EXIT_CODE);
+        assert_eq!(
+            result.error_message.as_deref(),
+            Some("Command timed out after 60 seconds")
+        );
+
+        let result = CommandResult::interrupted(Termination::Cancelled);
+        assert_eq!(result.status, CommandStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_only_transient_failures_stop_resending() {
+        let error = |e: ApiError| anyhow::Error::new(e).context("Failed to submit command result");
+        assert!(is_transient(&error(ApiError::Server {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+            body: String::new(),
+            retry_after: None,
+        })));
+        assert!(!is_transient(&error(ApiError::Rejected {
+            status: reqwest::StatusCode::REQUEST_TIMEOUT,
+            body: String::new(),
+        })));
+        assert!(!is_transient(&anyhow::anyhow!("journal is corrupt")));
+    }
+
+    #[test]
+    fn test_rejected_result() {
+        let result = CommandResult::rejected("Command rejected: Command is not signed");
+        assert_eq!(result.status, CommandStatus::Rejected);
+        assert_eq!(result.exit_code, AGENT_ERROR_EXIT_CODE);
+
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("\"status\":\"rejected\""));
+    }
+}
@@ -0,0 +1,124 @@
+//! Shaping captured output for command results
+//!
+//! The backend rejects results whose `output` is over 1,000,000 characters,
+//! so each stream is capped: past the limit the head and tail are kept with
+//! a marker saying how much was cut from the middle. Output is decoded as
+//! UTF-8 with invalid sequences (and NUL bytes, which the database refuses)
+//! replaced by U+FFFD. The full output can be sent separately, gzipped.
+
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+/// Separator between stdout and stderr in the combined `output` field
+const STDERR_SEPARATOR: &str = "\n--- stderr ---\n";
+
+/// One output stream prepared for the result payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CappedText {
+    /// Text to report, at most the limit plus the truncation marker
+    pub text: String,
+    /// Whether bytes were cut from the middle
+    pub truncated: bool,
+}
+
+/// Decode a stream, keeping its head and tail if it exceeds `limit` bytes
+pub fn cap_output(bytes: &[u8], limit: usize) -> CappedText {
+    if bytes.len() <= limit {
+        return CappedText {
+            text: decode(bytes),
+            truncated: false,
+        };
+    }
+
+    let head_end = char_boundary_before(bytes, limit / 2);
+    let tail_start = char_boundary_after(bytes, bytes.len() - (limit - limit / 2));
+    let omitted = tail_start - head_end;
+
+    CappedText {
+        text: format!(
+            "{}\n\n[... {} of {} bytes truncated ...]\n\n{}",
+            decode(&bytes[..head_end]),
+            omitted,
+            bytes.len(),
+            decode(&bytes[tail_start..])
+        ),
+        truncated: true,
+    }
+}
+
+/// Combine stdout and stderr the way the `output` field always has
+pub fn combine(stdout: &str, stderr: &str) -> String {
+    if stderr.is_empty() {
+        stdout.to_string()
+    } else if stdout.is_empty() {
+        stderr.to_string()
+    } else {
+        format!("{}{}{}", stdout.trim_end(), STDERR_SEPARATOR, stderr)
+    }
+}
+
+/// Gzip raw output for upload
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).context("Failed to compress output")?;
+    encoder.finish().context("Failed to compress output")
+}
+
+/// Lossy UTF-8 decode that also replaces NUL bytes
+fn decode(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).replace('\0', "\u{FFFD}")
+}
+
+/// Move `index` back so it doesn't split a UTF-8 character
+fn char_boundary_before(bytes: &[u8], mut index: usize) -> usize {
+    let floor = index.saturating_sub(3);
+    while index > floor && index < bytes.len() && is_continuation(bytes[index]) {
+        index -= 1;
+    }
+    index
+}
+
+/// Move `index` forward so it doesn't split a UTF-8 character
+fn char_boundary_after(bytes: &[u8], mut index: usize) -> usize {
+    let ceiling = (index + 3).min(bytes.len());
+    while index < ceiling && is_continuation(bytes[index]) {
+        index += 1;
+    }
+    index
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_keeps_head_and_tail() {
+        let short = cap_output(b"hello\n", 100);
+        assert_eq!(short.text, "hello\n");
+        assert!(!short.truncated);
+
+        let long: Vec<u8> = (0..1000).flat_map(|i| format!("{:04}\n", i).into_bytes()).collect();
+        let capped = cap_output(&long, 100);
+        assert!(capped.truncated);
+        assert!(capped.text.starts_with("0000\n"));
+        assert!(capped.text.ends_with("0999\n"));
+        assert!(capped.text.contains("[... 4900 of 5000 bytes truncated ...]"));
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_replaced() {
+        let capped = cap_output(b"ok \xFF\xFE nul\0", 100);
+        assert_eq!(capped.text, "ok \u{FFFD}\u{FFFD} nul\u{FFFD}");
+
+        // A cut never splits a multi-byte character
+        let text = "é".repeat(100);
+        let capped = cap_output(text.as_bytes(), 11);
+        assert!(!capped.text.contains('\u{FFFD}'));
+        assert!(capped.text.starts_with("éé"));
+    }
+}
@@ -0,0 +1,284 @@
+//! Local execution policy
+//!
+//! A JSON file in the data dir that limits what the server may run on this
+//! machine. It lives outside `RuntimeConfig` and nothing the backend sends
+//! can change it - only a local administrator can edit it.
+//!
+//! ```json
+//! {
+//!   "mode": "restricted",
+//!   "allowed_script_types": ["powershell"],
+//!   "allowed_script_hashes": ["<hex sha256 of the script content>"],
+//!   "allowed_actions": ["collect_system_info", "submit_metrics"],
+//!   "file_transfer_roots": ["/var/log", "/opt/app/config"],
+//!   "limits": { "cpu_seconds": 600, "memory_max_bytes": 536870912 }
+//! }
+//! ```
+//!
+//! The shell and file transfer actions can do anything a script could, so
+//! once scripts are restricted at all (a mode other than `restricted`, or
+//! either script allow list) they are refused unless `allowed_actions` names
+//! them.
+//!
+//! Without a policy file everything is allowed. A policy file that can't be
+//! read or parsed, including one with a key the agent doesn't know, refuses
+//! everything rather than falling back to allow-all.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use super::interpreters::Shebang;
+use super::{PendingCommand, ResourceLimits, ScriptType};
+
+/// Actions as powerful as a script, only run when listed once scripts are restricted
+const SCRIPT_EQUIVALENT_ACTIONS: &[&str] = &["open_shell", "fetch_file", "put_file"];
+
+/// What kinds of command the policy lets through at all
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyMode {
+    /// Scripts and native actions, subject to the allow lists
+    #[default]
+    Restricted,
+    /// Native actions only - every script, and any shell or file transfer
+    /// not explicitly allowed, is refused
+    ActionsOnly,
+    /// Nothing runs
+    Disabled,
+}
+
+/// Locally enforced limits on remote commands
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecutionPolicy {
+    #[serde(default)]
+    pub mode: PolicyMode,
+    /// Script types that may run (all when absent)
+    #[serde(default)]
+    pub allowed_script_types: Option<Vec<String>>,
+    /// SHA-256 hashes (hex) of the only scripts that may run (any when absent)
+    #[serde(default)]
+    pub allowed_script_hashes: Option<Vec<String>>,
+    /// Native action names that may run (all when absent)
+    #[serde(default)]
+    pub allowed_actions: Option<Vec<String>>,
+    /// Directories `fetch_file` and `put_file` may use (anywhere outside the
+    /// agent's data dir when absent)
+    #[serde(default)]
+    pub file_transfer_roots: Option<Vec<PathBuf>>,
+    /// Maximum resource limits for scripts; commands may only ask for less
+    #[serde(default)]
+    pub limits: ResourceLimits,
+}
+
+impl ExecutionPolicy {
+    /// Load the policy file, allowing everything if it doesn't exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("No execution policy at {:?}, allowing all commands", path);
+                return Ok(Self::default());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read execution policy {:?}", path))
+            }
+        };
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse execution policy {:?}", path))
+    }
+
+    /// Whether the policy places any limits at all
+    pub fn is_unrestricted(&self) -> bool {
+        self.mode == PolicyMode::Restricted
+            && self.allowed_script_types.is_none()
+            && self.allowed_script_hashes.is_none()
+            && self.allowed_actions.is_none()
+            && self.file_transfer_roots.is_none()
+            && self.limits == ResourceLimits::default()
+    }
+
+    /// Whether scripts are limited in any way
+    fn restricts_scripts(&self) -> bool {
+        self.mode != PolicyMode::Restricted
+            || self.allowed_script_types.is_some()
+            || self.allowed_script_hashes.is_some()
+    }
+
+    /// Check a command against the policy, describing why it is refused
+    pub fn check(&self, command: &PendingCommand) -> Result<()> {
+        if self.mode == PolicyMode::Disabled {
+            anyhow::bail!("remote command execution is disabled on this machine");
+        }
+
+        match &command.action {
+            Some(action) => self.check_action(action.name()),
+            None => self.check_script(&command.script_type, &command.script_content),
+        }
+    }
+
+    fn check_action(&self, name: &str) -> Result<()> {
+        let listed = self
+            .allowed_actions
+            .as_ref()
+            .map(|allowed| allowed.iter().any(|a| a == name));
+
+        match listed {
+            Some(false) => anyhow::bail!("native action '{}' is not allowed", name),
+            None if self.restricts_scripts() && SCRIPT_EQUIVALENT_ACTIONS.contains(&name) =>
+            {
+                anyhow::bail!(
+                    "native action '{}' must be listed in allowed_actions on this machine",
+                    name
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_script(&self, script_type: &str, script_content: &str) -> Result<()> {
+        if self.mode == PolicyMode::ActionsOnly {
+            anyhow::bail!("only native actions are allowed on this machine");
+        }
+
+        if let Some(allowed) = &self.allowed_script_types {
+            let requested = script_type.trim();
+            let is_allowed = |name: &str| allowed.iter().any(|a| a.trim().eq_ignore_ascii_case(name));
+            if !is_allowed(requested) {
+                anyhow::bail!("script type '{}' is not allowed", script_type);
+            }
+
+            // A #! line picks the interpreter, so it has to be allowed too
+            if let Some(shebang) = Shebang::parse(script_content) {
+                let name = shebang.name();
+                let canonical = ScriptType::parse(name).map(|t| t.as_str());
+                if !is_allowed(name) && !canonical.is_some_and(is_allowed) {
+                    anyhow::bail!("interpreter '{}' is not allowed", shebang.program);
+                }
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_script_hashes {
+            let hash = hex::encode(Sha256::digest(script_content.as_bytes()));
+            if !allowed.iter().any(|h| h.trim().eq_ignore_ascii_case(&hash)) {
+                anyhow::bail!("script hash {} is not on the allow list", hash);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::NativeAction;
+
+    fn script(script_type: &str, content: &str) -> PendingCommand {
+        PendingCommand {
+            id: 1,
+            script_content: content.to_string(),
+            script_type: script_type.to_string(),
+            action: None,
+            timeout_seconds: None,
+            requires_admin: false,
+            limits: None,
+            variables: Default::default(),
+            secrets: Default::default(),
+            signature: None,
+        }
+    }
+
+    fn action(action: NativeAction) -> PendingCommand {
+        PendingCommand {
+            action: Some(action),
+            ..script("", "")
+        }
+    }
+
+    #[test]
+    fn test_policy_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = ExecutionPolicy::load(dir.path().join("policy.json")).unwrap();
+        assert!(policy.is_unrestricted());
+        assert!(policy.check(&script("bash", "uptime")).is_ok());
+
+        let policy: ExecutionPolicy = serde_json::from_str(r#"{"mode":"actions_only"}"#).unwrap();
+        assert!(policy.check(&script("bash", "uptime")).is_err());
+        assert!(policy.check(&action(NativeAction::SubmitMetrics)).is_ok());
+
+        // A shell or file transfer would run anything, so it must be listed
+        let shell = action(NativeAction::OpenShell {
+            cols: None,
+            rows: None,
+            idle_timeout_seconds: None,
+            max_duration_seconds: None,
+        });
+        let put = action(NativeAction::PutFile {
+            path: "/usr/local/bin/run".to_string(),
+            sha256: String::new(),
+            size: None,
+            mode: None,
+            overwrite: true,
+        });
+        assert!(policy.check(&shell).is_err());
+        assert!(policy.check(&put).is_err());
+
+        let policy: ExecutionPolicy =
+            serde_json::from_str(r#"{"mode":"actions_only","allowed_actions":["open_shell"]}"#)
+                .unwrap();
+        assert!(policy.check(&shell).is_ok());
+        assert!(policy.check(&put).is_err());
+
+        let policy: ExecutionPolicy = serde_json::from_str(r#"{"mode":"disabled"}"#).unwrap();
+        assert!(policy.check(&action(NativeAction::SubmitMetrics)).is_err());
+    }
+
+    #[test]
+    fn test_allow_lists() {
+        let hash = hex::encode(Sha256::digest(b"uptime"));
+        let policy: ExecutionPolicy = serde_json::from_value(serde_json::json!({
+            "allowed_script_types": ["powershell"],
+            "allowed_script_hashes": [hash],
+            "allowed_actions": ["collect_system_info"],
+        }))
+        .unwrap();
+
+        assert!(policy.check(&script("PowerShell", "uptime")).is_ok());
+        assert!(policy.check(&script("bash", "uptime")).is_err());
+        assert!(policy.check(&script("powershell", "Remove-Item C:\\")).is_err());
+        assert!(policy.check(&script("powershell", "#!/bin/bash\nuptime")).is_err());
+        assert!(policy.check(&action(NativeAction::CollectSystemInfo)).is_ok());
+        assert!(policy.check(&action(NativeAction::RestartAgent)).is_err());
+
+        // Restricting scripts also takes away an unlisted shell
+        let shell = action(NativeAction::OpenShell {
+            cols: None,
+            rows: None,
+            idle_timeout_seconds: None,
+            max_duration_seconds: None,
+        });
+        let policy: ExecutionPolicy =
+            serde_json::from_str(r#"{"allowed_script_types":["powershell"]}"#).unwrap();
+        assert!(policy.check(&shell).is_err());
+        assert!(policy.check(&action(NativeAction::CollectSystemInfo)).is_ok());
+    }
+
+    #[test]
+    fn test_unreadable_policy_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(ExecutionPolicy::load(&path).is_err());
+
+        // A misspelled key must not leave the machine unrestricted
+        std::fs::write(&path, r#"{"mode":"restricted","allowed_script_hash":["00"]}"#).unwrap();
+        assert!(ExecutionPolicy::load(&path).is_err());
+    }
+}
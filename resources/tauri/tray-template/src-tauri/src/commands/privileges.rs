@@ -0,0 +1,232 @@
+//! Privilege dropping for scripts that don't need admin rights
+//!
+//! The agent runs as root (or LocalSystem), so on Unix a script without
+//! `requires_admin` is started as a configured unprivileged account instead:
+//! setgid/setuid (supplementary groups are cleared), a minimal environment
+//! and its own working directory owned by that account.
+//!
+//! On Windows scripts still run as the service account.
+
+use anyhow::Result;
+use std::path::Path;
+use tokio::process::Command;
+
+/// PATH given to scripts running with a clean environment
+#[cfg(unix)]
+const SAFE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// An account to run a script as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAs {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    /// Look up an account by name
+    #[cfg(unix)]
+    pub fn lookup(name: &str) -> Result<Self> {
+        use anyhow::Context;
+        use std::ffi::{CStr, CString};
+
+        let c_name = CString::new(name).context("Account name contains a NUL byte")?;
+        let mut buf: Vec<libc::c_char> = vec![0; 4096];
+
+        loop {
+            let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut found: *mut libc::passwd = std::ptr::null_mut();
+            let rc = unsafe {
+                libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found)
+            };
+
+            if rc == libc::ERANGE && buf.len() < 1024 * 1024 {
+                buf.resize(buf.len() * 2, 0);
+                continue;
+            }
+            if rc != 0 {
+                anyhow::bail!(
+                    "Failed to look up account '{}': {}",
+                    name,
+                    std::io::Error::from_raw_os_error(rc)
+                );
+            }
+            if found.is_null() {
+                anyhow::bail!("Account '{}' does not exist", name);
+            }
+
+            let name = unsafe { CStr::from_ptr(pwd.pw_name) }
+                .to_string_lossy()
+                .to_string();
+            return Ok(Self {
+                name,
+                uid: pwd.pw_uid,
+                gid: pwd.pw_gid,
+            });
+        }
+    }
+
+    /// Look up an account by name
+    #[cfg(not(unix))]
+    pub fn lookup(name: &str) -> Result<Self> {
+        anyhow::bail!("Running scripts as '{}' is not supported on this platform", name)
+    }
+
+    /// Give the account ownership of the script's working directory and files
+    #[cfg(unix)]
+    pub fn take_ownership(&self, path: &Path) -> Result<()> {
+        use anyhow::Context;
+
+        std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))
+            .with_context(|| format!("Failed to give {} ownership of {:?}", self.name, path))
+    }
+
+    /// Give the account ownership of the script's working directory and files
+    #[cfg(not(unix))]
+    pub fn take_ownership(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start the command as this account with a minimal environment
+    ///
+    /// The switch happens in a `pre_exec` hook, so hooks registered after
+    /// this one run as the account. Nothing that needs root is done in the
+    /// child: the agent places the script in its cgroup once it has started.
+    #[cfg(unix)]
+    pub fn apply(&self, command: &mut Command, work_dir: &Path) {
+        command
+            .env_clear()
+            .env("PATH", SAFE_PATH)
+            .env("HOME", work_dir)
+            .env("USER", &self.name)
+            .env("LOGNAME", &self.name)
+            .env("SHELL", "/bin/sh")
+            .env("LANG", "C.UTF-8");
+
+        let (uid, gid) = (self.uid, self.gid);
+        // SAFETY: the closure only calls setgroups/setgid/setuid, which are async-signal-safe
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setgroups(0, std::ptr::null()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Start the command as this account with a minimal environment
+    #[cfg(not(unix))]
+    pub fn apply(&self, _command: &mut Command, _work_dir: &Path) {}
+
+    /// Run blocking filesystem calls on this thread with the account's access
+    ///
+    /// Linux keeps a per-thread filesystem uid/gid, so only `f` is affected;
+    /// a raw `setgroups` keeps root's groups from leaking in (glibc's wrapper
+    /// would change every thread). Files `f` creates belong to the account.
+    #[cfg(target_os = "linux")]
+    pub fn with_fs_identity<T>(
+        &self,
+        f: impl FnOnce() -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if count < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut groups = vec![0 as libc::gid_t; count as usize];
+        let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+        if count < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        groups.truncate(count as usize);
+
+        let no_groups: [libc::gid_t; 0] = [];
+        if unsafe { libc::syscall(libc::SYS_setgroups, 0, no_groups.as_ptr()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let previous_gid = unsafe { libc::setfsgid(self.gid) };
+        let previous_uid = unsafe { libc::setfsuid(self.uid) };
+
+        let result = f();
+
+        unsafe {
+            libc::setfsuid(previous_uid as libc::uid_t);
+            libc::setfsgid(previous_gid as libc::gid_t);
+            libc::syscall(libc::SYS_setgroups, groups.len(), groups.as_ptr());
+        }
+        result
+    }
+
+    /// Run blocking filesystem calls with the account's access
+    #[cfg(not(target_os = "linux"))]
+    pub fn with_fs_identity<T>(
+        &self,
+        _f: impl FnOnce() -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!(
+                "acting on files as '{}' is only supported on Linux (set requires_admin)",
+                self.name
+            ),
+        ))
+    }
+}
+
+/// Whether the agent can switch to another account
+pub fn can_drop_privileges() -> bool {
+    #[cfg(unix)]
+    {
+        unsafe { libc::geteuid() == 0 }
+    }
+
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_account() {
+        let root = RunAs::lookup("root").unwrap();
+        assert_eq!(root.uid, 0);
+        assert_eq!(root.gid, 0);
+
+        assert!(RunAs::lookup("no-such-rmm-account").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "needs root and a `nobody` account; run with --ignored"]
+    fn test_fs_identity_is_the_accounts() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        assert!(can_drop_privileges(), "must run as root");
+        let nobody = RunAs::lookup("nobody").expect("no `nobody` account");
+
+        let dir = tempfile::tempdir().unwrap();
+        let private = dir.path().join("private");
+        std::fs::write(&private, "root only").unwrap();
+        std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let denied = nobody.with_fs_identity(|| std::fs::read(&private));
+        assert_eq!(denied.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+        let created = dir.path().join("created");
+        nobody
+            .with_fs_identity(|| std::fs::write(&created, "hi"))
+            .unwrap();
+        assert_eq!(std::fs::metadata(&created).unwrap().uid(), nobody.uid);
+
+        // The agent's own access is back afterwards
+        assert!(std::fs::read(&private).is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+//! Process tree control for executed scripts
+//!
+//! Scripts are started in their own process group so the whole tree
+//! (children and grandchildren) can be killed on timeout, cancellation or
+//! shutdown.
+
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+/// Start the command in a new process group
+pub fn isolate_process_group(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        command.process_group(0);
+    }
+
+    #[cfg(windows)]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Ask a process and all of its descendants to exit
+#[cfg(unix)]
+pub fn terminate_process_tree(pid: u32) {
+    debug!("Terminating process group {}", pid);
+    signal_process_group(pid, libc::SIGTERM);
+}
+
+/// Forcefully kill a process and all of its descendants
+#[cfg(unix)]
+pub fn kill_process_tree(pid: u32) {
+    debug!("Killing process group {}", pid);
+    signal_process_group(pid, libc::SIGKILL);
+}
+
+#[cfg(unix)]
+fn signal_process_group(pid: u32, signal: libc::c_int) {
+    // The script leads its own process group, so a negative PID signals the whole group
+    let result = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+    if result != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            warn!("Failed to signal process group {}: {}", pid, err);
+        }
+    }
+}
+
+/// Ask a process and all of its descendants to exit
+#[cfg(windows)]
+pub fn terminate_process_tree(pid: u32) {
+    debug!("Terminating process tree {}", pid);
+    taskkill(pid, false);
+}
+
+/// Forcefully kill a process and all of its descendants
+#[cfg(windows)]
+pub fn kill_process_tree(pid: u32) {
+    debug!("Killing process tree {}", pid);
+    taskkill(pid, true);
+}
+
+#[cfg(windows)]
+fn taskkill(pid: u32, force: bool) {
+    let pid = pid.to_string();
+    let mut args = vec!["/T", "/PID", pid.as_str()];
+    if force {
+        args.push("/F");
+    }
+
+    match std::process::Command::new("taskkill").args(&args).output() {
+        Ok(output) if !output.status.success() => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // Process may have exited between the timeout and the kill
+            if !stderr.contains("not found") {
+                warn!("taskkill returned: {}", stderr.trim());
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to execute taskkill: {}", e),
+    }
+}
@@ -0,0 +1,888 @@
+//! Script runner - runs command scripts with the matching interpreter
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[cfg(target_os = "linux")]
+use super::limits::{apply_rlimits, killed_by_cpu_limit, CommandCgroup};
+use super::interpreters::{self, Invocation};
+use super::limits::{LimitKind, ResourceLimits};
+use super::privileges::RunAs;
+use super::variables::Redactor;
+use super::process::{isolate_process_group, kill_process_tree, terminate_process_tree};
+
+/// How long to keep reading output after the process has exited or been killed
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a cancelled script gets to exit before its tree is killed
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Script interpreter requested by the backend (`script_type`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptType {
+    PowerShell,
+    Bash,
+    Cmd,
+    Sh,
+    Python,
+}
+
+impl ScriptType {
+    /// Parse the backend `script_type` value
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "powershell" | "pwsh" => Some(ScriptType::PowerShell),
+            "bash" => Some(ScriptType::Bash),
+            "cmd" => Some(ScriptType::Cmd),
+            "sh" => Some(ScriptType::Sh),
+            "python" | "python3" => Some(ScriptType::Python),
+            _ => None,
+        }
+    }
+
+    /// Canonical `script_type` value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScriptType::PowerShell => "powershell",
+            ScriptType::Bash => "bash",
+            ScriptType::Cmd => "cmd",
+            ScriptType::Sh => "sh",
+            ScriptType::Python => "python",
+        }
+    }
+
+    /// File extension used for the temporary script file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ScriptType::PowerShell => "ps1",
+            ScriptType::Bash | ScriptType::Sh => "sh",
+            ScriptType::Cmd => "cmd",
+            ScriptType::Python => "py",
+        }
+    }
+}
+
+/// How a script run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// The process exited on its own
+    Exited,
+    /// The process tree was killed after exceeding its timeout
+    TimedOut { after_secs: u64 },
+    /// The process tree was stopped because the backend cancelled the command
+    Cancelled,
+    /// The process tree was killed because the agent is shutting down
+    Shutdown,
+    /// The process tree was stopped by one of its resource limits
+    LimitExceeded { limit: LimitKind },
+}
+
+/// Limits and stop signals for a single script run
+#[derive(Debug, Clone)]
+pub struct ExecutionControl {
+    /// Maximum run time before the process tree is killed
+    pub timeout: Duration,
+    /// Fired when the backend cancels the command (graceful, then forced stop)
+    pub cancel: CancellationToken,
+    /// Fired when the agent shuts down (immediate kill)
+    pub shutdown: CancellationToken,
+}
+
+/// Who and how a script runs
+///
+/// Not `Debug`: `env` may hold secret values.
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    /// Unprivileged account to run as (the agent's own account when absent)
+    pub run_as: Option<RunAs>,
+    /// Resource limits for the process tree
+    pub limits: ResourceLimits,
+    /// Extra environment variables (blueprint variables and secrets)
+    pub env: Vec<(String, String)>,
+    /// Strips secret values from captured output
+    pub redactor: Redactor,
+}
+
+/// Outcome of running a script
+#[derive(Debug, Clone)]
+pub struct ExecutionOutcome {
+    /// How the run ended
+    pub termination: Termination,
+    /// Process exit code (-1 if the process was terminated by a signal)
+    pub exit_code: i32,
+    /// Captured standard output (raw bytes, not necessarily UTF-8)
+    pub stdout: Vec<u8>,
+    /// Captured standard error (raw bytes, not necessarily UTF-8)
+    pub stderr: Vec<u8>,
+}
+
+/// Runs scripts from a scratch directory under the agent data dir
+pub struct ScriptRunner {
+    work_dir: PathBuf,
+}
+
+impl ScriptRunner {
+    /// Create a new runner writing scripts into `work_dir`
+    pub fn new(work_dir: impl AsRef<Path>) -> Self {
+        Self {
+            work_dir: work_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Write the script to disk, run it and capture its output
+    ///
+    /// The interpreter (from a `#!` line, else from `script_type`) is looked
+    /// up first, so a missing one fails before anything is written.
+    /// Each run gets its own working directory, removed afterwards. Output
+    /// is written into `output` as it is produced so callers can stream it
+    /// while the script runs. The process tree is stopped when `control`
+    /// says so; output captured up to that point is kept.
+    pub async fn execute(
+        &self,
+        command_id: u64,
+        script_type: ScriptType,
+        script_content: &str,
+        options: &RunOptions,
+        output: &CapturedOutput,
+        control: &ExecutionControl,
+    ) -> Result<ExecutionOutcome> {
+        let invocation = interpreters::resolve(script_type, script_content)?;
+
+        fs::create_dir_all(&self.work_dir)
+            .await
+            .context("Failed to create command work directory")?;
+
+        let run_dir = self.work_dir.join(format!("command-{}", command_id));
+        if fs::metadata(&run_dir).await.is_ok() {
+            // Left behind by an interrupted run
+            fs::remove_dir_all(&run_dir)
+                .await
+                .context("Failed to clear stale command directory")?;
+        }
+        fs::create_dir(&run_dir)
+            .await
+            .context("Failed to create command directory")?;
+
+        // PowerShell's -File needs .ps1, so this follows the interpreter that runs it
+        let script_path = run_dir.join(format!("script.{}", invocation.script_type.extension()));
+        let tmp_dir = run_dir.join("tmp");
+
+        let result = async {
+            fs::write(&script_path, script_content)
+                .await
+                .context("Failed to write script file")?;
+            fs::create_dir(&tmp_dir)
+                .await
+                .context("Failed to create command temp directory")?;
+
+            if let Some(run_as) = &options.run_as {
+                prepare_for_account(&self.work_dir, &run_dir, &[&script_path, &tmp_dir], run_as)?;
+            }
+
+            self.run_script(&invocation, &script_path, &run_dir, options, output, control)
+                .await
+        }
+        .await;
+
+        if let Err(e) = fs::remove_dir_all(&run_dir).await {
+            warn!("Failed to remove command directory {:?}: {}", run_dir, e);
+        }
+
+        result
+    }
+
+    /// Spawn the interpreter and wait for it to finish, time out or be stopped
+    async fn run_script(
+        &self,
+        invocation: &Invocation,
+        script_path: &Path,
+        run_dir: &Path,
+        options: &RunOptions,
+        output: &CapturedOutput,
+        control: &ExecutionControl,
+    ) -> Result<ExecutionOutcome> {
+        let program = &invocation.program;
+        debug!("Running {:?} {:?} {:?}", program, invocation.args, script_path);
+
+        let mut command = Command::new(program);
+        command
+            .args(&invocation.args)
+            .arg(script_path)
+            .current_dir(run_dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        isolate_process_group(&mut command);
+
+        let limits = &options.limits;
+        #[cfg(target_os = "linux")]
+        let cgroup = if limits.needs_cgroup() {
+            let name = run_dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let cgroup = CommandCgroup::create(&name, limits)
+                .context("Failed to set up cgroup for memory/CPU caps")?;
+            Some(cgroup)
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        apply_rlimits(&mut command, limits);
+        #[cfg(not(target_os = "linux"))]
+        if limits.needs_cgroup() || limits.needs_rlimits() {
+            warn!("CPU, memory and file limits are only enforced on Linux");
+        }
+
+        if let Some(run_as) = &options.run_as {
+            debug!("Running script as {} (uid {})", run_as.name, run_as.uid);
+            run_as.apply(&mut command, run_dir);
+        }
+
+        let tmp_dir = run_dir.join("tmp");
+        command
+            .env("TMPDIR", &tmp_dir)
+            .env("TMP", &tmp_dir)
+            .env("TEMP", &tmp_dir)
+            .envs(options.env.iter().map(|(k, v)| (k, v)));
+
+        let spawned = command
+            .spawn()
+            .with_context(|| format!("Failed to start interpreter {:?}", program));
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                #[cfg(target_os = "linux")]
+                if let Some(cgroup) = cgroup {
+                    cgroup.remove().await;
+                }
+                return Err(e);
+            }
+        };
+
+        // The interpreter has only just exec'd, before it could start anything
+        #[cfg(target_os = "linux")]
+        if let Some(e) = cgroup.as_ref().and_then(|cgroup| {
+            child
+                .id()
+                .context("Script exited before it could be placed in its cgroup")
+                .and_then(|pid| cgroup.add_process(pid))
+                .err()
+        }) {
+            let _ = kill_and_reap(&mut child).await;
+            if let Some(cgroup) = cgroup {
+                cgroup.remove().await;
+            }
+            return Err(e.context("Failed to apply memory/CPU caps"));
+        }
+
+        let output_limit = limits.output_bytes.map(OutputLimit::new);
+        let stdout_reader = output.stdout.capture(
+            child.stdout.take(),
+            options.redactor.clone(),
+            output_limit.clone(),
+        );
+        let stderr_reader = output.stderr.capture(
+            child.stderr.take(),
+            options.redactor.clone(),
+            output_limit.clone(),
+        );
+        let output_exceeded = async {
+            match &output_limit {
+                Some(limit) => limit.exceeded.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let (termination, status) = tokio::select! {
+            status = child.wait() => {
+                (Termination::Exited, status.context("Failed to wait for script process")?)
+            }
+            _ = tokio::time::sleep(control.timeout) => {
+                warn!("Script exceeded timeout of {}s, killing process tree", control.timeout.as_secs());
+                let status = kill_and_reap(&mut child).await?;
+                (Termination::TimedOut { after_secs: control.timeout.as_secs() }, status)
+            }
+            _ = control.cancel.cancelled() => {
+                info!("Command cancelled by server, stopping script");
+                let status = terminate_and_reap(&mut child, &control.shutdown).await?;
+                (Termination::Cancelled, status)
+            }
+            _ = control.shutdown.cancelled() => {
+                warn!("Agent shutting down, killing running script");
+                let status = kill_and_reap(&mut child).await?;
+                (Termination::Shutdown, status)
+            }
+            _ = output_exceeded => {
+                warn!("Script exceeded its output limit, killing process tree");
+                let status = kill_and_reap(&mut child).await?;
+                (Termination::LimitExceeded { limit: LimitKind::Output }, status)
+            }
+        };
+
+        #[cfg(target_os = "linux")]
+        let termination = {
+            let mut termination = termination;
+            if termination == Termination::Exited {
+                if cgroup.as_ref().is_some_and(|c| c.oom_killed()) {
+                    termination = Termination::LimitExceeded { limit: LimitKind::Memory };
+                } else if limits
+                    .cpu_seconds
+                    .is_some_and(|secs| killed_by_cpu_limit(&status, secs, cgroup.as_ref()))
+                {
+                    termination = Termination::LimitExceeded { limit: LimitKind::CpuTime };
+                }
+            }
+            if let Some(cgroup) = cgroup {
+                cgroup.remove().await;
+            }
+            termination
+        };
+
+        // A detached grandchild could keep the pipes open - don't wait on it forever
+        for reader in [stdout_reader, stderr_reader] {
+            let abort = reader.abort_handle();
+            if tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, reader).await.is_err() {
+                debug!("Output pipe still open after process exit, abandoning reader");
+                abort.abort();
+            }
+        }
+
+        Ok(ExecutionOutcome {
+            termination,
+            exit_code: status.code().unwrap_or(-1),
+            stdout: output.stdout.contents(),
+            stderr: output.stderr.contents(),
+        })
+    }
+}
+
+/// Hand the command directory to the account the script will run as
+fn prepare_for_account(
+    work_dir: &Path,
+    run_dir: &Path,
+    contents: &[&Path],
+    run_as: &RunAs,
+) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // The account may pass through the shared work dir but not list it
+        std::fs::set_permissions(work_dir, std::fs::Permissions::from_mode(0o711))
+            .context("Failed to set command work directory permissions")?;
+        std::fs::set_permissions(run_dir, std::fs::Permissions::from_mode(0o700))
+            .context("Failed to set command directory permissions")?;
+    }
+    #[cfg(not(unix))]
+    let _ = work_dir;
+
+    run_as.take_ownership(run_dir)?;
+    for path in contents {
+        run_as.take_ownership(path)?;
+    }
+    Ok(())
+}
+
+/// Ask the process tree to exit, killing it if it is still running after the
+/// grace period (or immediately if the agent starts shutting down)
+async fn terminate_and_reap(
+    child: &mut tokio::process::Child,
+    shutdown: &CancellationToken,
+) -> Result<std::process::ExitStatus> {
+    let pid = child.id();
+    if let Some(pid) = pid {
+        terminate_process_tree(pid);
+    }
+
+    tokio::select! {
+        status = child.wait() => {
+            // The leader exited - make sure nothing it left behind survives
+            if let Some(pid) = pid {
+                kill_process_tree(pid);
+            }
+            status.context("Failed to wait for script process")
+        }
+        _ = tokio::time::sleep(CANCEL_GRACE_PERIOD) => {
+            warn!("Script ignored termination request, killing process tree");
+            kill_and_reap(child).await
+        }
+        _ = shutdown.cancelled() => kill_and_reap(child).await,
+    }
+}
+
+/// Kill the whole process tree and wait for the direct child to exit
+async fn kill_and_reap(child: &mut tokio::process::Child) -> Result<std::process::ExitStatus> {
+    if let Some(pid) = child.id() {
+        kill_process_tree(pid);
+    }
+    // Fall back to killing the direct child if the tree kill missed it
+    let _ = child.start_kill();
+    child.wait().await.context("Failed to reap killed script process")
+}
+
+/// Output of a running script, readable while the script is still running
+#[derive(Clone, Default)]
+pub struct CapturedOutput {
+    pub stdout: OutputBuffer,
+    pub stderr: OutputBuffer,
+}
+
+/// Cap on a script's combined stdout and stderr
+#[derive(Clone)]
+struct OutputLimit {
+    remaining: Arc<Mutex<u64>>,
+    exceeded: CancellationToken,
+}
+
+impl OutputLimit {
+    fn new(max_bytes: u64) -> Self {
+        Self {
+            remaining: Arc::new(Mutex::new(max_bytes)),
+            exceeded: CancellationToken::new(),
+        }
+    }
+
+    /// Claim up to `len` bytes of the allowance, returning how many fit
+    fn take(&self, len: usize) -> usize {
+        let mut remaining = self.remaining.lock().unwrap();
+        let allowed = (*remaining).min(len as u64);
+        *remaining -= allowed;
+        if (allowed as usize) < len {
+            self.exceeded.cancel();
+        }
+        allowed as usize
+    }
+}
+
+/// Output captured from a pipe, shared with its reader task so partial
+/// output survives the process being killed
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl OutputBuffer {
+    /// Spawn a task copying the pipe into this buffer until EOF
+    ///
+    /// Secrets are redacted before anything becomes visible in the buffer;
+    /// the last few bytes are held back so a secret split across two reads
+    /// is still caught. With a `limit`, reading stops once the script's
+    /// total output reaches it.
+    fn capture<R>(
+        &self,
+        pipe: Option<R>,
+        redactor: Redactor,
+        limit: Option<OutputLimit>,
+    ) -> JoinHandle<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let buffer = self.clone();
+        tokio::spawn(async move {
+            let Some(mut pipe) = pipe else { return };
+            let mut chunk = [0u8; 8192];
+            let mut held_back = Vec::new();
+            loop {
+                match pipe.read(&mut chunk).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) if redactor.is_empty() => {
+                        if !buffer.append_limited(&chunk[..n], limit.as_ref()) {
+                            return;
+                        }
+                    }
+                    Ok(n) => {
+                        held_back.extend_from_slice(&chunk[..n]);
+                        let mut redacted = redactor.redact(&held_back);
+                        held_back = redacted.split_off(redacted.len().saturating_sub(redactor.overlap()));
+                        if !buffer.append_limited(&redacted, limit.as_ref()) {
+                            return;
+                        }
+                    }
+                }
+            }
+            buffer.append_limited(&held_back, limit.as_ref());
+        })
+    }
+
+    /// Append as much of `bytes` as the limit allows, returning false once it is hit
+    fn append_limited(&self, bytes: &[u8], limit: Option<&OutputLimit>) -> bool {
+        let allowed = limit.map_or(bytes.len(), |l| l.take(bytes.len()));
+        self.append(&bytes[..allowed]);
+        allowed == bytes.len()
+    }
+
+    /// Append newly read bytes
+    pub fn append(&self, bytes: &[u8]) {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+    }
+
+    /// Everything captured so far
+    fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Bytes captured from `offset` onwards, at most `max_len` of them
+    pub fn read_from(&self, offset: usize, max_len: usize) -> Vec<u8> {
+        let data = self.0.lock().unwrap();
+        let start = offset.min(data.len());
+        let end = start.saturating_add(max_len).min(data.len());
+        data[start..end].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::privileges::can_drop_privileges;
+
+    fn control(timeout: Duration) -> ExecutionControl {
+        ExecutionControl {
+            timeout,
+            cancel: CancellationToken::new(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_script_type() {
+        assert_eq!(ScriptType::parse("powershell"), Some(ScriptType::PowerShell));
+        assert_eq!(ScriptType::parse("Bash"), Some(ScriptType::Bash));
+        assert_eq!(ScriptType::parse("cmd"), Some(ScriptType::Cmd));
+        assert_eq!(ScriptType::parse("sh"), Some(ScriptType::Sh));
+        assert_eq!(ScriptType::parse("python3"), Some(ScriptType::Python));
+        assert_eq!(ScriptType::parse("perl"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_sh_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+
+        let outcome = runner
+            .execute(
+                1,
+                ScriptType::Sh,
+                "echo hello\necho oops >&2\nexit 3\n",
+                &RunOptions::default(),
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.termination, Termination::Exited);
+        assert_eq!(outcome.exit_code, 3);
+        assert_eq!(outcome.stdout, b"hello\n");
+        assert_eq!(outcome.stderr, b"oops\n");
+        assert!(!dir.path().join("command-1").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_kills_process_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let started = std::time::Instant::now();
+
+        // The backgrounded sleep inherits the pipes; the run only finishes
+        // promptly if the grandchild is killed along with the shell
+        let outcome = runner
+            .execute(
+                2,
+                ScriptType::Sh,
+                "echo partial\nsleep 60 &\nsleep 60\n",
+                &RunOptions::default(),
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(1)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.termination, Termination::TimedOut { after_secs: 1 });
+        assert_eq!(outcome.stdout, b"partial\n");
+        assert!(started.elapsed() < OUTPUT_DRAIN_TIMEOUT);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_shutdown_kills_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let control = control(Duration::from_secs(30));
+
+        let shutdown = control.shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            shutdown.cancel();
+        });
+
+        let outcome = runner
+            .execute(
+                3,
+                ScriptType::Sh,
+                "sleep 60\n",
+                &RunOptions::default(),
+                &CapturedOutput::default(),
+                &control,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.termination, Termination::Shutdown);
+        assert_eq!(outcome.exit_code, -1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancel_terminates_gracefully() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let control = control(Duration::from_secs(30));
+
+        let cancel = control.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            cancel.cancel();
+        });
+
+        // The script traps SIGTERM, so it gets to report before exiting
+        let outcome = runner
+            .execute(
+                4,
+                ScriptType::Sh,
+                "trap 'echo stopping; exit 143' TERM\necho started\nwhile true; do sleep 0.1; done\n",
+                &RunOptions::default(),
+                &CapturedOutput::default(),
+                &control,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.termination, Termination::Cancelled);
+        assert_eq!(outcome.exit_code, 143);
+        assert_eq!(outcome.stdout, b"started\nstopping\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore = "needs root and a `nobody` account; run with --ignored"]
+    async fn test_run_as_unprivileged_account() {
+        use std::os::unix::fs::PermissionsExt;
+
+        assert!(can_drop_privileges(), "must run as root");
+        let nobody = RunAs::lookup("nobody").expect("no `nobody` account");
+
+        let dir = tempfile::tempdir().unwrap();
+        // The temp dir is private to root; the account needs to reach its command dir
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o711)).unwrap();
+        let runner = ScriptRunner::new(dir.path().join("commands"));
+        let options = RunOptions {
+            run_as: Some(nobody.clone()),
+            ..Default::default()
+        };
+
+        let outcome = runner
+            .execute(
+                5,
+                ScriptType::Sh,
+                "id -u\necho \"$HOME\"\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        let stdout = String::from_utf8(outcome.stdout).unwrap();
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some(nobody.uid.to_string().as_str()));
+        assert!(lines.next().unwrap().ends_with("command-5"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_limit_kills_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let options = RunOptions {
+            limits: ResourceLimits {
+                output_bytes: Some(1000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let outcome = runner
+            .execute(
+                6,
+                ScriptType::Sh,
+                "while true; do echo spam; done\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome.termination,
+            Termination::LimitExceeded { limit: LimitKind::Output }
+        );
+        assert_eq!(outcome.stdout.len(), 1000);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_cpu_limit_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let options = RunOptions {
+            limits: ResourceLimits {
+                cpu_seconds: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let outcome = runner
+            .execute(
+                7,
+                ScriptType::Sh,
+                "while true; do :; done\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome.termination,
+            Termination::LimitExceeded { limit: LimitKind::CpuTime }
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore = "needs root and a `nobody` account; run with --ignored"]
+    async fn test_limits_apply_to_unprivileged_account() {
+        use std::os::unix::fs::PermissionsExt;
+
+        assert!(can_drop_privileges(), "must run as root");
+        let nobody = RunAs::lookup("nobody").expect("no `nobody` account");
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o711)).unwrap();
+        let runner = ScriptRunner::new(dir.path().join("commands"));
+        let options = RunOptions {
+            run_as: Some(nobody.clone()),
+            limits: ResourceLimits {
+                cpu_seconds: Some(1),
+                open_files: Some(64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let outcome = runner
+            .execute(
+                8,
+                ScriptType::Sh,
+                "id -u\nulimit -n\nwhile true; do :; done\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(outcome.stdout).unwrap(),
+            format!("{}\n64\n", nobody.uid)
+        );
+        assert_eq!(
+            outcome.termination,
+            Termination::LimitExceeded { limit: LimitKind::CpuTime }
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore = "needs root, a `nobody` account and a delegated cgroup v2 subtree; run with --ignored"]
+    async fn test_cgroup_limits_apply_to_unprivileged_account() {
+        use std::os::unix::fs::PermissionsExt;
+
+        assert!(can_drop_privileges(), "must run as root");
+        let nobody = RunAs::lookup("nobody").expect("no `nobody` account");
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o711)).unwrap();
+        let runner = ScriptRunner::new(dir.path().join("commands"));
+        let options = RunOptions {
+            run_as: Some(nobody.clone()),
+            limits: ResourceLimits {
+                memory_max_bytes: Some(64 * 1024 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let outcome = runner
+            .execute(
+                9,
+                ScriptType::Sh,
+                "id -u\ncat /proc/self/cgroup\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        let stdout = String::from_utf8(outcome.stdout).unwrap();
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some(nobody.uid.to_string().as_str()));
+        assert!(lines.any(|line| line.starts_with("0::") && line.ends_with("/command-9")));
+        assert_eq!(outcome.termination, Termination::Exited);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_secrets_passed_by_env_and_redacted() {
+        use crate::commands::variables::SecretValue;
+
+        let dir = tempfile::tempdir().unwrap();
+        let runner = ScriptRunner::new(dir.path());
+        let secret = SecretValue::from("hunter2");
+        let options = RunOptions {
+            env: vec![("RMM_SECRET_TOKEN".to_string(), "hunter2".to_string())],
+            redactor: Redactor::new([&secret]),
+            ..Default::default()
+        };
+
+        // printf splits the secret across two writes
+        let outcome = runner
+            .execute(
+                8,
+                ScriptType::Sh,
+                "printf 'token=hun'\nsleep 0.2\nprintf 'ter2 len=%s\\n' \"${#RMM_SECRET_TOKEN}\"\n",
+                &options,
+                &CapturedOutput::default(),
+                &control(Duration::from_secs(30)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.stdout, b"token=[REDACTED] len=7\n");
+    }
+}
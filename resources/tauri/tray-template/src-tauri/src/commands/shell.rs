@@ -0,0 +1,601 @@
+//! Interactive remote shell sessions
+//!
+//! The `open_shell` action makes the agent dial out to
+//! `wss://<server>/api/commands/{id}/shell` and attach a PTY-backed shell to
+//! that connection, so devices still never accept inbound connections.
+//!
+//! On the socket, binary messages carry raw terminal bytes in both
+//! directions. Text messages are JSON control messages:
+//! - from the backend: `{"type":"resize","cols":120,"rows":40}` and
+//!   `{"type":"close"}`
+//! - from the agent, just before it closes: `{"type":"closed","reason":...}`
+//!
+//! A session ends when the shell exits, the operator closes it, nothing is
+//! typed for the idle timeout, the maximum session length is reached or the
+//! agent shuts down. Everything typed and printed is recorded in an
+//! asciicast v2 transcript under `data_dir/sessions` for audit, written as
+//! it happens so a crash doesn't lose it.
+//!
+//! Like a script, the shell runs as the unprivileged script account unless
+//! the command `requires_admin`.
+
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::interpreters::find_program;
+use super::privileges::RunAs;
+use super::process::kill_process_tree;
+use crate::api::{ApiClient, WebSocket};
+use crate::config::Config;
+
+/// Terminal size used until the backend sends a resize
+const DEFAULT_COLS: u16 = 80;
+const DEFAULT_ROWS: u16 = 24;
+
+/// Read buffer for PTY output
+const PTY_READ_BYTES: usize = 8192;
+
+/// Limits and initial size for one session
+#[derive(Debug, Clone, Copy)]
+pub struct ShellOptions {
+    pub cols: u16,
+    pub rows: u16,
+    /// Close the session when nothing has been typed for this long
+    pub idle_timeout: Duration,
+    /// Close the session this long after it started, regardless of activity
+    pub max_duration: Duration,
+}
+
+impl ShellOptions {
+    /// Session options from the action, capped by the agent's configuration
+    pub fn new(
+        config: &Config,
+        cols: Option<u16>,
+        rows: Option<u16>,
+        idle_timeout_seconds: Option<u64>,
+        max_duration_seconds: Option<u64>,
+    ) -> Self {
+        let cap = |requested: Option<u64>, max: u64| {
+            Duration::from_secs(requested.map_or(max, |r| r.clamp(1, max)))
+        };
+        Self {
+            cols: cols.filter(|c| *c > 0).unwrap_or(DEFAULT_COLS),
+            rows: rows.filter(|r| *r > 0).unwrap_or(DEFAULT_ROWS),
+            idle_timeout: cap(idle_timeout_seconds, config.shell_idle_timeout),
+            max_duration: cap(max_duration_seconds, config.shell_max_duration),
+        }
+    }
+}
+
+/// Why a session ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    ShellExited,
+    ClosedByOperator,
+    Disconnected,
+    IdleTimeout,
+    MaxDuration,
+    Shutdown,
+}
+
+impl SessionEnd {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionEnd::ShellExited => "shell_exited",
+            SessionEnd::ClosedByOperator => "closed_by_operator",
+            SessionEnd::Disconnected => "disconnected",
+            SessionEnd::IdleTimeout => "idle_timeout",
+            SessionEnd::MaxDuration => "max_duration",
+            SessionEnd::Shutdown => "shutdown",
+        }
+    }
+}
+
+/// Control messages sent by the backend as text frames
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    Resize { cols: u16, rows: u16 },
+    Close,
+}
+
+/// A shell session whose socket is open but which hasn't started yet
+pub struct ShellSession {
+    command_id: u64,
+    socket: WebSocket,
+    options: ShellOptions,
+    run_as: Option<RunAs>,
+    transcript_path: PathBuf,
+}
+
+impl ShellSession {
+    /// Dial out to the backend's shell endpoint for this command
+    pub async fn connect(
+        config: &Config,
+        api: &ApiClient,
+        command_id: u64,
+        options: ShellOptions,
+        run_as: Option<RunAs>,
+        api_key: &str,
+    ) -> Result<Self> {
+        info!("Opening shell session for command {}", command_id);
+        let socket = api.connect_command_shell(api_key, command_id).await?;
+
+        let started = chrono::Utc::now();
+        let transcript_path = config.data_dir.join("sessions").join(format!(
+            "session-{}-{}.cast",
+            command_id,
+            started.format("%Y%m%dT%H%M%SZ")
+        ));
+
+        Ok(Self {
+            command_id,
+            socket,
+            options,
+            run_as,
+            transcript_path,
+        })
+    }
+
+    /// Where the session's transcript is written
+    pub fn transcript_path(&self) -> &Path {
+        &self.transcript_path
+    }
+
+    /// Run the shell until the session ends, then close the socket
+    pub async fn run(mut self, shutdown: CancellationToken) -> Result<SessionEnd> {
+        let mut transcript = Transcript::create(&self.transcript_path, &self.options)?;
+        let mut shell = match Shell::spawn(&self.options, self.run_as.as_ref()) {
+            Ok(shell) => shell,
+            Err(e) => {
+                let _ = self
+                    .send_closed(&format!("failed to start shell: {:#}", e), None)
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let started = tokio::time::Instant::now();
+        let max_deadline = started + self.options.max_duration;
+        let mut idle_deadline = started + self.options.idle_timeout;
+
+        let end = loop {
+            tokio::select! {
+                output = shell.output.recv() => match output {
+                    Some(data) => {
+                        transcript.record("o", &data);
+                        if self.socket.send(Message::Binary(data)).await.is_err() {
+                            break SessionEnd::Disconnected;
+                        }
+                    }
+                    None => break SessionEnd::ShellExited,
+                },
+                message = self.socket.next() => match message {
+                    Some(Ok(Message::Binary(data))) => {
+                        idle_deadline = tokio::time::Instant::now() + self.options.idle_timeout;
+                        transcript.record("i", &data);
+                        if shell.input.send(data).await.is_err() {
+                            break SessionEnd::ShellExited;
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(ControlMessage::Resize { cols, rows }) => {
+                            shell.resize(cols, rows);
+                            transcript.record("r", format!("{}x{}", cols, rows).as_bytes());
+                        }
+                        Ok(ControlMessage::Close) => break SessionEnd::ClosedByOperator,
+                        Err(e) => debug!("Ignoring unknown shell control message: {}", e),
+                    },
+                    Some(Ok(Message::Close(_))) | None => break SessionEnd::Disconnected,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        warn!("Shell session socket error: {}", e);
+                        break SessionEnd::Disconnected;
+                    }
+                },
+                _ = tokio::time::sleep_until(idle_deadline) => break SessionEnd::IdleTimeout,
+                _ = tokio::time::sleep_until(max_deadline) => break SessionEnd::MaxDuration,
+                _ = shutdown.cancelled() => break SessionEnd::Shutdown,
+            }
+        };
+
+        let exit_code = shell.stop().await;
+        info!(
+            "Shell session for command {} ended: {} after {}s",
+            self.command_id,
+            end.as_str(),
+            started.elapsed().as_secs()
+        );
+
+        if end != SessionEnd::Disconnected {
+            let _ = self.send_closed(end.as_str(), exit_code).await;
+        }
+        transcript.finish();
+
+        Ok(end)
+    }
+
+    async fn send_closed(&mut self, reason: &str, exit_code: Option<u32>) -> Result<()> {
+        let message = json!({ "type": "closed", "reason": reason, "exit_code": exit_code });
+        self.socket.send(Message::Text(message.to_string())).await?;
+        self.socket.close(None).await?;
+        Ok(())
+    }
+}
+
+/// The shell process and the threads moving bytes to and from its PTY
+struct Shell {
+    master: Box<dyn MasterPty + Send>,
+    child: Box<dyn portable_pty::Child + Send + Sync>,
+    output: mpsc::Receiver<Vec<u8>>,
+    input: mpsc::Sender<Vec<u8>>,
+}
+
+impl Shell {
+    fn spawn(options: &ShellOptions, run_as: Option<&RunAs>) -> Result<Self> {
+        let pair = native_pty_system()
+            .openpty(PtySize {
+                rows: options.rows,
+                cols: options.cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .context("Failed to open PTY")?;
+
+        let program = shell_program().context("No shell available for the session")?;
+        debug!("Starting shell {:?}", program);
+        let child = match run_as {
+            Some(account) => spawn_as(pair.master.as_ref(), &program, account)?,
+            None => {
+                let mut command = CommandBuilder::new(program);
+                command.env("TERM", "xterm-256color");
+                if let Some(home) = dirs::home_dir() {
+                    command.cwd(home);
+                }
+                pair.slave
+                    .spawn_command(command)
+                    .context("Failed to start shell")?
+            }
+        };
+        // Only the child should hold the slave side, so reads end when it exits
+        drop(pair.slave);
+
+        let mut reader = pair
+            .master
+            .try_clone_reader()
+            .context("Failed to read from PTY")?;
+        let mut writer = pair
+            .master
+            .take_writer()
+            .context("Failed to write to PTY")?;
+
+        // PTY handles are blocking, so each direction gets its own thread
+        let (output_tx, output) = mpsc::channel(64);
+        std::thread::spawn(move || {
+            let mut buf = [0u8; PTY_READ_BYTES];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if output_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let (input, mut input_rx) = mpsc::channel::<Vec<u8>>(64);
+        std::thread::spawn(move || {
+            while let Some(data) = input_rx.blocking_recv() {
+                if writer
+                    .write_all(&data)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            master: pair.master,
+            child,
+            output,
+            input,
+        })
+    }
+
+    fn resize(&self, cols: u16, rows: u16) {
+        if cols == 0 || rows == 0 {
+            return;
+        }
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        if let Err(e) = self.master.resize(size) {
+            warn!("Failed to resize shell: {}", e);
+        }
+    }
+
+    /// Kill the shell and anything it started, returning its exit code if it had exited
+    async fn stop(self) -> Option<u32> {
+        let mut child = self.child;
+        if let Ok(Some(status)) = child.try_wait() {
+            return Some(status.exit_code());
+        }
+        // The shell leads its own session, so this takes its children too
+        match child.process_id() {
+            Some(pid) => kill_process_tree(pid),
+            None => {
+                let _ = child.kill();
+            }
+        }
+        let _ = tokio::task::spawn_blocking(move || child.wait()).await;
+        None
+    }
+}
+
+/// Start the shell on the PTY as `account`
+///
+/// portable-pty can't switch accounts, so the shell is started on the
+/// PTY's slave device directly, the way portable-pty itself does it.
+#[cfg(unix)]
+fn spawn_as(
+    master: &dyn MasterPty,
+    program: &Path,
+    account: &RunAs,
+) -> Result<Box<dyn portable_pty::Child + Send + Sync>> {
+    use std::os::unix::process::CommandExt;
+
+    let fd = master.as_raw_fd().context("PTY has no file descriptor")?;
+    let mut name = [0 as libc::c_char; 128];
+    if unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to find PTY device");
+    }
+    let tty_path = PathBuf::from(
+        unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .to_string(),
+    );
+    // As after a login, the terminal belongs to the account using it
+    account.take_ownership(&tty_path)?;
+    let tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&tty_path)
+        .with_context(|| format!("Failed to open {:?}", tty_path))?;
+
+    // The account has no home of its own to rely on
+    let home = std::env::temp_dir();
+    let mut command = tokio::process::Command::new(program);
+    command
+        .stdin(tty.try_clone()?)
+        .stdout(tty.try_clone()?)
+        .stderr(tty)
+        .current_dir(&home);
+    // SAFETY: the closure only calls setsid and ioctl, which are async-signal-safe.
+    // It runs before the hook `apply` adds, while the child is still root.
+    unsafe {
+        command.as_std_mut().pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    account.apply(&mut command, &home);
+    command
+        .env("SHELL", program)
+        .env("TERM", "xterm-256color");
+
+    let child = command
+        .as_std_mut()
+        .spawn()
+        .with_context(|| format!("Failed to start shell as {}", account.name))?;
+    Ok(Box::new(child))
+}
+
+/// Start the shell on the PTY as `account`
+#[cfg(not(unix))]
+fn spawn_as(
+    _master: &dyn MasterPty,
+    _program: &Path,
+    account: &RunAs,
+) -> Result<Box<dyn portable_pty::Child + Send + Sync>> {
+    anyhow::bail!("Running a shell as '{}' is not supported on this platform", account.name)
+}
+
+/// Shell to run in the PTY
+fn shell_program() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let candidates = ["powershell", "pwsh", "cmd"];
+    #[cfg(not(windows))]
+    let candidates = ["bash", "sh"];
+
+    candidates.iter().find_map(|name| find_program(name))
+}
+
+/// asciicast v2 recording of a session
+struct Transcript {
+    file: Option<std::fs::File>,
+    path: PathBuf,
+    started: Instant,
+}
+
+impl Transcript {
+    fn create(path: &Path, options: &ShellOptions) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("Failed to create sessions directory")?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("Failed to create session transcript {:?}", path))?;
+
+        // Transcripts can contain anything typed, so keep them private
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = file.set_permissions(std::fs::Permissions::from_mode(0o600));
+        }
+
+        let mut transcript = Self {
+            file: Some(file),
+            path: path.to_path_buf(),
+            started: Instant::now(),
+        };
+        transcript.write_line(&json!({
+            "version": 2,
+            "width": options.cols,
+            "height": options.rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+        }));
+        Ok(transcript)
+    }
+
+    /// Record an event: "o" for output, "i" for input, "r" for a resize
+    fn record(&mut self, kind: &str, data: &[u8]) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        self.write_line(&json!([elapsed, kind, String::from_utf8_lossy(data)]));
+    }
+
+    fn write_line(&mut self, value: &serde_json::Value) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        // One unbuffered write per event, so the transcript is complete up to a crash
+        if let Err(e) = file.write_all(format!("{}\n", value).as_bytes()) {
+            // Keep the session going; the log says the transcript is incomplete
+            warn!("Failed to write session transcript {:?}: {}", self.path, e);
+            self.file = None;
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(file) = self.file.take() {
+            if let Err(e) = file.sync_all() {
+                warn!("Failed to write session transcript {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_messages() {
+        let message: ControlMessage =
+            serde_json::from_str(r#"{"type":"resize","cols":120,"rows":40}"#).unwrap();
+        assert_eq!(
+            message,
+            ControlMessage::Resize {
+                cols: 120,
+                rows: 40
+            }
+        );
+    }
+
+    #[test]
+    fn test_options_are_capped_by_config() {
+        let config = Config {
+            shell_idle_timeout: 600,
+            shell_max_duration: 3600,
+            ..Default::default()
+        };
+
+        let options = ShellOptions::new(&config, None, Some(50), Some(60), Some(86400));
+        assert_eq!((options.cols, options.rows), (DEFAULT_COLS, 50));
+        assert_eq!(options.idle_timeout, Duration::from_secs(60));
+        assert_eq!(options.max_duration, Duration::from_secs(3600));
+    }
+
+    /// Run a session against a stand-in backend that types `input`
+    ///
+    /// Returns what the shell printed, the closing message and the transcript.
+    #[cfg(unix)]
+    async fn run_session(
+        input: &'static [u8],
+        run_as: Option<RunAs>,
+    ) -> (String, serde_json::Value, String) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            base_url: format!("http://127.0.0.1:{}", port),
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket.send(Message::Binary(input.to_vec())).await.unwrap();
+
+            let mut output = Vec::new();
+            let mut closed = None;
+            while let Some(Ok(message)) = socket.next().await {
+                match message {
+                    Message::Binary(data) => output.extend(data),
+                    Message::Text(text) => closed = Some(text),
+                    _ => {}
+                }
+            }
+            (String::from_utf8_lossy(&output).to_string(), closed)
+        });
+
+        let options = ShellOptions::new(&config, None, None, None, None);
+        let api = ApiClient::new(&config).unwrap();
+        let session = ShellSession::connect(&config, &api, 5, options, run_as, "key")
+            .await
+            .unwrap();
+        let transcript = session.transcript_path().to_path_buf();
+        let end = session.run(CancellationToken::new()).await.unwrap();
+        assert_eq!(end, SessionEnd::ShellExited);
+
+        let (output, closed) = server.await.unwrap();
+        let closed = serde_json::from_str(&closed.unwrap()).unwrap();
+        (output, closed, std::fs::read_to_string(transcript).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_session_runs_shell_and_records_transcript() {
+        let (output, closed, recorded) =
+            run_session(b"echo rmm-$((40 + 2))\nexit 3\n", None).await;
+        assert!(output.contains("rmm-42"));
+        assert_eq!(closed["reason"], "shell_exited");
+
+        assert!(recorded.starts_with("{\"height\":24,\"timestamp\":"));
+        assert!(recorded.contains("rmm-42"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore = "needs root and a `nobody` account; run with --ignored"]
+    async fn test_shell_runs_as_unprivileged_account() {
+        use super::super::privileges::can_drop_privileges;
+
+        assert!(can_drop_privileges(), "must run as root");
+        let nobody = RunAs::lookup("nobody").expect("no `nobody` account");
+        let (output, _, _) =
+            run_session(b"echo uid-$(id -u)\nexit\n", Some(nobody.clone())).await;
+        assert!(output.contains(&format!("uid-{}", nobody.uid)), "{}", output);
+    }
+}
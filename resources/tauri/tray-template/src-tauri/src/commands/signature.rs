@@ -0,0 +1,289 @@
+//! Ed25519 signature verification for commands
+//!
+//! The publisher key is pinned in the agent's local config at install time,
+//! so a compromised backend can't push commands the publisher never signed.
+//!
+//! Without a pinned key commands are accepted unsigned; heartbeats report
+//! `command_signing: "unsigned"` so the backend can see which devices those are.
+//!
+//! The signature covers this message (UTF-8, `\n` separated, no trailing
+//! newline). The first line names the format and changes whenever a line is
+//! added or its encoding changes:
+//!
+//! ```text
+//! rmm-command-v3
+//! id:<command id>
+//! type:<script_type>
+//! timeout:<timeout_seconds, empty if absent>
+//! admin:<requires_admin, true or false>
+//! script-sha256:<hex SHA-256 of script_content>
+//! action:<canonical JSON of the native action, empty if absent>
+//! limits:<canonical limits, empty if absent>
+//! variables-sha256:<hex SHA-256 of the canonical JSON of variables, empty if none>
+//! secrets-sha256:<hex SHA-256 of the canonical JSON of secrets, empty if none>
+//! ```
+//!
+//! Canonical JSON is the action as the agent parsed it, compact, with object
+//! keys in byte order and `null` fields left out; defaulted fields such as
+//! `"overwrite":false` are included. Canonical limits are the set limits as
+//! `name=value` pairs joined by `,`, in this order: `cpu_seconds`,
+//! `address_space_bytes`, `open_files`, `output_bytes`, `memory_max_bytes`,
+//! `cpu_max_percent` (e.g. `cpu_seconds=30,memory_max_bytes=268435456`).
+//! Variables and secrets are hashed as compact JSON objects with sorted
+//! keys, values exactly as sent (secrets are strings).
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use super::{PendingCommand, ResourceLimits};
+
+/// Tag on the first line of the signed message
+pub const SIGNATURE_VERSION: &str = "rmm-command-v3";
+
+/// Verifies command signatures against the pinned publisher key
+pub struct CommandVerifier {
+    key: Option<VerifyingKey>,
+}
+
+impl CommandVerifier {
+    /// Create a verifier from a base64-encoded Ed25519 public key
+    ///
+    /// Without a key, signatures are not checked.
+    pub fn new(public_key: Option<&str>) -> Result<Self> {
+        let key = match public_key {
+            Some(encoded) => Some(parse_public_key(encoded)?),
+            None => None,
+        };
+        Ok(Self { key })
+    }
+
+    /// Whether a publisher key is pinned
+    pub fn is_enforcing(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Check the command's signature, failing if it is missing or invalid
+    pub fn verify(&self, command: &PendingCommand) -> Result<()> {
+        let Some(key) = &self.key else {
+            return Ok(());
+        };
+
+        let encoded = command
+            .signature
+            .as_deref()
+            .context("Command is not signed")?;
+
+        let bytes = general_purpose::STANDARD
+            .decode(encoded.trim())
+            .context("Command signature is not valid base64")?;
+        let signature =
+            Signature::from_slice(&bytes).context("Command signature has the wrong length")?;
+
+        key.verify_strict(&signing_message(command), &signature)
+            .context("Command signature does not match the pinned publisher key")
+    }
+}
+
+/// Parse a base64-encoded Ed25519 public key
+pub fn parse_public_key(encoded: &str) -> Result<VerifyingKey> {
+    let bytes = general_purpose::STANDARD
+        .decode(encoded.trim())
+        .context("Signing key is not valid base64")?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key must be 32 bytes"))?;
+    VerifyingKey::from_bytes(&bytes).context("Signing key is not a valid Ed25519 public key")
+}
+
+/// Canonical message a command's signature covers
+pub fn signing_message(command: &PendingCommand) -> Vec<u8> {
+    let script_hash = hex::encode(Sha256::digest(command.script_content.as_bytes()));
+    let timeout = command
+        .timeout_seconds
+        .map(|t| t.to_string())
+        .unwrap_or_default();
+    let action = command
+        .action
+        .as_ref()
+        .and_then(|a| serde_json::to_value(a).ok())
+        .map(|a| without_nulls(a).to_string())
+        .unwrap_or_default();
+    let limits = command
+        .limits
+        .as_ref()
+        .map(canonical_limits)
+        .unwrap_or_default();
+    let variables = json_sha256(
+        command
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+    );
+    let secrets = json_sha256(
+        command
+            .secrets
+            .iter()
+            .map(|(name, value)| (name.clone(), value.expose().into()))
+            .collect(),
+    );
+
+    format!(
+        "{}\nid:{}\ntype:{}\ntimeout:{}\nadmin:{}\nscript-sha256:{}\naction:{}\nlimits:{}\n\
+         variables-sha256:{}\nsecrets-sha256:{}",
+        SIGNATURE_VERSION,
+        command.id,
+        command.script_type,
+        timeout,
+        command.requires_admin,
+        script_hash,
+        action,
+        limits,
+        variables,
+        secrets
+    )
+    .into_bytes()
+}
+
+/// Hex SHA-256 of a JSON object (keys sorted), or empty for no entries
+fn json_sha256(map: serde_json::Map<String, serde_json::Value>) -> String {
+    if map.is_empty() {
+        return String::new();
+    }
+    let json = serde_json::Value::Object(map).to_string();
+    hex::encode(Sha256::digest(json.as_bytes()))
+}
+
+/// Drop `null` fields; `serde_json` maps already keep keys sorted
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k, without_nulls(v)))
+            .collect(),
+        serde_json::Value::Array(items) => items.into_iter().map(without_nulls).collect(),
+        other => other,
+    }
+}
+
+/// `name=value` pairs for the set limits, in the documented order
+fn canonical_limits(limits: &ResourceLimits) -> String {
+    [
+        ("cpu_seconds", limits.cpu_seconds),
+        ("address_space_bytes", limits.address_space_bytes),
+        ("open_files", limits.open_files),
+        ("output_bytes", limits.output_bytes),
+        ("memory_max_bytes", limits.memory_max_bytes),
+        ("cpu_max_percent", limits.cpu_max_percent.map(u64::from)),
+    ]
+    .iter()
+    .filter_map(|(name, value)| value.map(|v| format!("{}={}", name, v)))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::SecretValue;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn command(signature: Option<String>) -> PendingCommand {
+        PendingCommand {
+            id: 12,
+            script_content: "uptime".to_string(),
+            script_type: "sh".to_string(),
+            action: None,
+            timeout_seconds: Some(60),
+            requires_admin: false,
+            limits: None,
+            variables: Default::default(),
+            secrets: Default::default(),
+            signature,
+        }
+    }
+
+    fn sign(key: &SigningKey, command: &PendingCommand) -> String {
+        general_purpose::STANDARD.encode(key.sign(&signing_message(command)).to_bytes())
+    }
+
+    #[test]
+    fn test_verify_signed_command() {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = general_purpose::STANDARD.encode(signing_key.verifying_key().as_bytes());
+        let verifier = CommandVerifier::new(Some(&public_key)).unwrap();
+        assert!(verifier.is_enforcing());
+
+        let mut signed = command(None);
+        signed.signature = Some(sign(&signing_key, &signed));
+        assert!(verifier.verify(&signed).is_ok());
+
+        // Any change to the signed fields invalidates the signature
+        let mut tampered = signed.clone();
+        tampered.script_content = "rm -rf /".to_string();
+        assert!(verifier.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.timeout_seconds = Some(86400);
+        assert!(verifier.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered.requires_admin = true;
+        assert!(verifier.verify(&tampered).is_err());
+
+        let mut tampered = signed.clone();
+        tampered
+            .variables
+            .insert("target".to_string(), serde_json::json!("/"));
+        assert!(verifier.verify(&tampered).is_err());
+
+        assert!(verifier.verify(&command(None)).is_err());
+    }
+
+    #[test]
+    fn test_canonical_message() {
+        let mut command = command(None);
+        command.script_content = String::new();
+        command.action = Some(crate::commands::NativeAction::Reboot {
+            delay_seconds: None,
+        });
+        command.limits = Some(ResourceLimits {
+            memory_max_bytes: Some(268435456),
+            cpu_seconds: Some(30),
+            ..Default::default()
+        });
+
+        let message = String::from_utf8(signing_message(&command)).unwrap();
+        assert_eq!(
+            message,
+            "rmm-command-v3\nid:12\ntype:sh\ntimeout:60\nadmin:false\n\
+             script-sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\
+             action:{\"type\":\"reboot\"}\n\
+             limits:cpu_seconds=30,memory_max_bytes=268435456\n\
+             variables-sha256:\nsecrets-sha256:"
+        );
+
+        command
+            .secrets
+            .insert("token".to_string(), SecretValue::from("hunter2"));
+        let message = String::from_utf8(signing_message(&command)).unwrap();
+        let expected = hex::encode(Sha256::digest(br#"{"token":"hunter2"}"#));
+        assert!(message.ends_with(&format!("secrets-sha256:{}", expected)));
+    }
+
+    #[test]
+    fn test_no_pinned_key_skips_verification() {
+        let verifier = CommandVerifier::new(None).unwrap();
+        assert!(!verifier.is_enforcing());
+        assert!(verifier.verify(&command(None)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_public_key() {
+        assert!(parse_public_key("not base64!").is_err());
+        assert!(parse_public_key(&general_purpose::STANDARD.encode([1u8; 16])).is_err());
+    }
+}
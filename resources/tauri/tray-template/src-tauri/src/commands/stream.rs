@@ -0,0 +1,130 @@
+//! Incremental output streaming for running commands
+//!
+//! While a script runs, its output is shipped to
+//! `POST /api/commands/{id}/output` in numbered chunks so the backend can
+//! reassemble it in order and show progress live.
+
+use serde::Serialize;
+
+use super::script::{CapturedOutput, OutputBuffer};
+
+/// Maximum bytes taken from each stream for a single chunk
+pub const MAX_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Payload for `POST /api/commands/{id}/output`
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputChunk {
+    /// Position of this chunk in the command's output, starting at 0
+    pub sequence: u64,
+    /// New standard output since the previous chunk
+    pub stdout: String,
+    /// New standard error since the previous chunk
+    pub stderr: String,
+}
+
+/// A chunk ready to send, plus how far it advances each stream
+#[derive(Debug)]
+pub struct PendingChunk {
+    pub chunk: OutputChunk,
+    stdout_bytes: usize,
+    stderr_bytes: usize,
+}
+
+/// Tracks how much of a command's output has been delivered
+#[derive(Debug, Default)]
+pub struct OutputCursor {
+    stdout_offset: usize,
+    stderr_offset: usize,
+    next_sequence: u64,
+}
+
+impl OutputCursor {
+    /// Build the next chunk of unsent output, if there is any
+    ///
+    /// Unless `is_final` is set, a multi-byte character split at the end of
+    /// the captured data is held back until the rest of it arrives.
+    pub fn next_chunk(&self, output: &CapturedOutput, is_final: bool) -> Option<PendingChunk> {
+        let (stdout, stdout_bytes) = read_text(&output.stdout, self.stdout_offset, is_final);
+        let (stderr, stderr_bytes) = read_text(&output.stderr, self.stderr_offset, is_final);
+
+        if stdout_bytes == 0 && stderr_bytes == 0 {
+            return None;
+        }
+
+        Some(PendingChunk {
+            chunk: OutputChunk {
+                sequence: self.next_sequence,
+                stdout,
+                stderr,
+            },
+            stdout_bytes,
+            stderr_bytes,
+        })
+    }
+
+    /// Record a chunk as delivered
+    pub fn commit(&mut self, pending: &PendingChunk) {
+        self.stdout_offset += pending.stdout_bytes;
+        self.stderr_offset += pending.stderr_bytes;
+        self.next_sequence += 1;
+    }
+}
+
+/// Read unsent bytes as text, returning the text and the bytes consumed
+fn read_text(buffer: &OutputBuffer, offset: usize, is_final: bool) -> (String, usize) {
+    let bytes = buffer.read_from(offset, MAX_CHUNK_BYTES);
+
+    match std::str::from_utf8(&bytes) {
+        Ok(text) => (text.to_string(), bytes.len()),
+        // Incomplete character at the end - wait for the remaining bytes
+        Err(e) if e.error_len().is_none() && !is_final => {
+            let valid = e.valid_up_to();
+            (String::from_utf8_lossy(&bytes[..valid]).to_string(), valid)
+        }
+        Err(_) => (String::from_utf8_lossy(&bytes).to_string(), bytes.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_are_sequenced() {
+        let output = CapturedOutput::default();
+        let mut cursor = OutputCursor::default();
+        assert!(cursor.next_chunk(&output, false).is_none());
+
+        output.stdout.append(b"step 1\n");
+        let first = cursor.next_chunk(&output, false).unwrap();
+        assert_eq!(first.chunk.sequence, 0);
+        assert_eq!(first.chunk.stdout, "step 1\n");
+        cursor.commit(&first);
+
+        output.stdout.append(b"step 2\n");
+        output.stderr.append(b"warning\n");
+        let second = cursor.next_chunk(&output, false).unwrap();
+        assert_eq!(second.chunk.sequence, 1);
+        assert_eq!(second.chunk.stdout, "step 2\n");
+        assert_eq!(second.chunk.stderr, "warning\n");
+        cursor.commit(&second);
+
+        assert!(cursor.next_chunk(&output, false).is_none());
+    }
+
+    #[test]
+    fn test_split_character_is_held_back() {
+        let output = CapturedOutput::default();
+        let mut cursor = OutputCursor::default();
+
+        // "é" is 0xC3 0xA9 - only the first byte has arrived
+        output.stdout.append(b"caf\xC3");
+        let pending = cursor.next_chunk(&output, false).unwrap();
+        assert_eq!(pending.chunk.stdout, "caf");
+        cursor.commit(&pending);
+
+        output.stdout.append(b"\xA9");
+        let pending = cursor.next_chunk(&output, false).unwrap();
+        assert_eq!(pending.chunk.stdout, "é");
+    }
+}
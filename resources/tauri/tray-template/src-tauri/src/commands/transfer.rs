@@ -0,0 +1,535 @@
+//! File transfer actions - moving files between this machine and the backend
+//!
+//! - `fetch_file` streams a local file to `POST /api/commands/{id}/files`
+//! - `put_file` streams `GET /api/commands/{id}/files` into place
+//!
+//! Both are capped at `file_transfer_max_bytes` (and any smaller limit in the
+//! action) and report the path, size and SHA-256 of what was sent or written.
+//! A put is written to a temporary file next to the target, checked against
+//! the expected hash, given its permissions and only then renamed over the
+//! target, so a failed transfer never leaves a partial file behind.
+//!
+//! Paths are resolved (following symlinks) and must not be inside the
+//! agent's data dir, which holds its key, policy and signing key, nor
+//! outside `file_transfer_roots` when the local policy sets them. Unless the
+//! command `requires_admin`, files are opened, created and renamed with the
+//! script account's access, so a transfer can't reach more than a script.
+
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use super::privileges::RunAs;
+use super::ActionContext;
+use crate::api::ApiClient;
+use crate::config::Config;
+
+/// Read size when streaming a file to the backend
+const UPLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Streams files to and from the backend for a single command
+pub struct FileTransfer {
+    config: Config,
+    api: ApiClient,
+}
+
+impl FileTransfer {
+    /// Create a new file transfer client
+    pub fn new(config: Config, api: ApiClient) -> Self {
+        Self { config, api }
+    }
+
+    /// Upload a local file, reading at most its size when the transfer started
+    pub async fn fetch(
+        &self,
+        context: &ActionContext,
+        path: &str,
+        max_bytes: Option<u64>,
+        api_key: &str,
+    ) -> Result<serde_json::Value> {
+        let (command_id, timeout) = (context.command_id, context.timeout);
+        let path = self.allowed_path(path, context.transfer_roots.as_deref())?;
+        let limit = self.limit(max_bytes);
+
+        let file = as_account(context.run_as.as_ref(), {
+            let path = path.clone();
+            move || std::fs::File::open(path)
+        })
+        .await
+        .with_context(|| format!("Failed to open {:?}", path))?;
+        let file = fs::File::from_std(file);
+        let metadata = file
+            .metadata()
+            .await
+            .context("Failed to read file metadata")?;
+        if !metadata.is_file() {
+            anyhow::bail!("{:?} is not a regular file", path);
+        }
+        let size = metadata.len();
+        if size > limit {
+            anyhow::bail!(
+                "{:?} is {} bytes, over the {} byte transfer limit",
+                path,
+                size,
+                limit
+            );
+        }
+
+        info!(
+            "Uploading {:?} ({} bytes) for command {}",
+            path, size, command_id
+        );
+
+        // Log files keep growing, so only the bytes present now are sent
+        let hasher = Arc::new(Mutex::new(Sha256::new()));
+        let body = upload_stream(file, size, hasher.clone());
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        // Each transfer is bounded by the command's timeout
+        self.api
+            .upload_command_file(
+                api_key,
+                command_id,
+                &file_name,
+                size,
+                reqwest::Body::wrap_stream(body),
+                timeout,
+            )
+            .await
+            .context("Failed to upload file")?;
+
+        let sha256 = hex::encode(hasher.lock().unwrap().clone().finalize());
+        info!("Uploaded {:?} (sha256 {})", path, sha256);
+
+        Ok(json!({
+            "path": path.display().to_string(),
+            "size": size,
+            "sha256": sha256,
+        }))
+    }
+
+    /// Download a file and atomically place it at `path`
+    pub async fn put(
+        &self,
+        context: &ActionContext,
+        path: &str,
+        expected: &ExpectedFile<'_>,
+        api_key: &str,
+    ) -> Result<serde_json::Value> {
+        let (command_id, timeout) = (context.command_id, context.timeout);
+        let run_as = context.run_as.as_ref();
+        let target = self.allowed_path(path, context.transfer_roots.as_deref())?;
+        let expected_sha256 = expected.sha256.trim().to_lowercase();
+        if expected_sha256.len() != 64 || !expected_sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("put_file needs the file's hex SHA-256");
+        }
+        let mode = expected.mode.map(parse_mode).transpose()?;
+
+        let limit = self.limit(None);
+        if let Some(size) = expected.size {
+            if size > limit {
+                anyhow::bail!(
+                    "File is {} bytes, over the {} byte transfer limit",
+                    size,
+                    limit
+                );
+            }
+        }
+        let limit = expected.size.unwrap_or(limit);
+
+        let metadata = as_account(run_as, {
+            let target = target.clone();
+            move || std::fs::metadata(target)
+        })
+        .await;
+        let existing = match metadata {
+            Ok(metadata) if metadata.is_dir() => anyhow::bail!("{:?} is a directory", target),
+            Ok(_) if !expected.overwrite => {
+                anyhow::bail!("{:?} already exists (set overwrite to replace it)", target)
+            }
+            Ok(metadata) => Some(metadata.permissions()),
+            Err(_) => None,
+        };
+        let replaced = existing.is_some();
+
+        let parent = target
+            .parent()
+            .with_context(|| format!("{:?} has no parent directory", target))?;
+        let file_name = target
+            .file_name()
+            .with_context(|| format!("{:?} has no file name", target))?
+            .to_string_lossy();
+        let partial = parent.join(format!(".{}.rmm-{}.part", file_name, command_id));
+
+        info!(
+            "Downloading file for command {} to {:?}",
+            command_id, target
+        );
+
+        let result = async {
+            let (size, sha256) = self
+                .download(command_id, &partial, limit, timeout, run_as, api_key)
+                .await?;
+
+            if let Some(expected_size) = expected.size {
+                if size != expected_size {
+                    anyhow::bail!(
+                        "Downloaded file size mismatch: expected {} bytes, got {} bytes",
+                        expected_size,
+                        size
+                    );
+                }
+            }
+            if sha256 != expected_sha256 {
+                anyhow::bail!(
+                    "Downloaded file hash mismatch: expected {}, got {}",
+                    expected_sha256,
+                    sha256
+                );
+            }
+
+            set_permissions(&partial, mode, existing).await?;
+            as_account(run_as, {
+                let (partial, target) = (partial.clone(), target.clone());
+                move || std::fs::rename(partial, target)
+            })
+            .await
+            .with_context(|| format!("Failed to move file into place at {:?}", target))?;
+            sync_dir(parent);
+
+            Ok((size, sha256))
+        }
+        .await;
+
+        let (size, sha256) = match result {
+            Ok(done) => done,
+            Err(e) => {
+                if let Err(remove_err) = fs::remove_file(&partial).await {
+                    debug!("No partial file to remove at {:?}: {}", partial, remove_err);
+                }
+                return Err(e);
+            }
+        };
+
+        info!("Placed {:?} ({} bytes, sha256 {})", target, size, sha256);
+
+        Ok(json!({
+            "path": target.display().to_string(),
+            "size": size,
+            "sha256": sha256,
+            "replaced": replaced,
+        }))
+    }
+
+    /// Stream the command's file into `partial`, returning its size and hash
+    async fn download(
+        &self,
+        command_id: u64,
+        partial: &Path,
+        limit: u64,
+        timeout: Duration,
+        run_as: Option<&RunAs>,
+        api_key: &str,
+    ) -> Result<(u64, String)> {
+        let response = self
+            .api
+            .download_command_file(api_key, command_id, timeout)
+            .await
+            .context("Failed to start download")?;
+        if let Some(length) = response.content_length() {
+            if length > limit {
+                anyhow::bail!("File is {} bytes, over the {} byte limit", length, limit);
+            }
+        }
+
+        // Left behind by an interrupted transfer
+        let _ = fs::remove_file(partial).await;
+        let file = as_account(run_as, {
+            let partial = partial.to_path_buf();
+            move || {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(partial)
+            }
+        })
+        .await
+        .with_context(|| format!("Failed to create {:?}", partial))?;
+        let mut file = fs::File::from_std(file);
+
+        let mut hasher = Sha256::new();
+        let mut written: u64 = 0;
+        let mut stream = response.bytes_stream();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.context("Error reading download stream")?;
+            written += chunk.len() as u64;
+            if written > limit {
+                anyhow::bail!("File is over the {} byte limit", limit);
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .context("Error writing to file")?;
+        }
+
+        file.flush().await.context("Failed to flush download")?;
+        file.sync_all().await.context("Failed to sync download")?;
+
+        Ok((written, hex::encode(hasher.finalize())))
+    }
+
+    /// Resolve `path` and check a transfer may use it
+    ///
+    /// The file itself may not exist yet, so its directory is resolved and
+    /// the file name joined back on; an existing file is resolved too, so a
+    /// symlink can't lead anywhere the path itself couldn't.
+    fn allowed_path(&self, path: &str, roots: Option<&[PathBuf]>) -> Result<PathBuf> {
+        let path = absolute_path(path)?;
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("{:?} has no file name", path))?;
+        let parent = path
+            .parent()
+            .with_context(|| format!("{:?} has no parent directory", path))?;
+        let parent = std::fs::canonicalize(parent)
+            .with_context(|| format!("Directory {:?} does not exist", parent))?;
+        let candidate = parent.join(file_name);
+
+        let mut resolved = vec![candidate.clone()];
+        if let Ok(target) = std::fs::canonicalize(&candidate) {
+            resolved.push(target);
+        }
+
+        let data_dir = std::fs::canonicalize(&self.config.data_dir)
+            .unwrap_or_else(|_| self.config.data_dir.clone());
+        let roots: Option<Vec<PathBuf>> = roots.map(|roots| {
+            roots
+                .iter()
+                .filter_map(|root| std::fs::canonicalize(root).ok())
+                .collect()
+        });
+
+        for resolved in &resolved {
+            if resolved.starts_with(&data_dir) {
+                anyhow::bail!("{:?} is inside the agent's data directory", path);
+            }
+            if let Some(roots) = &roots {
+                if !roots.iter().any(|root| resolved.starts_with(root)) {
+                    anyhow::bail!("{:?} is outside the allowed file transfer directories", path);
+                }
+            }
+        }
+
+        Ok(candidate)
+    }
+
+    /// The smaller of the configured cap and the action's own limit
+    fn limit(&self, max_bytes: Option<u64>) -> u64 {
+        max_bytes.map_or(self.config.file_transfer_max_bytes, |max| {
+            max.min(self.config.file_transfer_max_bytes)
+        })
+    }
+}
+
+/// What a `put_file` download must match
+#[derive(Debug, Clone, Copy)]
+pub struct ExpectedFile<'a> {
+    /// Hex SHA-256 of the file
+    pub sha256: &'a str,
+    /// Exact size in bytes, if known
+    pub size: Option<u64>,
+    /// Octal permissions (`"0644"`), Unix only
+    pub mode: Option<&'a str>,
+    /// Whether an existing file may be replaced
+    pub overwrite: bool,
+}
+
+/// Stream up to `size` bytes of `file`, hashing them on the way out
+fn upload_stream(
+    file: fs::File,
+    size: u64,
+    hasher: Arc<Mutex<Sha256>>,
+) -> impl futures_util::Stream<Item = std::io::Result<Vec<u8>>> {
+    futures_util::stream::try_unfold((file, size), move |(mut file, remaining)| {
+        let hasher = hasher.clone();
+        async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let mut buf = vec![0u8; UPLOAD_CHUNK_BYTES.min(remaining as usize)];
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "file shrank while it was being uploaded",
+                ));
+            }
+            buf.truncate(read);
+            hasher.lock().unwrap().update(&buf);
+            Ok(Some((buf, (file, remaining - read as u64))))
+        }
+    })
+}
+
+/// Run a blocking filesystem call with the account's access, if there is one
+async fn as_account<T: Send + 'static>(
+    run_as: Option<&RunAs>,
+    op: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> std::io::Result<T> {
+    let run_as = run_as.cloned();
+    tokio::task::spawn_blocking(move || match run_as {
+        Some(account) => account.with_fs_identity(op),
+        None => op(),
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+fn absolute_path(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        anyhow::bail!("File path must be absolute: {:?}", path);
+    }
+    Ok(path)
+}
+
+/// Parse octal permissions such as `"0644"` or `"755"`
+fn parse_mode(mode: &str) -> Result<u32> {
+    let mode = mode.trim();
+    let value = u32::from_str_radix(mode.strip_prefix("0o").unwrap_or(mode), 8)
+        .ok()
+        .filter(|m| *m <= 0o7777)
+        .with_context(|| format!("Invalid file mode: {:?}", mode))?;
+    Ok(value)
+}
+
+/// Apply the requested mode, or keep the permissions of the file being replaced
+async fn set_permissions(
+    path: &Path,
+    mode: Option<u32>,
+    existing: Option<std::fs::Permissions>,
+) -> Result<()> {
+    #[cfg(unix)]
+    let permissions = mode
+        .map(|mode| {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::Permissions::from_mode(mode)
+        })
+        .or(existing);
+
+    #[cfg(not(unix))]
+    let permissions = {
+        if mode.is_some() {
+            warn!("File modes are only applied on Unix");
+        }
+        existing
+    };
+
+    if let Some(permissions) = permissions {
+        fs::set_permissions(path, permissions)
+            .await
+            .with_context(|| format!("Failed to set permissions on {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Flush the rename to disk (best effort)
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Err(e) = std::fs::File::open(dir).and_then(|d| d.sync_all()) {
+        warn!("Failed to sync directory {:?}: {}", dir, e);
+    }
+
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_mode("755").unwrap(), 0o755);
+        assert_eq!(parse_mode("0o600").unwrap(), 0o600);
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("rwxr-xr-x").is_err());
+    }
+
+    #[test]
+    fn test_transfers_stay_out_of_the_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let logs = dir.path().join("logs");
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::create_dir_all(&logs).unwrap();
+        std::fs::write(data_dir.join("agent.key"), "secret").unwrap();
+
+        let config = Config {
+            data_dir: data_dir.clone(),
+            ..Default::default()
+        };
+        let transfer = FileTransfer::new(config.clone(), ApiClient::new(&config).unwrap());
+        let path = |p: &Path| p.display().to_string();
+
+        assert!(transfer.allowed_path(&path(&data_dir.join("agent.key")), None).is_err());
+        assert!(transfer.allowed_path(&path(&data_dir.join("policy.json")), None).is_err());
+        assert!(transfer.allowed_path(&path(&logs.join("app.log")), None).is_ok());
+        assert!(transfer.allowed_path("relative/app.log", None).is_err());
+
+        // Neither `..` nor a symlink gets around the check
+        let sneaky = logs.join("..").join("data").join("agent.key");
+        assert!(transfer.allowed_path(&path(&sneaky), None).is_err());
+        #[cfg(unix)]
+        {
+            let link = logs.join("key.txt");
+            std::os::unix::fs::symlink(data_dir.join("agent.key"), &link).unwrap();
+            assert!(transfer.allowed_path(&path(&link), None).is_err());
+        }
+
+        let roots = [logs.clone()];
+        assert!(transfer.allowed_path(&path(&logs.join("app.log")), Some(&roots)).is_ok());
+        assert!(transfer
+            .allowed_path(&path(&dir.path().join("other.txt")), Some(&roots))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_stream_stops_at_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.log");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        // Bytes appended after the transfer started are not sent
+        let hasher = Arc::new(Mutex::new(Sha256::new()));
+        let file = fs::File::open(&path).await.unwrap();
+        let chunks: Vec<Vec<u8>> = upload_stream(file, 5, hasher.clone())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), b"hello");
+        assert_eq!(
+            hex::encode(hasher.lock().unwrap().clone().finalize()),
+            hex::encode(Sha256::digest(b"hello"))
+        );
+
+        // A file that shrank mid-transfer is an error, not a short upload
+        let file = fs::File::open(&path).await.unwrap();
+        let results: Vec<_> = upload_stream(file, 100, Arc::new(Mutex::new(Sha256::new())))
+            .collect()
+            .await;
+        assert!(results.last().unwrap().is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Blueprint variables and secrets
+//!
+//! Values sent alongside a script are never spliced into the script text.
+//! Each one is passed to the process as an environment variable
+//! (`RMM_VAR_<NAME>` or `RMM_SECRET_<NAME>`), and `{{name}}` placeholders in
+//! the script are rewritten into references to that variable for the
+//! script's shell. Secret values are redacted from captured output before
+//! it is streamed, reported, journaled or logged.
+//!
+//! cmd expands `%VAR%` before it parses a line, so a value holding `&` or
+//! `|` would still run as a command. cmd scripts get `!VAR!` references
+//! instead, with delayed expansion switched on at the top of the script;
+//! those are expanded after parsing.
+//!
+//! Environment variable names are case-insensitive on Windows, so names
+//! that differ only in case are refused rather than silently merged.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use super::ScriptType;
+
+/// Prefix for environment variables holding blueprint variables
+pub const VARIABLE_ENV_PREFIX: &str = "RMM_VAR_";
+
+/// Prefix for environment variables holding secrets
+pub const SECRET_ENV_PREFIX: &str = "RMM_SECRET_";
+
+/// Replacement for secret values found in output
+pub const REDACTED: &str = "[REDACTED]";
+
+/// First line of a cmd script that uses `!VAR!` references
+const CMD_DELAYED_EXPANSION: &str = "@setlocal EnableDelayedExpansion\r\n";
+
+/// A secret value that never shows up in `Debug` output (and so in logs)
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct SecretValue(String);
+
+impl SecretValue {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl From<&str> for SecretValue {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+/// Environment variables for a command's variables and secrets
+pub fn environment(
+    variables: &BTreeMap<String, serde_json::Value>,
+    secrets: &BTreeMap<String, SecretValue>,
+) -> Result<Vec<(String, String)>> {
+    let mut env = Vec::with_capacity(variables.len() + secrets.len());
+
+    for (name, value) in variables {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Null => String::new(),
+            other => other.to_string(),
+        };
+        env.push((env_name(VARIABLE_ENV_PREFIX, name)?, value));
+    }
+    for (name, value) in secrets {
+        env.push((env_name(SECRET_ENV_PREFIX, name)?, value.expose().to_string()));
+    }
+
+    let mut seen = HashSet::new();
+    for (var, _) in &env {
+        if !seen.insert(var.as_str()) {
+            anyhow::bail!("Variable names differ only in case: {}", var);
+        }
+    }
+
+    Ok(env)
+}
+
+/// Rewrite `{{name}}` placeholders into environment variable references
+///
+/// Placeholders that don't name a variable or secret are left untouched.
+pub fn render_placeholders(
+    script: &str,
+    script_type: ScriptType,
+    variables: &BTreeMap<String, serde_json::Value>,
+    secrets: &BTreeMap<String, SecretValue>,
+) -> String {
+    let mut rendered = String::with_capacity(script.len());
+    let mut rest = script;
+    let mut referenced = false;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + 2 + len].trim();
+
+        let prefix = if variables.contains_key(name) {
+            Some(VARIABLE_ENV_PREFIX)
+        } else if secrets.contains_key(name) {
+            Some(SECRET_ENV_PREFIX)
+        } else {
+            None
+        };
+
+        rendered.push_str(&rest[..start]);
+        match prefix.and_then(|p| env_name(p, name).ok()) {
+            Some(var) => {
+                rendered.push_str(&env_reference(script_type, &var));
+                referenced = true;
+            }
+            None => rendered.push_str(&rest[start..start + 4 + len]),
+        }
+        rest = &rest[start + 4 + len..];
+    }
+
+    rendered.push_str(rest);
+    if referenced && script_type == ScriptType::Cmd {
+        rendered.insert_str(0, CMD_DELAYED_EXPANSION);
+    }
+    rendered
+}
+
+/// Environment variable name for a blueprint variable or secret
+fn env_name(prefix: &str, name: &str) -> Result<String> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        anyhow::bail!("Invalid variable name: {:?}", name);
+    }
+    Ok(format!("{}{}", prefix, name.to_ascii_uppercase()))
+}
+
+/// How the script's shell reads an environment variable
+fn env_reference(script_type: ScriptType, var: &str) -> String {
+    match script_type {
+        ScriptType::PowerShell => format!("$env:{}", var),
+        ScriptType::Bash | ScriptType::Sh => format!("${{{}}}", var),
+        ScriptType::Cmd => format!("!{}!", var),
+        // An expression, so it works without the script importing os
+        ScriptType::Python => format!("__import__(\"os\").environ[\"{}\"]", var),
+    }
+}
+
+/// Replaces secret values in output
+#[derive(Clone, Default)]
+pub struct Redactor {
+    secrets: Arc<Vec<Vec<u8>>>,
+}
+
+impl Redactor {
+    /// Create a redactor for the given secrets (empty values are ignored)
+    pub fn new<'a>(secrets: impl IntoIterator<Item = &'a SecretValue>) -> Self {
+        let mut secrets: Vec<Vec<u8>> = secrets
+            .into_iter()
+            .filter(|s| !s.expose().is_empty())
+            .map(|s| s.expose().as_bytes().to_vec())
+            .collect();
+        // Longest first, so a secret containing another is replaced whole
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        Self {
+            secrets: Arc::new(secrets),
+        }
+    }
+
+    /// Whether there is anything to redact
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    /// Bytes to hold back at the end of a stream so a secret split across
+    /// two reads is still caught
+    pub fn overlap(&self) -> usize {
+        self.secrets.first().map_or(0, |s| s.len() - 1)
+    }
+
+    /// Replace every secret value in `bytes`
+    pub fn redact(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut data = bytes.to_vec();
+        for secret in self.secrets.iter() {
+            data = replace_all(&data, secret, REDACTED.as_bytes());
+        }
+        data
+    }
+
+    /// Replace every secret value in a string
+    pub fn redact_str(&self, text: &str) -> String {
+        if self.is_empty() {
+            return text.to_string();
+        }
+        String::from_utf8_lossy(&self.redact(text.as_bytes())).to_string()
+    }
+}
+
+fn replace_all(haystack: &[u8], needle: &[u8], replacement: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(needle) {
+            out.extend_from_slice(replacement);
+            i += needle.len();
+        } else {
+            out.push(haystack[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_placeholders_become_env_references() {
+        let variables = BTreeMap::from([("path".to_string(), json!("C:\\Temp"))]);
+        let secrets = BTreeMap::from([("token".to_string(), SecretValue::from("hunter2"))]);
+
+        let script = "Remove-Item {{ path }} -Token {{token}} {{unknown}}";
+        assert_eq!(
+            render_placeholders(script, ScriptType::PowerShell, &variables, &secrets),
+            "Remove-Item $env:RMM_VAR_PATH -Token $env:RMM_SECRET_TOKEN {{unknown}}"
+        );
+        assert_eq!(
+            render_placeholders("echo \"{{path}}\"", ScriptType::Bash, &variables, &secrets),
+            "echo \"${RMM_VAR_PATH}\""
+        );
+
+        let env = environment(&variables, &secrets).unwrap();
+        assert!(env.contains(&("RMM_VAR_PATH".to_string(), "C:\\Temp".to_string())));
+        assert!(env.contains(&("RMM_SECRET_TOKEN".to_string(), "hunter2".to_string())));
+
+        let bad = BTreeMap::from([("LD_PRELOAD=x".to_string(), json!(1))]);
+        assert!(environment(&bad, &BTreeMap::new()).is_err());
+
+        let clashing = BTreeMap::from([
+            ("path".to_string(), json!("a")),
+            ("PATH".to_string(), json!("b")),
+        ]);
+        assert!(environment(&clashing, &BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_cmd_references_use_delayed_expansion() {
+        let variables = BTreeMap::from([("name".to_string(), json!("x & del /q C:\\*"))]);
+
+        assert_eq!(
+            render_placeholders("echo {{name}}", ScriptType::Cmd, &variables, &BTreeMap::new()),
+            "@setlocal EnableDelayedExpansion\r\necho !RMM_VAR_NAME!"
+        );
+        // Scripts without references are left exactly as sent
+        assert_eq!(
+            render_placeholders("echo hi!", ScriptType::Cmd, &variables, &BTreeMap::new()),
+            "echo hi!"
+        );
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let secret = SecretValue::from("hunter2");
+        let redactor = Redactor::new([&secret]);
+        assert_eq!(
+            redactor.redact(b"password is hunter2!"),
+            b"password is [REDACTED]!".to_vec()
+        );
+        assert_eq!(redactor.overlap(), 6);
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+    }
+}
@@ -4,12 +4,68 @@ use std::path::PathBuf;
 /// Default interval for collecting and submitting metrics
 pub const DEFAULT_METRICS_INTERVAL_SECS: u64 = 60;
 
+/// Shortest loop interval accepted from the runtime config (0 would spin)
+pub const MIN_INTERVAL_SECS: u64 = 1;
+
 /// Default interval for heartbeat (lightweight check-in)
 pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 30;
 
 /// Default interval for checking agent status with backend
 pub const DEFAULT_STATUS_CHECK_INTERVAL_SECS: u64 = 60;
 
+/// Default interval for polling the backend for pending commands
+pub const DEFAULT_COMMAND_POLL_INTERVAL_SECS: u64 = 30;
+
+/// Default command timeout when the backend doesn't specify one
+pub const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 300;
+
+/// Default interval for streaming output of running commands (0 disables streaming)
+pub const DEFAULT_COMMAND_OUTPUT_INTERVAL_SECS: u64 = 5;
+
+/// Default cap on each of stdout and stderr in a command result, in bytes
+/// (keeps the combined `output` under the backend's 1,000,000 character limit)
+pub const DEFAULT_COMMAND_OUTPUT_LIMIT_BYTES: usize = 400_000;
+
+/// Largest per-stream cap accepted from the runtime config; both streams
+/// plus the truncation markers must fit in the backend's 1,000,000 characters
+pub const MAX_COMMAND_OUTPUT_LIMIT_BYTES: usize = 490_000;
+
+/// Default interval for checking whether a running command was cancelled (0 disables)
+pub const DEFAULT_COMMAND_CANCEL_CHECK_INTERVAL_SECS: u64 = 10;
+
+/// Default number of metrics samples sent per batch once the backend supports batching
+pub const DEFAULT_METRICS_BATCH_SIZE: usize = 10;
+
+/// Default longest a metrics sample waits for its batch to fill (5 minutes)
+pub const DEFAULT_METRICS_BATCH_INTERVAL_SECS: u64 = 300;
+
+/// Default `Content-Encoding` for large request bodies ("gzip", "zstd" or "none")
+pub const DEFAULT_REQUEST_COMPRESSION: &str = "gzip";
+
+/// Default smallest request body worth compressing, in bytes
+pub const DEFAULT_REQUEST_COMPRESSION_THRESHOLD_BYTES: usize = 1024;
+
+/// Default age after which unsent metrics samples are dropped (24 hours)
+pub const DEFAULT_METRICS_QUEUE_MAX_AGE_SECS: u64 = 86400;
+
+/// Default number of unsent metrics samples kept on disk (24 hours at the default interval)
+pub const DEFAULT_METRICS_QUEUE_MAX_SAMPLES: usize = 1440;
+
+/// Default cap on the disk used by unsent metrics samples (64 MiB)
+pub const DEFAULT_METRICS_QUEUE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Default cap on files moved by `fetch_file` and `put_file` (1 GiB)
+pub const DEFAULT_FILE_TRANSFER_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Default idle timeout for interactive shell sessions (15 minutes)
+pub const DEFAULT_SHELL_IDLE_TIMEOUT_SECS: u64 = 900;
+
+/// Default maximum length of an interactive shell session (4 hours)
+pub const DEFAULT_SHELL_MAX_DURATION_SECS: u64 = 14400;
+
+/// Account non-admin scripts run as on Unix when the agent runs as root
+pub const DEFAULT_SCRIPT_USER: &str = "nobody";
+
 /// Default interval for polling enrollment status during device approval
 pub const DEFAULT_ENROLLMENT_POLL_INTERVAL_SECS: u64 = 30;
 
@@ -19,6 +75,16 @@ pub const DEFAULT_UPDATE_CHECK_INTERVAL_SECS: u64 = 86400;
 /// Default Netdata API base URL
 pub const DEFAULT_NETDATA_URL: &str = "http://127.0.0.1:19999";
 
+/// Default Netdata contexts collected with each metrics sample
+pub const DEFAULT_NETDATA_CONTEXTS: &[&str] = &[
+    "system.cpu",
+    "system.ram",
+    "system.load",
+    "system.uptime",
+    "disk.space",
+    "system.net",
+];
+
 /// Default base URL placeholder (replaced at build time)
 pub const DEFAULT_BASE_URL: &str = "https://rmm.benjh.com";
 
@@ -39,20 +105,82 @@ pub struct Config {
     pub key_file: PathBuf,
     /// Path to log file
     pub log_file: PathBuf,
+    /// Path to the command journal (in-flight commands and unsent results)
+    pub command_journal_file: PathBuf,
+    /// Path to the local execution policy (never written by the agent)
+    pub policy_file: PathBuf,
+    /// Directory holding metrics samples the backend hasn't received yet
+    pub metrics_queue_dir: PathBuf,
+    /// Path to the config block last received from the server
+    pub server_config_file: PathBuf,
     /// Metrics collection interval in seconds
     pub metrics_interval: u64,
+    /// Compression for large request bodies ("gzip", "zstd" or "none"),
+    /// used only once the backend accepts it
+    pub request_compression: String,
+    /// Smallest request body compressed, in bytes
+    pub request_compression_threshold: usize,
+    /// Samples per batched metrics submission (1 disables batching)
+    pub metrics_batch_size: usize,
+    /// Longest a sample waits for its batch to fill, in seconds
+    pub metrics_batch_interval: u64,
+    /// Age in seconds after which unsent metrics samples are dropped
+    pub metrics_queue_max_age: u64,
+    /// Maximum number of unsent metrics samples kept
+    pub metrics_queue_max_samples: usize,
+    /// Maximum bytes of unsent metrics samples kept
+    pub metrics_queue_max_bytes: u64,
     /// Heartbeat interval in seconds
     pub heartbeat_interval: u64,
     /// Status check interval in seconds
+    #[allow(dead_code)]
     pub status_check_interval: u64,
     /// Enrollment poll interval in seconds
     pub enrollment_poll_interval: u64,
+    /// Pending command poll interval in seconds
+    pub command_poll_interval: u64,
+    /// Default command timeout in seconds
+    pub command_timeout: u64,
+    /// Interval for streaming output of running commands in seconds (0 disables)
+    pub command_output_interval: u64,
+    /// Maximum bytes of stdout and of stderr kept in a command result
+    pub command_output_limit: usize,
+    /// Upload the full gzipped output when a result had to be truncated
+    pub command_output_attachments: bool,
+    /// Interval for checking running commands for cancellation in seconds (0 disables)
+    pub command_cancel_check_interval: u64,
+    /// Largest file `fetch_file` and `put_file` will move, in bytes
+    pub file_transfer_max_bytes: u64,
+    /// Seconds without input before a shell session is closed
+    pub shell_idle_timeout: u64,
+    /// Maximum length of a shell session in seconds
+    pub shell_max_duration: u64,
+    /// Keep a push channel open for instant command delivery
+    pub push_enabled: bool,
     /// Update check interval in seconds
     pub update_check_interval: u64,
     /// Skip automatic updates
     pub skip_updates: bool,
     /// Netdata API base URL
     pub netdata_url: String,
+    /// Netdata contexts collected with each metrics sample
+    pub netdata_contexts: Vec<String>,
+    /// Pinned Ed25519 public key (base64) that command signatures must match
+    pub command_signing_key: Option<String>,
+    /// Unprivileged account for scripts that don't require admin (Unix only)
+    pub script_user: String,
+    /// HTTP(S) proxy for backend, update and WebSocket traffic
+    pub proxy_url: Option<String>,
+    /// Proxy basic auth username
+    pub proxy_username: Option<String>,
+    /// Proxy basic auth password
+    pub proxy_password: Option<String>,
+    /// Hosts, domains and CIDR ranges that bypass the proxy
+    pub no_proxy: Vec<String>,
+    /// PEM file of CA certificates trusted in addition to the bundled roots
+    pub ca_bundle_file: Option<PathBuf>,
+    /// `sha256/<base64>` public key pins the backend's certificate chain must match
+    pub pinned_public_keys: Vec<String>,
 }
 
 impl Default for Config {
@@ -73,25 +201,61 @@ impl Default for Config {
 
         let key_file = data_dir.join("agent.key");
         let log_file = data_dir.join("agent.log");
+        // Kept out of `commands/`, which unprivileged scripts can traverse
+        let command_journal_file = data_dir.join("command-journal.json");
+        let policy_file = data_dir.join("policy.json");
+        let metrics_queue_dir = data_dir.join("metrics-queue");
+        let server_config_file = data_dir.join("server-config.json");
 
         Self {
             base_url: DEFAULT_BASE_URL.to_string(),
             data_dir,
             key_file,
             log_file,
+            command_journal_file,
+            policy_file,
+            metrics_queue_dir,
+            server_config_file,
             metrics_interval: DEFAULT_METRICS_INTERVAL_SECS,
+            request_compression: DEFAULT_REQUEST_COMPRESSION.to_string(),
+            request_compression_threshold: DEFAULT_REQUEST_COMPRESSION_THRESHOLD_BYTES,
+            metrics_batch_size: DEFAULT_METRICS_BATCH_SIZE,
+            metrics_batch_interval: DEFAULT_METRICS_BATCH_INTERVAL_SECS,
+            metrics_queue_max_age: DEFAULT_METRICS_QUEUE_MAX_AGE_SECS,
+            metrics_queue_max_samples: DEFAULT_METRICS_QUEUE_MAX_SAMPLES,
+            metrics_queue_max_bytes: DEFAULT_METRICS_QUEUE_MAX_BYTES,
             heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL_SECS,
             status_check_interval: DEFAULT_STATUS_CHECK_INTERVAL_SECS,
             enrollment_poll_interval: DEFAULT_ENROLLMENT_POLL_INTERVAL_SECS,
+            command_poll_interval: DEFAULT_COMMAND_POLL_INTERVAL_SECS,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT_SECS,
+            command_output_interval: DEFAULT_COMMAND_OUTPUT_INTERVAL_SECS,
+            command_output_limit: DEFAULT_COMMAND_OUTPUT_LIMIT_BYTES,
+            command_output_attachments: false,
+            command_cancel_check_interval: DEFAULT_COMMAND_CANCEL_CHECK_INTERVAL_SECS,
+            file_transfer_max_bytes: DEFAULT_FILE_TRANSFER_MAX_BYTES,
+            shell_idle_timeout: DEFAULT_SHELL_IDLE_TIMEOUT_SECS,
+            shell_max_duration: DEFAULT_SHELL_MAX_DURATION_SECS,
+            push_enabled: false,
             update_check_interval: DEFAULT_UPDATE_CHECK_INTERVAL_SECS,
             skip_updates: false,
             netdata_url: DEFAULT_NETDATA_URL.to_string(),
+            netdata_contexts: DEFAULT_NETDATA_CONTEXTS.iter().map(|c| c.to_string()).collect(),
+            command_signing_key: None,
+            script_user: DEFAULT_SCRIPT_USER.to_string(),
+            proxy_url: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: Vec::new(),
+            ca_bundle_file: None,
+            pinned_public_keys: Vec::new(),
         }
     }
 }
 
 impl Config {
     /// Create a new configuration with custom base URL
+    #[allow(dead_code)]
     pub fn new(base_url: String) -> Self {
         Self {
             base_url,
@@ -107,6 +271,34 @@ impl Config {
         config.base_url = runtime.effective_server_url(&config.base_url);
         config.netdata_url = runtime.effective_netdata_url(&config.netdata_url);
         config.metrics_interval = runtime.effective_metrics_interval(config.metrics_interval);
+        config.request_compression = runtime.effective_request_compression(&config.request_compression);
+        config.request_compression_threshold =
+            runtime.effective_request_compression_threshold(config.request_compression_threshold);
+        config.metrics_batch_size = runtime.effective_metrics_batch_size(config.metrics_batch_size);
+        config.metrics_batch_interval =
+            runtime.effective_metrics_batch_interval(config.metrics_batch_interval);
+        config.metrics_queue_max_age =
+            runtime.effective_metrics_queue_max_age(config.metrics_queue_max_age);
+        config.metrics_queue_max_samples =
+            runtime.effective_metrics_queue_max_samples(config.metrics_queue_max_samples);
+        config.metrics_queue_max_bytes =
+            runtime.effective_metrics_queue_max_bytes(config.metrics_queue_max_bytes);
+        config.command_output_interval =
+            runtime.effective_command_output_interval(config.command_output_interval);
+        config.command_output_limit = runtime.effective_command_output_limit(config.command_output_limit);
+        config.command_output_attachments =
+            runtime.effective_command_output_attachments(config.command_output_attachments);
+        config.file_transfer_max_bytes =
+            runtime.effective_file_transfer_max_bytes(config.file_transfer_max_bytes);
+        config.push_enabled = runtime.effective_push_enabled(config.push_enabled);
+        config.command_signing_key = runtime.command_signing_key.clone();
+        config.script_user = runtime.effective_script_user(&config.script_user);
+        config.proxy_url = runtime.proxy_url.clone();
+        config.proxy_username = runtime.proxy_username.clone();
+        config.proxy_password = runtime.proxy_password.clone();
+        config.no_proxy = runtime.effective_no_proxy(&config.no_proxy);
+        config.ca_bundle_file = runtime.ca_bundle_file.as_ref().map(PathBuf::from);
+        config.pinned_public_keys = runtime.effective_pinned_public_keys(&config.pinned_public_keys);
 
         config
     }
@@ -1,18 +1,26 @@
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
+use crate::api::{ApiClient, ApiError, CheckRequest, EnrollRequest};
+use crate::backoff::{retry_after_of, Backoff};
 use crate::config::Config;
+use crate::schedule::Schedule;
 use crate::storage::Storage;
 use crate::sysinfo::SystemInfo;
 
-/// Determine if an HTTP error response indicates rejection (stop retrying) vs temporary failure (retry)
-fn is_rejection_response(status: reqwest::StatusCode, body: &str) -> bool {
+/// First wait after a failed enrollment request
+const ENROLL_RETRY_MIN: Duration = Duration::from_secs(30);
+
+/// Longest wait between enrollment requests or failed status checks
+const ENROLL_RETRY_MAX: Duration = Duration::from_secs(300);
+
+/// Determine if an enrollment error is a rejection (stop retrying) vs temporary failure (retry)
+fn is_rejection_response(error: &ApiError) -> bool {
     // Check for explicit rejection status codes
-    if status == reqwest::StatusCode::FORBIDDEN {
-        let body_lower = body.to_lowercase();
+    if error.status() == Some(reqwest::StatusCode::FORBIDDEN) {
+        let body_lower = error.body().unwrap_or_default().to_lowercase();
         // Look for rejection keywords in the response body
         if body_lower.contains("revoked")
             || body_lower.contains("rejected")
@@ -27,51 +35,21 @@ fn is_rejection_response(status: reqwest::StatusCode, body: &str) -> bool {
     false
 }
 
-/// Enrollment request payload
-#[derive(Debug, Serialize)]
-struct EnrollRequest {
-    hostname: String,
-    os: String,
-    hardware_fingerprint: String,
-    cpu_model: String,
-    cpu_cores: usize,
-    total_ram_bytes: u64,
-}
-
-/// Status check request
-#[derive(Debug, Serialize)]
-struct CheckRequest {
-    hostname: String,
-    hardware_fingerprint: String,
-}
-
-/// Status check response
-#[derive(Debug, Deserialize)]
-struct CheckResponse {
-    status: String,
-    api_key: Option<String>,
-}
-
 /// Enrollment manager
 pub struct EnrollmentManager {
     config: Config,
     storage: Storage,
-    client: reqwest::Client,
+    api: ApiClient,
 }
 
 impl EnrollmentManager {
     /// Create a new enrollment manager
-    pub fn new(config: Config, storage: Storage) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .context("Failed to create HTTP client")?;
-
-        Ok(Self {
+    pub fn new(config: Config, storage: Storage, api: ApiClient) -> Self {
+        Self {
             config,
             storage,
-            client,
-        })
+            api,
+        }
     }
 
     /// Check if device is enrolled (has API key)
@@ -102,7 +80,6 @@ impl EnrollmentManager {
     ) -> Result<()> {
         info!("Enrolling device: {}", system_info.hostname);
 
-        let url = format!("{}/api/enroll", self.config.base_url);
         let payload = EnrollRequest {
             hostname: system_info.hostname.clone(),
             os: format!("{} {}", system_info.os_name, system_info.os_version),
@@ -112,92 +89,38 @@ impl EnrollmentManager {
             total_ram_bytes: system_info.total_ram_bytes,
         };
 
-        // Retry with exponential backoff: 30s, 60s, 120s, 240s, 300s (cap at 5 minutes)
-        let retry_delays = [30, 60, 120, 240, 300];
-        let mut attempt = 0;
+        // Retry with exponential backoff from 30s, capped at 5 minutes
+        let mut backoff = Backoff::new(ENROLL_RETRY_MIN, ENROLL_RETRY_MAX);
 
         loop {
-            debug!("Sending enrollment request to {} (attempt {})", url, attempt + 1);
+            debug!("Sending enrollment request (attempt {})", backoff.failures() + 1);
 
-            let response = match self.client.post(&url).json(&payload).send().await {
-                Ok(resp) => resp,
+            let retry_after = match self.api.enroll(&payload).await {
+                Ok(()) => {
+                    info!("Enrollment request submitted successfully");
+                    return Ok(());
+                }
                 Err(e) => {
-                    warn!("Failed to send enrollment request (network error): {}", e);
-
-                    // Network errors are temporary - retry
-                    if attempt < retry_delays.len() {
-                        let delay = retry_delays[attempt];
-                        warn!("Retrying enrollment in {} seconds...", delay);
-
-                        tokio::select! {
-                            _ = cancellation_token.cancelled() => {
-                                anyhow::bail!("Enrollment cancelled by shutdown signal");
-                            }
-                            _ = tokio::time::sleep(Duration::from_secs(delay)) => {
-                                attempt += 1;
-                                continue;
-                            }
-                        }
-                    } else {
-                        // Max delay reached - keep retrying at 5 minute intervals
-                        let delay = 300;
-                        warn!("Max retry delay reached - retrying every {} seconds", delay);
-
-                        tokio::select! {
-                            _ = cancellation_token.cancelled() => {
-                                anyhow::bail!("Enrollment cancelled by shutdown signal");
-                            }
-                            _ = tokio::time::sleep(Duration::from_secs(delay)) => {
-                                continue;
-                            }
-                        }
+                    // Check if this is a rejection (stop retrying) or temporary failure (retry)
+                    if is_rejection_response(&e) {
+                        warn!("Enrollment rejected by server: {}", e);
+                        anyhow::bail!("Enrollment rejected by server: {}", e.body().unwrap_or_default());
                     }
-                }
-            };
-
-            if response.status().is_success() {
-                info!("Enrollment request submitted successfully");
-                return Ok(());
-            } else {
-                let status = response.status();
-                let body = response.text().await.unwrap_or_default();
 
-                // Check if this is a rejection (stop retrying) or temporary failure (retry)
-                if is_rejection_response(status, &body) {
-                    warn!("Enrollment rejected by server: {} - {}", status, body);
-                    anyhow::bail!("Enrollment rejected by server: {}", body);
+                    warn!("Enrollment failed (temporary): {}", e);
+                    e.retry_after()
                 }
+            };
 
-                warn!("Enrollment failed (temporary): {} - {}", status, body);
-
-                // Temporary failure - retry with backoff
-                if attempt < retry_delays.len() {
-                    let delay = retry_delays[attempt];
-                    warn!("Retrying enrollment in {} seconds...", delay);
+            // Temporary failure - retry with backoff
+            let delay = backoff.fail(retry_after);
+            warn!("Retrying enrollment in {} seconds...", delay.as_secs());
 
-                    tokio::select! {
-                        _ = cancellation_token.cancelled() => {
-                            anyhow::bail!("Enrollment cancelled by shutdown signal");
-                        }
-                        _ = tokio::time::sleep(Duration::from_secs(delay)) => {
-                            attempt += 1;
-                            continue;
-                        }
-                    }
-                } else {
-                    // Max delay reached - keep retrying at 5 minute intervals
-                    let delay = 300;
-                    warn!("Max retry delay reached - retrying every {} seconds", delay);
-
-                    tokio::select! {
-                        _ = cancellation_token.cancelled() => {
-                            anyhow::bail!("Enrollment cancelled by shutdown signal");
-                        }
-                        _ = tokio::time::sleep(Duration::from_secs(delay)) => {
-                            continue;
-                        }
-                    }
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    anyhow::bail!("Enrollment cancelled by shutdown signal");
                 }
+                _ = tokio::time::sleep(delay) => {}
             }
         }
     }
@@ -206,31 +129,18 @@ impl EnrollmentManager {
     pub async fn check_status(&self, system_info: &SystemInfo) -> Result<EnrollmentStatus> {
         debug!("Checking enrollment status");
 
-        let url = format!("{}/api/check", self.config.base_url);
         let payload = CheckRequest {
             hostname: system_info.hostname.clone(),
             hardware_fingerprint: system_info.hardware_fingerprint.clone(),
         };
 
-        let response = self
-            .client
-            .post(&url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to send status check request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            warn!("Status check failed: {} - {}", status, body);
-            anyhow::bail!("Status check failed with status {}: {}", status, body)
-        }
-
-        let check_response: CheckResponse = response
-            .json()
-            .await
-            .context("Failed to parse status check response")?;
+        let check_response = match self.api.check(&payload).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Status check failed: {}", e);
+                return Err(e).context("Status check failed");
+            }
+        };
 
         debug!("Status check response: {:?}", check_response);
 
@@ -269,6 +179,10 @@ impl EnrollmentManager {
         cancellation_token: CancellationToken,
     ) -> Result<()> {
         info!("Waiting for device approval...");
+        let poll_interval = Duration::from_secs(self.config.enrollment_poll_interval);
+        let mut backoff = Backoff::new(poll_interval, ENROLL_RETRY_MAX);
+        let mut ticker =
+            Schedule::new(&system_info.hardware_fingerprint).ticker("status", poll_interval);
 
         loop {
             tokio::select! {
@@ -277,14 +191,17 @@ impl EnrollmentManager {
                     info!("Enrollment polling cancelled - shutting down gracefully");
                     anyhow::bail!("Enrollment cancelled by shutdown signal");
                 }
-                // Wait for the poll interval to elapse
-                _ = tokio::time::sleep(Duration::from_secs(self.config.enrollment_poll_interval)) => {
-                    match self.check_status(system_info).await {
+                // Wait for this device's next tick, or longer after failures
+                _ = tokio::time::sleep(backoff.wait(ticker.remaining())) => {
+                    let status = self.check_status(system_info).await;
+                    ticker.advance();
+                    match status {
                         Ok(EnrollmentStatus::Approved) => {
                             info!("Device approved!");
                             return Ok(());
                         }
                         Ok(EnrollmentStatus::Pending) => {
+                            backoff.succeed();
                             debug!(
                                 "Still pending, waiting {} seconds...",
                                 self.config.enrollment_poll_interval
@@ -294,10 +211,12 @@ impl EnrollmentManager {
                             anyhow::bail!("Device was revoked during enrollment");
                         }
                         Ok(EnrollmentStatus::Unknown(status)) => {
+                            backoff.succeed();
                             warn!("Unknown status '{}', continuing to wait...", status);
                         }
                         Err(e) => {
-                            warn!("Error checking status: {}", e);
+                            let delay = backoff.fail(retry_after_of(&e));
+                            warn!("Error checking status, next check in {}s: {}", delay.as_secs(), e);
                         }
                     }
                 }
@@ -321,6 +240,7 @@ pub enum EnrollmentStatus {
 
 impl EnrollmentStatus {
     /// Get a display string for the status
+    #[allow(dead_code)]
     pub fn as_str(&self) -> &str {
         match self {
             EnrollmentStatus::Approved => "Approved",
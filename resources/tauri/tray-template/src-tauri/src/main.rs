@@ -2,12 +2,20 @@
 // No GUI - runs as a headless service managed via web panel
 
 mod agent;
+mod api;
+mod backoff;
+mod commands;
 mod config;
 mod enrollment;
 mod metrics;
+mod proxy;
+mod push;
 mod runtime_config;
+mod schedule;
+mod server_config;
 mod storage;
 mod sysinfo;
+mod tls;
 mod updater;
 
 use agent::Agent;
@@ -86,6 +94,30 @@ struct Cli {
     #[arg(long)]
     reset: bool,
 
+    /// Pin the Ed25519 public key (base64) that commands must be signed with
+    #[arg(long, value_name = "KEY")]
+    signing_key: Option<String>,
+
+    /// HTTP(S) proxy for outbound traffic, or "none" to connect directly
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Proxy credentials, or "none" to clear them
+    #[arg(long, value_name = "USER:PASS")]
+    proxy_auth: Option<String>,
+
+    /// Comma-separated hosts, domains and CIDR ranges that bypass the proxy
+    #[arg(long, value_name = "HOSTS")]
+    no_proxy: Option<String>,
+
+    /// PEM file of extra CA certificates to trust, or "none" to clear
+    #[arg(long, value_name = "FILE")]
+    ca_bundle: Option<String>,
+
+    /// Comma-separated sha256/<base64> public key pins for the server, or "none" to clear
+    #[arg(long, value_name = "PINS")]
+    pin: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -146,12 +178,12 @@ fn init_logging(config: &Config) -> WorkerGuard {
 
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
-    // Set up subscriber with file output
+    // Set up subscriber with file output; the server can change the level later
     tracing_subscriber::registry()
-        .with(
+        .with(server_config::reloadable_log_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "info,reqwest=warn,hyper=warn".into()),
-        )
+        ))
         .with(tracing_subscriber::fmt::layer().with_writer(non_blocking))
         .init();
 
@@ -490,6 +522,7 @@ fn stop_service_cmd() -> Result<()> {
     Ok(())
 }
 
+#[allow(clippy::unnecessary_unwrap)]
 fn show_status() -> Result<()> {
     init_console_logging();
 
@@ -518,6 +551,49 @@ fn show_status() -> Result<()> {
         println!("Server URL Override: {}", runtime_config.server_url.as_ref().unwrap());
     }
 
+    match &config.command_signing_key {
+        Some(key) => println!(
+            "Command Signing: Enforced ({}, key {})",
+            commands::SIGNATURE_VERSION,
+            key
+        ),
+        None => println!("Command Signing: Unsigned (no key pinned, reported to the backend)"),
+    }
+
+    match proxy::ProxySettings::from_config(&config) {
+        Ok(Some(proxy)) => println!("Proxy: {}", proxy.describe()),
+        Ok(None) => match proxy::describe_environment() {
+            Some(proxy) => println!("Proxy: {}", proxy),
+            None => println!("Proxy: None (direct connections)"),
+        },
+        Err(e) => println!("Proxy: Invalid ({:#})", e),
+    }
+
+    match &config.ca_bundle_file {
+        Some(path) => println!("Extra CA Certificates: {}", path.display()),
+        None => println!("Extra CA Certificates: None (bundled roots only)"),
+    }
+    if config.pinned_public_keys.is_empty() {
+        println!("Certificate Pinning: Not enabled");
+    } else {
+        println!("Certificate Pinning: {} key(s) pinned", config.pinned_public_keys.len());
+    }
+    if let Err(e) = tls::client_config(&config) {
+        println!("TLS Settings: Invalid ({:#})", e);
+    }
+
+    if cfg!(unix) {
+        println!("Non-admin Script Account: {}", config.script_user);
+    }
+
+    match commands::ExecutionPolicy::load(&config.policy_file) {
+        Ok(policy) if policy.is_unrestricted() => {
+            println!("Execution Policy: Unrestricted ({} not present)", config.policy_file.display())
+        }
+        Ok(policy) => println!("Execution Policy: {:?}", policy),
+        Err(e) => println!("Execution Policy: INVALID - all commands refused ({:#})", e),
+    }
+
     Ok(())
 }
 
@@ -683,7 +759,8 @@ fn check_for_updates(check_only: bool) -> Result<()> {
     println!("Current version: {}", AGENT_VERSION);
     println!();
 
-    let updater = updater::Updater::new(config)?;
+    let api = api::ApiClient::new(&config)?;
+    let updater = updater::Updater::new(config, api);
     let rt = tokio::runtime::Runtime::new()?;
 
     rt.block_on(async {
@@ -723,6 +800,7 @@ fn check_for_updates(check_only: bool) -> Result<()> {
     Ok(())
 }
 
+#[allow(clippy::unnecessary_unwrap)]
 fn main() -> Result<()> {
     // Apply any pending updates FIRST, before anything else
     let default_config = Config::default();
@@ -756,6 +834,86 @@ fn main() -> Result<()> {
         }
     }
 
+    // Handle command signing key
+    if let Some(key) = &cli.signing_key {
+        commands::parse_public_key(key).context("Invalid command signing key")?;
+        runtime_config.command_signing_key = Some(key.trim().to_string());
+        runtime_config.save()?;
+        if cli.command.is_none() {
+            println!("Command signing key pinned. Unsigned commands will be rejected.");
+            return Ok(());
+        }
+    }
+
+    // Handle proxy settings
+    if cli.proxy.is_some() || cli.proxy_auth.is_some() || cli.no_proxy.is_some() {
+        if let Some(url) = &cli.proxy {
+            runtime_config.proxy_url = match url.trim() {
+                "none" => None,
+                url => Some(url.to_string()),
+            };
+        }
+        if let Some(auth) = &cli.proxy_auth {
+            let (username, password) = match auth.trim() {
+                "none" => (None, None),
+                auth => {
+                    let (username, password) = auth.split_once(':').unwrap_or((auth, ""));
+                    (Some(username.to_string()), Some(password.to_string()))
+                }
+            };
+            runtime_config.proxy_username = username;
+            runtime_config.proxy_password = password;
+        }
+        if let Some(hosts) = &cli.no_proxy {
+            let hosts: Vec<String> = hosts
+                .split(',')
+                .map(|host| host.trim().to_string())
+                .filter(|host| !host.is_empty())
+                .collect();
+            runtime_config.no_proxy = Some(hosts).filter(|hosts| !hosts.is_empty());
+        }
+
+        let proxy = proxy::ProxySettings::from_config(&Config::with_runtime_config(&runtime_config))?;
+        runtime_config.save()?;
+        if cli.command.is_none() {
+            match proxy {
+                Some(proxy) => println!("Proxy set to: {}", proxy.describe()),
+                None => println!("No proxy configured. Connections will be direct."),
+            }
+            return Ok(());
+        }
+    }
+
+    // Handle TLS trust settings
+    if cli.ca_bundle.is_some() || cli.pin.is_some() {
+        if let Some(path) = &cli.ca_bundle {
+            runtime_config.ca_bundle_file = match path.trim() {
+                "none" => None,
+                path => Some(
+                    std::fs::canonicalize(path)
+                        .with_context(|| format!("CA bundle not found: {}", path))?
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+            };
+        }
+        if let Some(pins) = &cli.pin {
+            let pins: Vec<String> = pins
+                .split(',')
+                .map(|pin| pin.trim().to_string())
+                .filter(|pin| !pin.is_empty() && pin != "none")
+                .collect();
+            runtime_config.pinned_public_keys = Some(pins).filter(|pins| !pins.is_empty());
+        }
+
+        tls::client_config(&Config::with_runtime_config(&runtime_config))?;
+        runtime_config.save()?;
+        if cli.command.is_none() {
+            println!("TLS trust settings saved.");
+            return Ok(());
+        }
+    }
+
     // Handle reset flag
     if cli.reset {
         let config = Config::default();
@@ -835,6 +993,9 @@ fn main() -> Result<()> {
                         eprintln!("  rmm update --check   Only check for updates");
                         eprintln!("  rmm --url <URL>      Set server URL");
                         eprintln!("  rmm --reset          Clear API key");
+                        eprintln!("  rmm --signing-key <KEY>  Pin command signing key");
+                        eprintln!("  rmm --proxy <URL>    Set outbound proxy");
+                        eprintln!("  rmm --ca-bundle <FILE>  Trust extra CA certificates");
                     }
                 }
             }
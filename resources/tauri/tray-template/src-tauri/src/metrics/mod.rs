@@ -0,0 +1,554 @@
+//! Simplified metrics collector - forwards raw Netdata JSON to Laravel
+//!
+//! The agent's job is simple:
+//! 1. Fetch raw JSON from Netdata v3 API
+//! 2. Forward it to Laravel
+//! 3. Let Laravel handle all parsing
+//!
+//! Samples the backend doesn't receive are kept in an on-disk queue and
+//! replayed, oldest first, once a submission succeeds again.
+//!
+//! When the heartbeat response advertises `metrics_batch`, samples are
+//! queued as they're collected and sent to `POST /api/metrics/batch`,
+//! `metrics_batch_size` at a time or once the oldest has waited
+//! `metrics_batch_interval`. Otherwise every sample is posted on its own.
+
+mod queue;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use futures_util::future::join_all;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::api::{ApiClient, ApiError, Capabilities, HeartbeatPayload, RawMetricsPayload};
+use crate::backoff::Backoff;
+use crate::config::Config;
+use crate::push::PushSignals;
+use crate::schedule::Schedule;
+use crate::server_config::{LiveSettings, ServerConfigStore};
+use queue::{MetricsQueue, QueueLimits};
+
+/// Heartbeat feature flag for `POST /api/metrics/batch`
+const BATCH_FEATURE: &str = "metrics_batch";
+
+/// Most submissions made from the queue per metrics interval
+const BACKFILL_REQUESTS_PER_TICK: usize = 30;
+
+/// Pause between submissions from the queue, keeping well under the
+/// backend's 120 requests per minute limit on `/api/metrics`
+const BACKFILL_PAUSE: Duration = Duration::from_millis(500);
+
+/// Longest wait between submissions while the backend is failing
+const SUBMIT_BACKOFF_MAX: Duration = Duration::from_secs(900);
+
+/// Longest wait between heartbeats while the backend is failing
+const HEARTBEAT_BACKOFF_MAX: Duration = Duration::from_secs(600);
+
+// ============================================================================
+// Metrics Collector
+// ============================================================================
+
+/// Simple metrics collector - fetches from Netdata and forwards to Laravel
+pub struct MetricsCollector {
+    config: Config,
+    /// Direct client for the local Netdata agent
+    netdata: reqwest::Client,
+    api: ApiClient,
+    /// Samples waiting for their batch or for the backend to be reachable
+    queue: MetricsQueue,
+    /// Whether the last heartbeat advertised batch submission
+    batch_supported: AtomicBool,
+    /// Holds submissions back after failures; samples are queued meanwhile
+    backoff: Mutex<Backoff>,
+    hostname: String,
+}
+
+impl MetricsCollector {
+    /// Create a new metrics collector
+    pub fn new(config: Config, hostname: String, api: ApiClient) -> Result<Self> {
+        // Netdata is local, so never send it through a proxy
+        let netdata = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .no_proxy()
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let queue = MetricsQueue::new(&config.metrics_queue_dir, QueueLimits::from_config(&config));
+        let backoff = Backoff::new(Duration::from_secs(config.metrics_interval), SUBMIT_BACKOFF_MAX);
+
+        Ok(Self {
+            config,
+            netdata,
+            api,
+            queue,
+            batch_supported: AtomicBool::new(false),
+            backoff: Mutex::new(backoff),
+            hostname,
+        })
+    }
+
+    /// Fetch raw JSON from Netdata v3 API (no parsing)
+    async fn fetch_netdata_info(&self) -> Option<serde_json::Value> {
+        let url = format!("{}/api/v3/info", self.config.netdata_url);
+        debug!("Fetching Netdata info from: {}", url);
+
+        match self.netdata.get(&url).send().await {
+            Ok(response) if response.status().is_success() => {
+                response.json().await.ok()
+            }
+            Ok(response) => {
+                debug!("Netdata info request failed: {}", response.status());
+                None
+            }
+            Err(e) => {
+                debug!("Netdata info request error: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Fetch raw data from a Netdata v3 API context (no parsing)
+    async fn fetch_netdata_context(&self, context: &str) -> Option<serde_json::Value> {
+        let url = format!(
+            "{}/api/v3/data?contexts={}&format=json&points=1&time_group=average",
+            self.config.netdata_url, context
+        );
+        debug!("Fetching Netdata {} from: {}", context, url);
+
+        match self.netdata.get(&url).send().await {
+            Ok(response) if response.status().is_success() => {
+                response.json().await.ok()
+            }
+            Ok(response) => {
+                debug!("Netdata {} request failed: {}", context, response.status());
+                None
+            }
+            Err(e) => {
+                debug!("Netdata {} request error: {}", context, e);
+                None
+            }
+        }
+    }
+
+    /// Collect raw metrics from Netdata
+    ///
+    /// The six standard contexts fill their own payload fields; any other
+    /// context goes into `netdata_extra` under its name.
+    pub async fn collect_metrics(&self, contexts: &[String]) -> RawMetricsPayload {
+        debug!("Collecting raw metrics from Netdata");
+
+        // Fetch all contexts in parallel
+        let (netdata_info, data) = tokio::join!(
+            self.fetch_netdata_info(),
+            join_all(contexts.iter().map(|c| self.fetch_netdata_context(c))),
+        );
+
+        let mut payload = RawMetricsPayload {
+            hostname: self.hostname.clone(),
+            timestamp: Utc::now().to_rfc3339(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            netdata_info,
+            ..Default::default()
+        };
+
+        for (context, data) in contexts.iter().zip(data) {
+            let Some(data) = data else { continue };
+            match context.as_str() {
+                "system.cpu" => payload.netdata_cpu = Some(data),
+                "system.ram" => payload.netdata_ram = Some(data),
+                "system.load" => payload.netdata_load = Some(data),
+                "system.uptime" => payload.netdata_uptime = Some(data),
+                "disk.space" => payload.netdata_disk = Some(data),
+                "system.net" => payload.netdata_net = Some(data),
+                other => {
+                    payload.netdata_extra.insert(other.to_string(), data);
+                }
+            }
+        }
+
+        payload
+    }
+
+    /// Submit raw metrics to Laravel backend
+    pub async fn submit_metrics(&self, metrics: &RawMetricsPayload, api_key: &str) -> Result<()> {
+        debug!("Submitting metrics to backend");
+
+        if let Err(e) = self.api.submit_metrics(api_key, metrics).await {
+            warn!("Metrics submission failed: {}", e);
+            return Err(e).context("Failed to submit metrics to backend");
+        }
+
+        debug!("Metrics submitted successfully");
+        Ok(())
+    }
+
+    /// Whether samples are currently sent in batches
+    fn batching(&self) -> bool {
+        self.config.metrics_batch_size > 1 && self.batch_supported.load(Ordering::Relaxed)
+    }
+
+    /// Record whether the backend supports batches, as told by a heartbeat
+    fn set_batch_supported(&self, supported: bool) {
+        if self.batch_supported.swap(supported, Ordering::Relaxed) != supported {
+            info!(
+                "Backend {} batched metrics",
+                if supported { "supports" } else { "no longer supports" }
+            );
+        }
+    }
+
+    /// Whether enough samples are queued, or the oldest has waited long enough, to send a batch
+    async fn batch_due(&self) -> bool {
+        if self.queue.len().await >= self.config.metrics_batch_size {
+            return true;
+        }
+        match self.queue.oldest_collected_at().await {
+            Some(collected) => {
+                let waited = Utc::now().signed_duration_since(collected);
+                waited.num_seconds() >= self.config.metrics_batch_interval as i64
+            }
+            None => false,
+        }
+    }
+
+    /// Record a failed submission, backing off for longer each time
+    fn submission_failed(&self, error: &ApiError) {
+        let mut backoff = self.backoff.lock().unwrap();
+        let delay = backoff.fail(error.retry_after());
+        warn!("Holding metrics submissions for {}s: {}", delay.as_secs(), error);
+    }
+
+    /// Collect and submit metrics in one operation
+    ///
+    /// A sample the backend couldn't take is queued for later; after a
+    /// successful submission queued samples are replayed. In batch mode
+    /// every sample is queued and the queue is sent once a batch is due.
+    /// While backing off after a failure, samples are only queued.
+    pub async fn collect_and_submit(
+        &self,
+        api_key: &str,
+        contexts: &[String],
+        shutdown: &CancellationToken,
+    ) -> Result<()> {
+        let metrics = self.collect_metrics(contexts).await;
+
+        if self.backoff.lock().unwrap().is_waiting() {
+            debug!("Backing off, queueing metrics sample");
+            if let Err(e) = self.queue.push(&metrics).await {
+                warn!("Failed to queue metrics sample: {}", e);
+            }
+            return Ok(());
+        }
+
+        if self.batching() {
+            match self.queue.push(&metrics).await {
+                Ok(()) => {
+                    if self.batch_due().await {
+                        self.flush_queue(api_key, shutdown).await;
+                    }
+                    return Ok(());
+                }
+                Err(e) => warn!("Failed to queue metrics sample, sending it alone: {}", e),
+            }
+        }
+
+        match self.api.submit_metrics(api_key, &metrics).await {
+            Ok(()) => {
+                self.backoff.lock().unwrap().succeed();
+                if metrics.netdata_cpu.is_some() || metrics.netdata_ram.is_some() {
+                    info!("Metrics submitted (raw Netdata data)");
+                } else {
+                    warn!("Metrics submitted with no Netdata data (Netdata may be unavailable)");
+                }
+                self.flush_queue(api_key, shutdown).await;
+            }
+            Err(e) if worth_keeping(&e) => {
+                warn!("Failed to submit metrics, queueing for later: {}", e);
+                self.submission_failed(&e);
+                if let Err(e) = self.queue.push(&metrics).await {
+                    warn!("Failed to queue metrics sample: {}", e);
+                }
+            }
+            Err(e) => {
+                // The backend will never take this sample - don't keep it
+                warn!("Metrics sample rejected: {}", e);
+            }
+        }
+
+        Ok(()) // Don't propagate - retry next interval
+    }
+
+    /// Send queued samples oldest first, in batches when the backend
+    /// supports them, stopping at the first failure
+    async fn flush_queue(&self, api_key: &str, shutdown: &CancellationToken) {
+        let mut sent = 0;
+
+        for _ in 0..BACKFILL_REQUESTS_PER_TICK {
+            let batching = self.batching();
+            let count = if batching { self.config.metrics_batch_size } else { 1 };
+            let samples = self.queue.oldest(count).await;
+            if samples.is_empty() {
+                break;
+            }
+
+            let payloads: Vec<&RawMetricsPayload> = samples.iter().map(|s| &s.payload).collect();
+            let result = if batching {
+                self.api.submit_metrics_batch(api_key, &payloads).await
+            } else {
+                self.api.submit_metrics(api_key, payloads[0]).await
+            };
+
+            match result {
+                Ok(()) => {
+                    self.backoff.lock().unwrap().succeed();
+                    for sample in &samples {
+                        self.queue.remove(sample).await;
+                    }
+                    sent += samples.len();
+                }
+                Err(ApiError::Rejected { status, .. })
+                    if batching
+                        && (status == reqwest::StatusCode::NOT_FOUND
+                            || status == reqwest::StatusCode::METHOD_NOT_ALLOWED) =>
+                {
+                    // Advertised but not routed - send one at a time instead
+                    warn!("Batch endpoint returned {}, falling back to single submissions", status);
+                    self.set_batch_supported(false);
+                    continue;
+                }
+                Err(e) if worth_keeping(&e) => {
+                    debug!("Stopped sending queued metrics: {}", e);
+                    self.submission_failed(&e);
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "Dropping {} queued metrics sample(s) from {}: {}",
+                        samples.len(),
+                        samples[0].payload.timestamp,
+                        e
+                    );
+                    for sample in &samples {
+                        self.queue.remove(sample).await;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(BACKFILL_PAUSE) => {}
+            }
+        }
+
+        if sent > 0 {
+            info!(
+                "Sent {} queued metrics sample(s), {} remaining",
+                sent,
+                self.queue.len().await
+            );
+        }
+    }
+
+    /// Check if Netdata is available
+    pub async fn check_netdata_available(&self) -> bool {
+        let url = format!("{}/api/v3/info", self.config.netdata_url);
+
+        match self.netdata.get(&url).send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("Netdata is available");
+                true
+            }
+            Ok(response) => {
+                warn!("Netdata returned status: {}", response.status());
+                false
+            }
+            Err(e) => {
+                debug!("Netdata is not available: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Start metrics collection loop
+    ///
+    /// The interval and Netdata contexts follow `settings`, so a new server
+    /// config takes effect without a restart.
+    pub async fn start_metrics_loop(
+        &self,
+        api_key: String,
+        schedule: &Schedule,
+        mut settings: watch::Receiver<LiveSettings>,
+        cancellation_token: CancellationToken,
+    ) {
+        info!(
+            "Starting metrics collection loop (interval: {}s)",
+            settings.borrow().metrics_interval
+        );
+
+        if !self.check_netdata_available().await {
+            warn!("Netdata is not available at startup - metrics will be limited");
+        }
+
+        let interval = Duration::from_secs(settings.borrow().metrics_interval);
+        let mut ticker = schedule.ticker("metrics", interval);
+
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    info!("Metrics collection loop cancelled");
+                    break;
+                }
+                Ok(()) = settings.changed() => {
+                    let interval = settings.borrow().metrics_interval;
+                    debug!("Metrics interval is now {}s", interval);
+                    ticker.set_interval(Duration::from_secs(interval));
+                }
+                _ = tokio::time::sleep(ticker.remaining()) => {
+                    let contexts = settings.borrow().netdata_contexts.clone();
+                    if let Err(e) = self.collect_and_submit(&api_key, &contexts, &cancellation_token).await {
+                        error!("Error in metrics collection: {}", e);
+                    }
+                    ticker.advance();
+                }
+            }
+        }
+
+        info!("Metrics collection loop stopped");
+    }
+
+    /// Send a lightweight heartbeat to the backend
+    ///
+    /// Reports the server config version in effect and applies any new
+    /// config block the response carries.
+    pub async fn send_heartbeat(
+        &self,
+        api_key: &str,
+        server_config: &ServerConfigStore,
+    ) -> Result<(), ApiError> {
+        debug!("Sending heartbeat");
+
+        let payload = HeartbeatPayload {
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            capabilities: Capabilities {
+                interpreters: crate::commands::interpreter_capabilities().await,
+                command_signing: if self.config.command_signing_key.is_some() {
+                    "enforced"
+                } else {
+                    "unsigned"
+                },
+                signature_version: crate::commands::SIGNATURE_VERSION,
+            },
+            config_version: server_config.running_version(),
+        };
+
+        let response = self.api.heartbeat(api_key, &payload).await?;
+        debug!(
+            "Heartbeat {} (server time {})",
+            response.status.as_deref().unwrap_or("OK"),
+            response.server_time.as_deref().unwrap_or("unknown")
+        );
+        self.set_batch_supported(response.supports(BATCH_FEATURE));
+        match response.server_config() {
+            Some(Ok(block)) => {
+                if let Err(e) = server_config.apply(&block) {
+                    warn!("{:#}", e);
+                }
+            }
+            Some(Err(e)) => warn!("Ignoring malformed server config: {}", e),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Start heartbeat loop
+    ///
+    /// A `config_changed` push event sends a heartbeat at once.
+    pub async fn start_heartbeat_loop(
+        &self,
+        api_key: String,
+        schedule: &Schedule,
+        push: &PushSignals,
+        server_config: &ServerConfigStore,
+        cancellation_token: CancellationToken,
+    ) {
+        let mut settings = server_config.subscribe();
+        info!(
+            "Starting heartbeat loop (interval: {}s)",
+            settings.borrow().heartbeat_interval
+        );
+        let interval = Duration::from_secs(settings.borrow().heartbeat_interval);
+        let mut backoff = Backoff::new(interval, HEARTBEAT_BACKOFF_MAX);
+        let mut ticker = schedule.ticker("heartbeat", interval);
+
+        loop {
+            let scheduled = tokio::select! {
+                _ = cancellation_token.cancelled() => {
+                    info!("Heartbeat loop cancelled");
+                    break;
+                }
+                Ok(()) = settings.changed() => {
+                    ticker.set_interval(Duration::from_secs(settings.borrow().heartbeat_interval));
+                    continue;
+                }
+                _ = push.config_changed.notified() => false,
+                _ = tokio::time::sleep(backoff.wait(ticker.remaining())) => true,
+            };
+
+            match self.send_heartbeat(&api_key, server_config).await {
+                Ok(()) => backoff.succeed(),
+                Err(e) => {
+                    let delay = backoff.fail(e.retry_after());
+                    warn!("Heartbeat failed, next in {}s: {}", delay.as_secs(), e);
+                }
+            }
+            if scheduled {
+                ticker.advance();
+            }
+        }
+
+        info!("Heartbeat loop stopped");
+    }
+}
+
+/// Whether a sample that failed with `error` may still be accepted later
+fn worth_keeping(error: &ApiError) -> bool {
+    error.is_transient()
+        || matches!(
+            error,
+            ApiError::Unauthorized { .. } | ApiError::PinMismatch { .. }
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_batching_needs_backend_support_and_a_full_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            metrics_queue_dir: dir.path().to_path_buf(),
+            metrics_batch_size: 2,
+            ..Default::default()
+        };
+        let api = ApiClient::new(&config).unwrap();
+        let collector = MetricsCollector::new(config, "test-host".to_string(), api).unwrap();
+
+        assert!(!collector.batching());
+        collector.set_batch_supported(true);
+        assert!(collector.batching());
+
+        let mut sample = collector.collect_metrics(&[]).await;
+        collector.queue.push(&sample).await.unwrap();
+        assert!(!collector.batch_due().await);
+
+        sample.timestamp = Utc::now().to_rfc3339();
+        collector.queue.push(&sample).await.unwrap();
+        assert!(collector.batch_due().await);
+    }
+}
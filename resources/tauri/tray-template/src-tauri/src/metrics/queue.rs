@@ -0,0 +1,295 @@
+//! Offline metrics queue
+//!
+//! Samples the backend couldn't take are written to `metrics_queue_dir`, one
+//! JSON file per sample, named after the collection time so a sorted listing
+//! is oldest first. The queue is bounded by age, sample count and total
+//! bytes; when a limit is hit the oldest samples are dropped. Replayed
+//! samples keep their original `timestamp`, so the backend files them where
+//! they belong.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::api::RawMetricsPayload;
+use crate::config::Config;
+
+/// How much the queue may hold before the oldest samples are dropped
+#[derive(Debug, Clone, Copy)]
+pub struct QueueLimits {
+    pub max_age: Duration,
+    pub max_samples: usize,
+    pub max_bytes: u64,
+}
+
+impl QueueLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_age: Duration::from_secs(config.metrics_queue_max_age),
+            max_samples: config.metrics_queue_max_samples,
+            max_bytes: config.metrics_queue_max_bytes,
+        }
+    }
+}
+
+/// A queued sample waiting to be replayed
+pub struct QueuedSample {
+    path: PathBuf,
+    pub payload: RawMetricsPayload,
+}
+
+/// A sample file on disk
+struct Entry {
+    path: PathBuf,
+    collected_ms: i64,
+    size: u64,
+}
+
+/// Bounded on-disk queue of metrics samples
+pub struct MetricsQueue {
+    dir: PathBuf,
+    limits: QueueLimits,
+    /// Serialises pushes and pops so pruning never races a replay
+    lock: Mutex<()>,
+}
+
+impl MetricsQueue {
+    pub fn new(dir: impl AsRef<Path>, limits: QueueLimits) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            limits,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Store a sample for later, dropping the oldest if the queue is full
+    pub async fn push(&self, payload: &RawMetricsPayload) -> Result<()> {
+        let _guard = self.lock.lock().await;
+
+        fs::create_dir_all(&self.dir)
+            .await
+            .context("Failed to create metrics queue directory")?;
+
+        let content = serde_json::to_vec(payload).context("Failed to serialize metrics sample")?;
+        let collected_ms = DateTime::parse_from_rfc3339(&payload.timestamp)
+            .map(|t| t.timestamp_millis())
+            .unwrap_or_else(|_| Utc::now().timestamp_millis());
+
+        let path = self.free_path(collected_ms).await;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, content)
+            .await
+            .context("Failed to write queued metrics sample")?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .context("Failed to store queued metrics sample")?;
+
+        debug!("Queued metrics sample {:?}", path);
+        self.prune().await;
+        Ok(())
+    }
+
+    /// Up to `count` of the oldest samples still within the limits
+    ///
+    /// Unreadable samples are deleted rather than blocking the queue.
+    pub async fn oldest(&self, count: usize) -> Vec<QueuedSample> {
+        let _guard = self.lock.lock().await;
+        self.prune().await;
+
+        let mut samples = Vec::new();
+        for entry in self.entries().await {
+            if samples.len() >= count {
+                break;
+            }
+
+            let parsed = fs::read(&entry.path)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?));
+
+            match parsed {
+                Ok(payload) => samples.push(QueuedSample {
+                    path: entry.path,
+                    payload,
+                }),
+                Err(e) => {
+                    warn!("Dropping unreadable queued sample {:?}: {}", entry.path, e);
+                    let _ = fs::remove_file(&entry.path).await;
+                }
+            }
+        }
+
+        samples
+    }
+
+    /// Remove a sample once the backend is done with it
+    pub async fn remove(&self, sample: &QueuedSample) {
+        let _guard = self.lock.lock().await;
+        if let Err(e) = fs::remove_file(&sample.path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove queued sample {:?}: {}", sample.path, e);
+            }
+        }
+    }
+
+    /// Number of queued samples
+    pub async fn len(&self) -> usize {
+        self.entries().await.len()
+    }
+
+    /// When the oldest queued sample was collected
+    pub async fn oldest_collected_at(&self) -> Option<DateTime<Utc>> {
+        let entry = self.entries().await.into_iter().next()?;
+        DateTime::from_timestamp_millis(entry.collected_ms)
+    }
+
+    /// A file name for a new sample that doesn't clash with a queued one
+    async fn free_path(&self, collected_ms: i64) -> PathBuf {
+        let mut sequence = 0;
+        loop {
+            let path = self
+                .dir
+                .join(format!("{:013}-{:04}.json", collected_ms, sequence));
+            if !fs::try_exists(&path).await.unwrap_or(false) {
+                return path;
+            }
+            sequence += 1;
+        }
+    }
+
+    /// Queued samples, oldest first
+    async fn entries(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut dir = match fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(_) => return entries,
+        };
+
+        while let Ok(Some(item)) = dir.next_entry().await {
+            let path = item.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let collected_ms = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.split('-').next())
+                .and_then(|s| s.parse().ok());
+            let (Some(collected_ms), Ok(metadata)) = (collected_ms, item.metadata().await) else {
+                continue;
+            };
+            entries.push(Entry {
+                path,
+                collected_ms,
+                size: metadata.len(),
+            });
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// Drop samples that are too old, then the oldest until count and size fit
+    async fn prune(&self) {
+        let entries = self.entries().await;
+        let cutoff = Utc::now().timestamp_millis() - self.limits.max_age.as_millis() as i64;
+
+        let mut count = entries.len();
+        let mut bytes: u64 = entries.iter().map(|e| e.size).sum();
+        let mut dropped = 0;
+
+        for entry in &entries {
+            let expired = entry.collected_ms < cutoff;
+            if !expired && count <= self.limits.max_samples && bytes <= self.limits.max_bytes {
+                break;
+            }
+            if let Err(e) = fs::remove_file(&entry.path).await {
+                warn!("Failed to drop queued sample {:?}: {}", entry.path, e);
+                continue;
+            }
+            count -= 1;
+            bytes -= entry.size;
+            dropped += 1;
+        }
+
+        if dropped > 0 {
+            warn!(
+                "Dropped {} queued metrics sample(s) over the queue limits",
+                dropped
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: String) -> RawMetricsPayload {
+        RawMetricsPayload {
+            hostname: "test-host".to_string(),
+            timestamp,
+            agent_version: "0.3.1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn minutes_ago(minutes: i64) -> String {
+        (Utc::now() - chrono::Duration::minutes(minutes)).to_rfc3339()
+    }
+
+    #[tokio::test]
+    async fn test_queue_replays_oldest_first_with_original_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let limits = QueueLimits {
+            max_age: Duration::from_secs(3600),
+            max_samples: 10,
+            max_bytes: 1024 * 1024,
+        };
+        let queue = MetricsQueue::new(dir.path(), limits);
+
+        let first = minutes_ago(5);
+        queue.push(&sample(minutes_ago(2))).await.unwrap();
+        queue.push(&sample(first.clone())).await.unwrap();
+        assert_eq!(queue.len().await, 2);
+
+        // A restarted agent sees the same queue; a damaged sample is skipped
+        let damaged = Utc::now().timestamp_millis() - 10 * 60 * 1000;
+        std::fs::write(dir.path().join(format!("{:013}-0000.json", damaged)), "{").unwrap();
+        let queue = MetricsQueue::new(dir.path(), limits);
+        let oldest = queue.oldest(1).await;
+        assert_eq!(oldest.len(), 1);
+        assert_eq!(oldest[0].payload.timestamp, first);
+        queue.remove(&oldest[0]).await;
+        assert_eq!(queue.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_queue_enforces_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = MetricsQueue::new(
+            dir.path(),
+            QueueLimits {
+                max_age: Duration::from_secs(3600),
+                max_samples: 2,
+                max_bytes: 1024 * 1024,
+            },
+        );
+
+        queue.push(&sample(minutes_ago(120))).await.unwrap();
+        assert_eq!(queue.len().await, 0, "samples past max_age are dropped");
+
+        let newest = minutes_ago(1);
+        queue.push(&sample(minutes_ago(3))).await.unwrap();
+        queue.push(&sample(newest.clone())).await.unwrap();
+        queue.push(&sample(minutes_ago(2))).await.unwrap();
+        assert_eq!(queue.len().await, 2);
+        let oldest = queue.oldest(10).await;
+        assert_eq!(oldest.len(), 2);
+        assert_eq!(oldest[1].payload.timestamp, newest);
+    }
+}
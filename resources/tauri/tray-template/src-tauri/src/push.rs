@@ -0,0 +1,247 @@
+//! Push channel - events from the backend over an outbound WebSocket
+//!
+//! When `push_enabled` is set the agent keeps a WebSocket open to
+//! `/api/events`. The backend sends JSON text frames such as
+//! `{"type":"command_available"}`, which wake the matching loop at once
+//! instead of waiting for its next interval:
+//!
+//! - `command_available` - poll `/api/commands/pending` now
+//! - `config_changed` - send a heartbeat now
+//! - `check_for_update` - check for an agent update now
+//! - `revoked` - confirm with `/api/check`, then stop and forget the API key
+//!
+//! Polling keeps running underneath. While the channel is up the command
+//! loop only polls as a slow safety net; as soon as it drops, normal
+//! polling resumes and the channel reconnects with the shared backoff.
+
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::api::{ApiClient, WebSocket};
+use crate::backoff::{retry_after_of, Backoff};
+
+/// First reconnect delay after the channel drops
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnect attempts
+const RECONNECT_MAX: Duration = Duration::from_secs(300);
+
+/// A connection that stayed up this long resets the backoff
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+/// How often a ping is sent to keep the connection alive
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Silence after which the connection is assumed dead
+const STALE_AFTER: Duration = Duration::from_secs(90);
+
+/// Interval the command loop polls at while the channel is up
+pub const PUSH_CONNECTED_POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+/// An event sent by the backend
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PushEvent {
+    CommandAvailable,
+    ConfigChanged,
+    CheckForUpdate,
+    Revoked,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Wake-ups delivered by the push channel, shared with the agent's loops
+#[derive(Debug, Default)]
+pub struct PushSignals {
+    connected: AtomicBool,
+    /// A command is waiting in `/api/commands/pending`
+    pub command_available: Notify,
+    /// The backend has new configuration for this agent
+    pub config_changed: Notify,
+    /// An update check was requested
+    pub check_for_update: Notify,
+    /// The backend says this device was revoked (not yet confirmed)
+    pub revoked: Notify,
+}
+
+impl PushSignals {
+    /// Whether the push channel is currently connected
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    fn dispatch(&self, event: &PushEvent) {
+        match event {
+            PushEvent::CommandAvailable => self.command_available.notify_one(),
+            PushEvent::ConfigChanged => self.config_changed.notify_one(),
+            PushEvent::CheckForUpdate => self.check_for_update.notify_one(),
+            PushEvent::Revoked => self.revoked.notify_one(),
+            PushEvent::Unknown => {}
+        }
+    }
+}
+
+/// Keeps the push WebSocket connected and turns its events into signals
+pub struct PushChannel {
+    api: ApiClient,
+    signals: Arc<PushSignals>,
+}
+
+impl PushChannel {
+    pub fn new(api: ApiClient, signals: Arc<PushSignals>) -> Self {
+        Self { api, signals }
+    }
+
+    /// Connect and listen until shutdown, reconnecting with backoff
+    pub async fn run(&self, api_key: String, shutdown: CancellationToken) {
+        info!("Starting push channel");
+        let mut backoff = Backoff::new(RECONNECT_MIN, RECONNECT_MAX);
+
+        loop {
+            let connected_at = Instant::now();
+            let retry_after = match self.api.connect_events(&api_key).await {
+                Ok(socket) => {
+                    info!("Push channel connected");
+                    self.signals.set_connected(true);
+                    // Anything queued while disconnected
+                    self.signals.command_available.notify_one();
+
+                    let result = self.listen(socket, &shutdown).await;
+                    self.signals.set_connected(false);
+                    match result {
+                        Ok(()) => info!("Push channel closed"),
+                        Err(e) => warn!("Push channel dropped: {}", e),
+                    }
+
+                    if connected_at.elapsed() >= STABLE_CONNECTION {
+                        backoff.succeed();
+                    }
+                    None
+                }
+                Err(e) => {
+                    warn!("Push channel unavailable, polling instead: {:#}", e);
+                    retry_after_of(&e)
+                }
+            };
+
+            if shutdown.is_cancelled() {
+                break;
+            }
+
+            let delay = backoff.fail(retry_after);
+            debug!("Reconnecting push channel in {:?}", delay);
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+
+        info!("Push channel stopped");
+    }
+
+    /// Handle events until the connection closes, goes quiet or the agent stops
+    async fn listen(&self, mut socket: WebSocket, shutdown: &CancellationToken) -> Result<()> {
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        ping.tick().await;
+        let mut last_seen = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    let _ = socket.close(None).await;
+                    return Ok(());
+                }
+                _ = ping.tick() => {
+                    if last_seen.elapsed() >= STALE_AFTER {
+                        anyhow::bail!("no traffic for {:?}", STALE_AFTER);
+                    }
+                    socket.send(Message::Ping(Vec::new())).await?;
+                }
+                message = socket.next() => {
+                    last_seen = Instant::now();
+                    match message {
+                        Some(Ok(Message::Text(text))) => self.handle(&text),
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&self, text: &str) {
+        match serde_json::from_str::<PushEvent>(text) {
+            Ok(PushEvent::Unknown) => debug!("Ignoring unknown push event: {}", text),
+            Ok(event) => {
+                info!("Push event: {:?}", event);
+                self.signals.dispatch(&event);
+            }
+            Err(e) => warn!("Ignoring malformed push event ({}): {}", e, text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_event_parsing() {
+        let event: PushEvent =
+            serde_json::from_str(r#"{"type":"command_available","command_id":7}"#).unwrap();
+        assert_eq!(event, PushEvent::CommandAvailable);
+        let event: PushEvent = serde_json::from_str(r#"{"type":"something_new"}"#).unwrap();
+        assert_eq!(event, PushEvent::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_events_wake_loops() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Stand-in for the backend: announce a command, then revoke the device
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            for event in ["command_available", "revoked"] {
+                let text = format!(r#"{{"type":"{}"}}"#, event);
+                socket.send(Message::Text(text)).await.unwrap();
+            }
+            while socket.next().await.is_some() {}
+        });
+
+        let api = ApiClient::new(&Config::new(format!("http://127.0.0.1:{}", port))).unwrap();
+        let signals = Arc::new(PushSignals::default());
+        let channel = PushChannel::new(api, signals.clone());
+        let shutdown = CancellationToken::new();
+        let run = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { channel.run("key".to_string(), shutdown).await }
+        });
+
+        tokio::time::timeout(Duration::from_secs(10), signals.revoked.notified())
+            .await
+            .expect("revocation should be signalled");
+        signals.command_available.notified().await;
+
+        // A revocation alone doesn't stop the channel; the agent confirms it first
+        assert!(signals.is_connected());
+        shutdown.cancel();
+        run.await.unwrap();
+        assert!(!signals.is_connected());
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use tracing::{debug, info};
 
+use crate::config::{MAX_COMMAND_OUTPUT_LIMIT_BYTES, MIN_INTERVAL_SECS};
+
 /// Runtime configuration that can be changed at runtime and persists across restarts
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuntimeConfig {
@@ -12,14 +15,95 @@ pub struct RuntimeConfig {
     pub netdata_url: Option<String>,
     /// Optional metrics interval override (in seconds)
     pub metrics_interval: Option<u64>,
+    /// Optional request body compression ("gzip", "zstd" or "none")
+    #[serde(default)]
+    pub request_compression: Option<String>,
+    /// Optional smallest request body compressed (in bytes)
+    #[serde(default)]
+    pub request_compression_threshold: Option<usize>,
+    /// Optional number of metrics samples per batch (1 disables batching)
+    #[serde(default)]
+    pub metrics_batch_size: Option<usize>,
+    /// Optional longest wait for a metrics batch to fill (in seconds)
+    #[serde(default)]
+    pub metrics_batch_interval: Option<u64>,
+    /// Optional age limit for unsent metrics samples (in seconds)
+    #[serde(default)]
+    pub metrics_queue_max_age: Option<u64>,
+    /// Optional count limit for unsent metrics samples
+    #[serde(default)]
+    pub metrics_queue_max_samples: Option<usize>,
+    /// Optional size limit for unsent metrics samples (in bytes)
+    #[serde(default)]
+    pub metrics_queue_max_bytes: Option<u64>,
+    /// Optional command output streaming interval override (in seconds, 0 disables)
+    #[serde(default)]
+    pub command_output_interval: Option<u64>,
+    /// Optional per-stream cap on command result output (in bytes)
+    #[serde(default)]
+    pub command_output_limit: Option<usize>,
+    /// Optional switch for uploading full output when a result is truncated
+    #[serde(default)]
+    pub command_output_attachments: Option<bool>,
+    /// Optional cap on files moved by file transfer commands (in bytes)
+    #[serde(default)]
+    pub file_transfer_max_bytes: Option<u64>,
+    /// Optional switch for the push channel
+    #[serde(default)]
+    pub push_enabled: Option<bool>,
+    /// Ed25519 public key (base64) pinned at install time for command signatures
+    #[serde(default)]
+    pub command_signing_key: Option<String>,
+    /// Optional account override for scripts that don't require admin
+    #[serde(default)]
+    pub script_user: Option<String>,
+    /// Optional HTTP(S) proxy for outbound requests
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// Optional proxy username
+    #[serde(default)]
+    pub proxy_username: Option<String>,
+    /// Optional proxy password
+    #[serde(default)]
+    pub proxy_password: Option<String>,
+    /// Optional hosts, domains or CIDR ranges reached without the proxy
+    #[serde(default)]
+    pub no_proxy: Option<Vec<String>>,
+    /// Optional PEM file of extra CA certificates to trust
+    #[serde(default)]
+    pub ca_bundle_file: Option<String>,
+    /// Optional `sha256/<base64>` public key pins for the backend host
+    #[serde(default)]
+    pub pinned_public_keys: Option<Vec<String>>,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for RuntimeConfig {
     fn default() -> Self {
         Self {
             server_url: None,
             netdata_url: None,
             metrics_interval: None,
+            request_compression: None,
+            request_compression_threshold: None,
+            metrics_batch_size: None,
+            metrics_batch_interval: None,
+            metrics_queue_max_age: None,
+            metrics_queue_max_samples: None,
+            metrics_queue_max_bytes: None,
+            command_output_interval: None,
+            command_output_limit: None,
+            command_output_attachments: None,
+            file_transfer_max_bytes: None,
+            push_enabled: None,
+            command_signing_key: None,
+            script_user: None,
+            proxy_url: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: None,
+            ca_bundle_file: None,
+            pinned_public_keys: None,
         }
     }
 }
@@ -63,7 +147,18 @@ impl RuntimeConfig {
     /// Save runtime config to disk
     pub fn save(&self) -> Result<()> {
         let path = Self::config_path();
+        self.save_to(&path)?;
 
+        info!("Runtime config saved to {:?}", path);
+        Ok(())
+    }
+
+    /// Write the config to `path`, readable only by the agent
+    ///
+    /// It can hold the proxy password and the signing key, and scripts run
+    /// as an unprivileged account, so it is written to a private temp file
+    /// and renamed over the old one.
+    fn save_to(&self, path: &Path) -> Result<()> {
         // Ensure directory exists
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)
@@ -73,14 +168,28 @@ impl RuntimeConfig {
         let content = serde_json::to_string_pretty(self)
             .context("Failed to serialize runtime config")?;
 
-        std::fs::write(&path, content)
+        let tmp_path = path.with_extension("json.tmp");
+        // A leftover temp file would keep its old permissions
+        let _ = std::fs::remove_file(&tmp_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&tmp_path)
+            .context("Failed to create runtime config file")?;
+        file.write_all(content.as_bytes())
+            .and_then(|_| file.sync_all())
             .context("Failed to write runtime config file")?;
+        drop(file);
+        std::fs::rename(&tmp_path, path)
+            .context("Failed to replace runtime config file")?;
 
-        info!("Runtime config saved to {:?}", path);
         Ok(())
     }
 
     /// Get the effective server URL (override or default)
+    #[allow(clippy::useless_asref)]
     pub fn effective_server_url(&self, default: &str) -> String {
         self.server_url
             .as_ref()
@@ -89,6 +198,7 @@ impl RuntimeConfig {
     }
 
     /// Get the effective Netdata URL (override or default)
+    #[allow(clippy::useless_asref)]
     pub fn effective_netdata_url(&self, default: &str) -> String {
         self.netdata_url
             .as_ref()
@@ -96,9 +206,93 @@ impl RuntimeConfig {
             .unwrap_or_else(|| default.to_string())
     }
 
-    /// Get the effective metrics interval (override or default)
+    /// Get the effective metrics interval (override or default), at least a second
     pub fn effective_metrics_interval(&self, default: u64) -> u64 {
-        self.metrics_interval.unwrap_or(default)
+        self.metrics_interval.unwrap_or(default).max(MIN_INTERVAL_SECS)
+    }
+
+    /// Get the effective request body compression (override or default)
+    pub fn effective_request_compression(&self, default: &str) -> String {
+        self.request_compression
+            .clone()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// Get the effective request compression threshold (override or default)
+    pub fn effective_request_compression_threshold(&self, default: usize) -> usize {
+        self.request_compression_threshold.unwrap_or(default)
+    }
+
+    /// Get the effective metrics batch size (override or default)
+    pub fn effective_metrics_batch_size(&self, default: usize) -> usize {
+        self.metrics_batch_size.unwrap_or(default)
+    }
+
+    /// Get the effective metrics batch flush interval (override or default)
+    pub fn effective_metrics_batch_interval(&self, default: u64) -> u64 {
+        self.metrics_batch_interval.unwrap_or(default)
+    }
+
+    /// Get the effective age limit for unsent metrics samples (override or default)
+    pub fn effective_metrics_queue_max_age(&self, default: u64) -> u64 {
+        self.metrics_queue_max_age.unwrap_or(default)
+    }
+
+    /// Get the effective count limit for unsent metrics samples (override or default)
+    pub fn effective_metrics_queue_max_samples(&self, default: usize) -> usize {
+        self.metrics_queue_max_samples.unwrap_or(default)
+    }
+
+    /// Get the effective size limit for unsent metrics samples (override or default)
+    pub fn effective_metrics_queue_max_bytes(&self, default: u64) -> u64 {
+        self.metrics_queue_max_bytes.unwrap_or(default)
+    }
+
+    /// Get the effective command output streaming interval (override or default)
+    pub fn effective_command_output_interval(&self, default: u64) -> u64 {
+        self.command_output_interval.unwrap_or(default)
+    }
+
+    /// Get the effective command result output cap (override or default),
+    /// never more than the backend accepts
+    pub fn effective_command_output_limit(&self, default: usize) -> usize {
+        self.command_output_limit
+            .unwrap_or(default)
+            .min(MAX_COMMAND_OUTPUT_LIMIT_BYTES)
+    }
+
+    /// Get whether full output is uploaded for truncated results (override or default)
+    pub fn effective_command_output_attachments(&self, default: bool) -> bool {
+        self.command_output_attachments.unwrap_or(default)
+    }
+
+    /// Get the effective file transfer size cap (override or default)
+    pub fn effective_file_transfer_max_bytes(&self, default: u64) -> u64 {
+        self.file_transfer_max_bytes.unwrap_or(default)
+    }
+
+    /// Get whether the push channel is enabled (override or default)
+    pub fn effective_push_enabled(&self, default: bool) -> bool {
+        self.push_enabled.unwrap_or(default)
+    }
+
+    /// Get the effective unprivileged script account (override or default)
+    pub fn effective_script_user(&self, default: &str) -> String {
+        self.script_user
+            .clone()
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    /// Get the effective proxy bypass list (override or default)
+    pub fn effective_no_proxy(&self, default: &[String]) -> Vec<String> {
+        self.no_proxy.clone().unwrap_or_else(|| default.to_vec())
+    }
+
+    /// Get the effective backend public key pins (override or default)
+    pub fn effective_pinned_public_keys(&self, default: &[String]) -> Vec<String> {
+        self.pinned_public_keys
+            .clone()
+            .unwrap_or_else(|| default.to_vec())
     }
 }
 
@@ -114,12 +308,55 @@ mod tests {
         assert!(config.metrics_interval.is_none());
     }
 
+    #[test]
+    fn test_saved_config_is_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let config = RuntimeConfig {
+            proxy_password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        config.save_to(&path).unwrap();
+
+        let saved: RuntimeConfig =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.proxy_password.as_deref(), Some("hunter2"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
     #[test]
     fn test_effective_values() {
         let config = RuntimeConfig {
             server_url: Some("https://custom.example.com".to_string()),
             netdata_url: None,
             metrics_interval: Some(120),
+            request_compression: Some("zstd".to_string()),
+            request_compression_threshold: None,
+            metrics_batch_size: None,
+            metrics_batch_interval: Some(600),
+            metrics_queue_max_age: None,
+            metrics_queue_max_samples: Some(100),
+            metrics_queue_max_bytes: None,
+            command_output_interval: None,
+            command_output_limit: Some(5_000_000),
+            command_output_attachments: None,
+            file_transfer_max_bytes: None,
+            push_enabled: Some(true),
+            command_signing_key: None,
+            script_user: None,
+            proxy_url: Some("http://proxy.corp:3128".to_string()),
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: Some(vec![".corp".to_string()]),
+            ca_bundle_file: None,
+            pinned_public_keys: None,
         };
 
         assert_eq!(
@@ -131,5 +368,21 @@ mod tests {
             "http://localhost:19999"
         );
         assert_eq!(config.effective_metrics_interval(60), 120);
+        let zero = RuntimeConfig {
+            metrics_interval: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(zero.effective_metrics_interval(60), MIN_INTERVAL_SECS);
+        assert_eq!(config.effective_request_compression("gzip"), "zstd");
+        assert_eq!(config.effective_metrics_batch_interval(300), 600);
+        assert_eq!(config.effective_metrics_queue_max_samples(1440), 100);
+        assert_eq!(config.effective_command_output_interval(5), 5);
+        assert_eq!(
+            config.effective_command_output_limit(400_000),
+            MAX_COMMAND_OUTPUT_LIMIT_BYTES
+        );
+        assert!(config.effective_push_enabled(false));
+        assert_eq!(config.effective_no_proxy(&[]), vec![".corp".to_string()]);
+        assert!(config.effective_pinned_public_keys(&[]).is_empty());
     }
 }
@@ -0,0 +1,201 @@
+//! Per-device tick scheduling
+//!
+//! The metrics, heartbeat, status check and update loops tick on a schedule
+//! derived from the hardware fingerprint, so a fleet that restarts together
+//! (mass reboot, server outage) doesn't stay in lockstep:
+//!
+//! - the first tick waits a fixed per-device phase, a fraction of the
+//!   interval (at most `MAX_PHASE`)
+//! - every later tick moves by a further per-device ±10% of the interval
+//!
+//! Both come from the fingerprint, so a device keeps the same slots across
+//! restarts. Ticks are anchored to the schedule rather than to when the
+//! previous run finished, so slow collection doesn't push later ticks back;
+//! ticks missed entirely are skipped rather than run back to back.
+
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+
+/// Longest first-tick delay, however long the interval
+const MAX_PHASE: Duration = Duration::from_secs(600);
+
+/// Shortest interval a loop ticks at; with a zero interval `advance` would
+/// spin forever (configured intervals are at least a second anyway)
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Fraction of the interval each later tick may move either way
+const TICK_JITTER: f64 = 0.1;
+
+/// Seed for every loop's schedule on this device
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    seed: [u8; 32],
+}
+
+impl Schedule {
+    pub fn new(hardware_fingerprint: &str) -> Self {
+        Self {
+            seed: Sha256::digest(hardware_fingerprint.as_bytes()).into(),
+        }
+    }
+
+    /// Ticker for one loop; each loop name gets its own phase
+    pub fn ticker(&self, name: &str, interval: Duration) -> Ticker {
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed);
+        hasher.update(name.as_bytes());
+        Ticker::new(hasher.finalize().into(), interval)
+    }
+}
+
+/// Drift-corrected, jittered ticks for one loop
+#[derive(Debug)]
+pub struct Ticker {
+    seed: [u8; 32],
+    interval: Duration,
+    /// Number of the upcoming tick
+    tick: u64,
+    /// Unjittered time of the upcoming tick
+    slot: Instant,
+    /// Jittered time of the upcoming tick
+    next: Instant,
+}
+
+impl Ticker {
+    fn new(seed: [u8; 32], interval: Duration) -> Self {
+        let interval = interval.max(MIN_INTERVAL);
+        let phase = interval.min(MAX_PHASE).mul_f64(fraction(&seed, 0));
+        let slot = Instant::now() + phase;
+        Self {
+            seed,
+            interval,
+            tick: 0,
+            slot,
+            next: slot,
+        }
+    }
+
+    /// Time left until the upcoming tick
+    pub fn remaining(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    /// Move on to the first tick still in the future, after a run finishes
+    pub fn advance(&mut self) {
+        let now = Instant::now();
+        loop {
+            self.tick += 1;
+            self.slot += self.interval;
+            self.next = self.jittered();
+            if self.next > now {
+                break;
+            }
+        }
+    }
+
+    /// Switch to a new interval, counted from the previous slot
+    pub fn set_interval(&mut self, interval: Duration) {
+        let interval = interval.max(MIN_INTERVAL);
+        if interval == self.interval {
+            return;
+        }
+        if self.tick > 0 {
+            self.slot = self.slot.checked_sub(self.interval).unwrap_or(self.slot) + interval;
+        }
+        self.interval = interval;
+        self.next = self.jittered();
+    }
+
+    fn jittered(&self) -> Instant {
+        if self.tick == 0 {
+            return self.slot;
+        }
+        let offset = fraction(&self.seed, self.tick) * 2.0 - 1.0;
+        let shift = self.interval.mul_f64(TICK_JITTER * offset.abs());
+        if offset < 0.0 {
+            self.slot.checked_sub(shift).unwrap_or(self.slot)
+        } else {
+            self.slot + shift
+        }
+    }
+}
+
+/// Deterministic number in [0, 1) for a seed and tick
+fn fraction(seed: &[u8; 32], tick: u64) -> f64 {
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update(tick.to_le_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_is_per_device_and_stable() {
+        let interval = Duration::from_secs(60);
+        let ticker = Schedule::new("device-a").ticker("metrics", interval);
+        assert!(ticker.remaining() <= interval);
+        assert_eq!(
+            ticker.seed,
+            Schedule::new("device-a").ticker("metrics", interval).seed
+        );
+        assert_ne!(
+            ticker.seed,
+            Schedule::new("device-a").ticker("heartbeat", interval).seed
+        );
+
+        // Across many devices the phases spread over the interval
+        let phases: Vec<f64> = (0..200)
+            .map(|i| fraction(&Schedule::new(&format!("device-{}", i)).seed, 0))
+            .collect();
+        assert!(phases.iter().any(|p| *p < 0.2));
+        assert!(phases.iter().any(|p| *p > 0.8));
+
+        let long = Schedule::new("device-a").ticker("update", Duration::from_secs(86400));
+        assert!(long.remaining() <= MAX_PHASE);
+    }
+
+    #[test]
+    fn test_ticks_stay_on_schedule_and_skip_missed_ones() {
+        let interval = Duration::from_secs(60);
+        let mut ticker = Schedule::new("device-a").ticker("heartbeat", interval);
+        let start = ticker.slot;
+
+        ticker.advance();
+        assert_eq!(ticker.tick, 1);
+        assert_eq!(ticker.slot, start + interval);
+        let jitter = interval.mul_f64(TICK_JITTER);
+        assert!(ticker.next >= ticker.slot - jitter && ticker.next <= ticker.slot + jitter);
+
+        // Changing the interval counts from the previous slot
+        ticker.set_interval(interval * 2);
+        assert_eq!(ticker.slot, start + interval * 2);
+
+        // A run that overran several intervals lands on the next future slot
+        let short = Duration::from_millis(10);
+        let mut ticker = Schedule::new("device-a").ticker("metrics", short);
+        std::thread::sleep(short * 5);
+        ticker.advance();
+        assert!(ticker.tick >= 4, "{}", ticker.tick);
+        assert!(ticker.next > Instant::now());
+        assert!(ticker.remaining() <= short + short.mul_f64(TICK_JITTER));
+    }
+
+    #[test]
+    fn test_zero_interval_still_advances() {
+        let mut ticker = Schedule::new("device-a").ticker("metrics", Duration::ZERO);
+        assert_eq!(ticker.interval, MIN_INTERVAL);
+        let before = Instant::now();
+        ticker.advance();
+        assert!(ticker.next > before);
+
+        ticker.set_interval(Duration::from_secs(60));
+        ticker.set_interval(Duration::ZERO);
+        assert_eq!(ticker.interval, MIN_INTERVAL);
+    }
+}
@@ -0,0 +1,389 @@
+//! Server-driven configuration
+//!
+//! A heartbeat response may carry a versioned `config` block:
+//!
+//! ```json
+//! {"config": {"version": 7, "metrics_interval": 120, "heartbeat_interval": 30,
+//!   "update_check_interval": 43200, "netdata_contexts": ["system.cpu", "system.ram"],
+//!   "features": {"auto_updates": true}, "log_level": "debug"}}
+//! ```
+//!
+//! A block whose version differs from the running one is validated as a
+//! whole, applied to the running loops through a watch channel, and saved
+//! to `server-config.json` next to the runtime config so it survives a
+//! restart. Omitted fields fall back to the local configuration. Each
+//! heartbeat reports the version the agent is running, so the backend can
+//! tell when a block was rejected.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::Config;
+
+/// Bounds on intervals the server may set (in seconds)
+const METRICS_INTERVAL_RANGE: std::ops::RangeInclusive<u64> = 10..=3600;
+const HEARTBEAT_INTERVAL_RANGE: std::ops::RangeInclusive<u64> = 10..=600;
+const UPDATE_CHECK_INTERVAL_RANGE: std::ops::RangeInclusive<u64> = 3600..=604800;
+
+/// Most Netdata contexts the server may ask for
+const MAX_NETDATA_CONTEXTS: usize = 32;
+
+/// Log levels the server may set
+const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+/// Noisy dependencies are kept at `warn` whatever the level
+const DEPENDENCY_LOG_FILTER: &str = "reqwest=warn,hyper=warn";
+
+/// Feature toggle for the automatic update check
+const FEATURE_AUTO_UPDATES: &str = "auto_updates";
+
+/// Feature toggle for uploading full output of truncated results
+const FEATURE_COMMAND_OUTPUT_ATTACHMENTS: &str = "command_output_attachments";
+
+/// Config block sent by the backend in a heartbeat response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// Version of this block, acknowledged in later heartbeats
+    pub version: u64,
+    /// Metrics collection interval (in seconds)
+    #[serde(default)]
+    pub metrics_interval: Option<u64>,
+    /// Heartbeat interval (in seconds)
+    #[serde(default)]
+    pub heartbeat_interval: Option<u64>,
+    /// Update check interval (in seconds)
+    #[serde(default)]
+    pub update_check_interval: Option<u64>,
+    /// Netdata contexts to collect (e.g. `system.cpu`)
+    #[serde(default)]
+    pub netdata_contexts: Option<Vec<String>>,
+    /// Feature toggles by name; unknown names are ignored
+    #[serde(default)]
+    pub features: BTreeMap<String, bool>,
+    /// Log level for the agent log file
+    #[serde(default)]
+    pub log_level: Option<String>,
+}
+
+impl ServerConfig {
+    /// Check every field, so a block is either applied whole or not at all
+    pub fn validate(&self) -> Result<()> {
+        check_range(
+            "metrics_interval",
+            self.metrics_interval,
+            METRICS_INTERVAL_RANGE,
+        )?;
+        check_range(
+            "heartbeat_interval",
+            self.heartbeat_interval,
+            HEARTBEAT_INTERVAL_RANGE,
+        )?;
+        check_range(
+            "update_check_interval",
+            self.update_check_interval,
+            UPDATE_CHECK_INTERVAL_RANGE,
+        )?;
+
+        if let Some(contexts) = &self.netdata_contexts {
+            if contexts.is_empty() || contexts.len() > MAX_NETDATA_CONTEXTS {
+                anyhow::bail!(
+                    "netdata_contexts must list 1 to {} contexts",
+                    MAX_NETDATA_CONTEXTS
+                );
+            }
+            if let Some(bad) = contexts.iter().find(|c| !is_valid_context(c)) {
+                anyhow::bail!("invalid Netdata context {:?}", bad);
+            }
+        }
+
+        if let Some(level) = &self.log_level {
+            if !LOG_LEVELS.contains(&level.as_str()) {
+                anyhow::bail!("invalid log level {:?}", level);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load the block saved by the last successful apply
+    fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path).context("Failed to read server config")?;
+        let config = serde_json::from_str(&content).context("Failed to parse server config")?;
+        Ok(Some(config))
+    }
+
+    /// Save the block, replacing the previous one atomically
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        let content =
+            serde_json::to_string_pretty(self).context("Failed to serialize server config")?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, content).context("Failed to write server config")?;
+        std::fs::rename(&tmp, path).context("Failed to replace server config")?;
+        Ok(())
+    }
+}
+
+fn check_range(name: &str, value: Option<u64>, range: std::ops::RangeInclusive<u64>) -> Result<()> {
+    match value {
+        Some(v) if !range.contains(&v) => anyhow::bail!(
+            "{} must be between {} and {} seconds, got {}",
+            name,
+            range.start(),
+            range.end(),
+            v
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Netdata context names are dotted lowercase identifiers
+fn is_valid_context(context: &str) -> bool {
+    !context.is_empty()
+        && context.len() <= 64
+        && context
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.')
+}
+
+/// Settings the running loops read, local config with the server's block applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveSettings {
+    /// Version of the server block in effect, if any
+    pub version: Option<u64>,
+    pub metrics_interval: u64,
+    pub heartbeat_interval: u64,
+    pub update_check_interval: u64,
+    pub netdata_contexts: Vec<String>,
+    /// Whether the update loop checks for updates
+    pub auto_updates: bool,
+    /// Whether full output is uploaded for truncated results
+    pub command_output_attachments: bool,
+    /// Log level set by the server (None keeps the startup filter)
+    pub log_level: Option<String>,
+}
+
+impl LiveSettings {
+    /// Settings from local configuration alone
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            version: None,
+            metrics_interval: config.metrics_interval,
+            heartbeat_interval: config.heartbeat_interval,
+            update_check_interval: config.update_check_interval,
+            netdata_contexts: config.netdata_contexts.clone(),
+            auto_updates: true,
+            command_output_attachments: config.command_output_attachments,
+            log_level: None,
+        }
+    }
+
+    /// These settings with a (validated) server block applied on top
+    fn with_server_config(&self, server: &ServerConfig) -> Self {
+        let mut settings = self.clone();
+        settings.version = Some(server.version);
+        settings.metrics_interval = server.metrics_interval.unwrap_or(self.metrics_interval);
+        settings.heartbeat_interval = server.heartbeat_interval.unwrap_or(self.heartbeat_interval);
+        settings.update_check_interval = server
+            .update_check_interval
+            .unwrap_or(self.update_check_interval);
+        if let Some(contexts) = &server.netdata_contexts {
+            settings.netdata_contexts = contexts.clone();
+        }
+        for (name, enabled) in &server.features {
+            match name.as_str() {
+                FEATURE_AUTO_UPDATES => settings.auto_updates = *enabled,
+                FEATURE_COMMAND_OUTPUT_ATTACHMENTS => {
+                    settings.command_output_attachments = *enabled
+                }
+                _ => warn!("Ignoring unknown feature toggle from server: {}", name),
+            }
+        }
+        settings.log_level = server.log_level.clone();
+        settings
+    }
+}
+
+/// Holds the server's config block and publishes the resulting settings
+pub struct ServerConfigStore {
+    /// Settings from local configuration, the base every block applies to
+    base: LiveSettings,
+    path: PathBuf,
+    sender: watch::Sender<LiveSettings>,
+}
+
+impl ServerConfigStore {
+    /// Start from local configuration plus the last block saved, if still valid
+    pub fn load(config: &Config) -> Self {
+        let base = LiveSettings::from_config(config);
+        let store = Self {
+            sender: watch::Sender::new(base.clone()),
+            base,
+            path: config.server_config_file.clone(),
+        };
+
+        match ServerConfig::load(&store.path) {
+            Ok(Some(server)) => match server.validate() {
+                Ok(()) => {
+                    info!(
+                        "Using server config version {} from {:?}",
+                        server.version, store.path
+                    );
+                    store.publish(&server);
+                }
+                Err(e) => warn!("Ignoring saved server config: {:#}", e),
+            },
+            Ok(None) => debug!("No saved server config"),
+            Err(e) => warn!("Ignoring saved server config: {:#}", e),
+        }
+
+        store
+    }
+
+    /// Receive the current settings and every change to them
+    pub fn subscribe(&self) -> watch::Receiver<LiveSettings> {
+        self.sender.subscribe()
+    }
+
+    /// Version of the server block in effect, reported in heartbeats
+    pub fn running_version(&self) -> Option<u64> {
+        self.sender.borrow().version
+    }
+
+    /// Apply a block from a heartbeat response; `Ok(false)` if it is already running
+    pub fn apply(&self, server: &ServerConfig) -> Result<bool> {
+        if self.running_version() == Some(server.version) {
+            return Ok(false);
+        }
+
+        server
+            .validate()
+            .with_context(|| format!("Rejected server config version {}", server.version))?;
+
+        self.publish(server);
+        info!("Applied server config version {}", server.version);
+
+        if let Err(e) = server.save(&self.path) {
+            warn!("Failed to save server config: {:#}", e);
+        }
+        Ok(true)
+    }
+
+    fn publish(&self, server: &ServerConfig) {
+        let settings = self.base.with_server_config(server);
+        if let Err(e) = set_log_level(settings.log_level.as_deref()) {
+            warn!("Failed to change log level: {:#}", e);
+        }
+        self.sender.send_replace(settings);
+    }
+}
+
+// ============================================================================
+// Log level
+// ============================================================================
+
+/// Handle for swapping the log file filter, with the filter set at startup
+static LOG_FILTER: OnceLock<(reload::Handle<EnvFilter, Registry>, String)> = OnceLock::new();
+
+/// Wrap the startup filter so the server can change the log level later
+pub fn reloadable_log_filter(filter: EnvFilter) -> reload::Layer<EnvFilter, Registry> {
+    let startup = filter.to_string();
+    let (layer, handle) = reload::Layer::new(filter);
+    let _ = LOG_FILTER.set((handle, startup));
+    layer
+}
+
+/// Switch the log level, or back to the startup filter for `None`
+fn set_log_level(level: Option<&str>) -> Result<()> {
+    let Some((handle, startup)) = LOG_FILTER.get() else {
+        return Ok(());
+    };
+
+    let directives = match level {
+        Some(level) => format!("{},{}", level, DEPENDENCY_LOG_FILTER),
+        None => startup.clone(),
+    };
+    let filter = EnvFilter::try_new(&directives).context("Invalid log filter")?;
+    handle
+        .reload(filter)
+        .context("Failed to reload log filter")?;
+    debug!("Log filter is now {}", directives);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(version: u64) -> ServerConfig {
+        serde_json::from_value(serde_json::json!({ "version": version })).unwrap()
+    }
+
+    #[test]
+    fn test_validation_rejects_the_whole_block() {
+        let mut server = block(1);
+        server.metrics_interval = Some(120);
+        server.netdata_contexts = Some(vec!["system.cpu".to_string(), "disk.space".to_string()]);
+        server.log_level = Some("debug".to_string());
+        assert!(server.validate().is_ok());
+
+        let mut bad = server.clone();
+        bad.heartbeat_interval = Some(1);
+        assert!(bad.validate().is_err());
+
+        let mut bad = server.clone();
+        bad.netdata_contexts = Some(vec!["system.cpu&points=1000".to_string()]);
+        assert!(bad.validate().is_err());
+
+        let mut bad = server.clone();
+        bad.log_level = Some("verbose".to_string());
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_apply_publishes_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            server_config_file: dir.path().join("server-config.json"),
+            ..Default::default()
+        };
+        let store = ServerConfigStore::load(&config);
+        let settings = store.subscribe();
+        assert_eq!(store.running_version(), None);
+
+        let mut server = block(3);
+        server.metrics_interval = Some(300);
+        server.features.insert("auto_updates".to_string(), false);
+        server.features.insert("not_a_feature".to_string(), true);
+        assert!(store.apply(&server).unwrap());
+        assert!(!store.apply(&server).unwrap());
+
+        assert!(settings.has_changed().unwrap());
+        let current = settings.borrow().clone();
+        assert_eq!(current.version, Some(3));
+        assert_eq!(current.metrics_interval, 300);
+        assert_eq!(current.heartbeat_interval, config.heartbeat_interval);
+        assert!(!current.auto_updates);
+
+        let mut invalid = block(4);
+        invalid.metrics_interval = Some(1);
+        assert!(store.apply(&invalid).is_err());
+        assert_eq!(store.running_version(), Some(3));
+
+        // A restart picks up the saved block
+        let restarted = ServerConfigStore::load(&config);
+        assert_eq!(restarted.running_version(), Some(3));
+        assert_eq!(restarted.subscribe().borrow().metrics_interval, 300);
+    }
+}
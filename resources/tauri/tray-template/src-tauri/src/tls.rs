@@ -0,0 +1,345 @@
+//! TLS trust for backend connections
+//!
+//! The agent trusts the bundled Mozilla roots. `ca_bundle_file` adds the CA
+//! certificates in a PEM file, for a backend behind an internal CA.
+//!
+//! `pinned_public_keys` lists SHA-256 hashes of certificate public keys
+//! (SubjectPublicKeyInfo) as `sha256/<base64>`, the form printed by
+//!
+//! ```text
+//! openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der \
+//!     | openssl dgst -sha256 -binary | base64
+//! ```
+//!
+//! When set, every TLS connection to the backend host (API requests,
+//! WebSockets, and update downloads served from it) must present a chain
+//! containing one of those keys, on top of the normal chain validation.
+//! Other hosts, such as GitHub releases, are only checked against the roots.
+//! A mismatch surfaces as `ApiError::PinMismatch`, which the agent reports
+//! as its own state and never treats as revocation.
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::Url;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, ClientConfig, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::config::Config;
+
+/// Why a handshake with the backend was refused despite a valid chain
+#[derive(Debug, thiserror::Error)]
+#[error("certificate does not match any pinned public key")]
+struct PinMismatch;
+
+/// TLS settings for the API client, or `None` to keep reqwest's defaults
+pub fn client_config(config: &Config) -> Result<Option<Arc<ClientConfig>>> {
+    if config.ca_bundle_file.is_none() && config.pinned_public_keys.is_empty() {
+        return Ok(None);
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    if let Some(path) = &config.ca_bundle_file {
+        for cert in load_certs(path)? {
+            roots
+                .add(&Certificate(cert))
+                .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+        }
+    }
+
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let tls = if config.pinned_public_keys.is_empty() {
+        builder.with_root_certificates(roots).with_no_client_auth()
+    } else {
+        let pins = config
+            .pinned_public_keys
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<Vec<_>>>()?;
+        let host = Url::parse(&config.base_url)
+            .ok()
+            .and_then(|url| {
+                url.host_str()
+                    .map(|h| h.trim_matches(['[', ']']).to_lowercase())
+            })
+            .context("Server URL has no host to pin")?;
+        let verifier = PinnedVerifier {
+            roots: WebPkiVerifier::new(roots, None),
+            host,
+            pins,
+        };
+        builder
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth()
+    };
+
+    Ok(Some(Arc::new(tls)))
+}
+
+/// Parse a `sha256/<base64>` pin (the prefix is optional)
+pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let encoded = pin.trim();
+    let encoded = encoded.strip_prefix("sha256/").unwrap_or(encoded);
+    general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .with_context(|| format!("Invalid public key pin (expected sha256/<base64>): {}", pin))
+}
+
+/// Whether a request failed because the backend's certificate didn't match a pin
+pub fn is_pin_mismatch(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        // TLS errors reach reqwest wrapped in an io::Error, whose source() skips them
+        if let Some(inner) = error
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.get_ref())
+        {
+            if is_pin_mismatch(inner) {
+                return true;
+            }
+        }
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(other))) =
+            error.downcast_ref::<rustls::Error>()
+        {
+            if other.downcast_ref::<PinMismatch>().is_some() {
+                return true;
+            }
+        }
+        source = error.source();
+    }
+    false
+}
+
+fn load_certs(path: &std::path::Path) -> Result<Vec<Vec<u8>>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open CA bundle {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in CA bundle {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Normal chain validation, plus the pins for the backend host
+struct PinnedVerifier {
+    roots: WebPkiVerifier,
+    host: String,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.roots.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let name = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_lowercase(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        if name != self.host {
+            return Ok(verified);
+        }
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| spki_sha256(&cert.0))
+            .any(|hash| self.pins.contains(&hash));
+        if pinned {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                Arc::new(PinMismatch),
+            )))
+        }
+    }
+}
+
+/// SHA-256 of a DER certificate's SubjectPublicKeyInfo
+fn spki_sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    let (certificate, _) = der_element(cert)?;
+    let (tbs, _) = der_element(certificate.contents)?;
+    let mut fields = tbs.contents;
+
+    // Skip the optional [0] version, then serial, signature algorithm,
+    // issuer, validity and subject
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.1;
+    }
+    for _ in 0..5 {
+        fields = der_element(fields)?.1;
+    }
+
+    let (spki, _) = der_element(fields)?;
+    (spki.tag == 0x30).then(|| Sha256::digest(spki.encoded).into())
+}
+
+/// One DER element: its tag, full encoding and contents
+struct DerElement<'a> {
+    tag: u8,
+    encoded: &'a [u8],
+    contents: &'a [u8],
+}
+
+/// Split the first DER element off `input`, returning it and the rest
+fn der_element(input: &[u8]) -> Option<(DerElement<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let len = rest[..octets]
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, &rest[octets..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = input.len() - rest.len();
+    let element = DerElement {
+        tag,
+        encoded: &input[..header + len],
+        contents: &rest[..len],
+    };
+    Some((element, &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiClient, ApiError};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const CA_PEM: &str = include_str!("../testdata/tls/ca.pem");
+    const SERVER_PEM: &str = include_str!("../testdata/tls/server.pem");
+    const SERVER_KEY: &str = include_str!("../testdata/tls/server.key");
+
+    /// `SERVER_PEM`'s pin, as computed by openssl
+    const SERVER_PIN: &str = "sha256/Ea5B8WI0K9gbxqCIRG4mYcjpy6Vrf2OM219IhhZu6lI=";
+
+    fn pem_certs(pem: &str) -> Vec<Vec<u8>> {
+        rustls_pemfile::certs(&mut pem.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_pins_and_spki_extraction() {
+        let leaf = &pem_certs(SERVER_PEM)[0];
+        assert_eq!(spki_sha256(leaf), Some(parse_pin(SERVER_PIN).unwrap()));
+        assert_ne!(spki_sha256(&pem_certs(CA_PEM)[0]), spki_sha256(leaf));
+        assert_eq!(spki_sha256(&leaf[..40]), None);
+
+        assert!(parse_pin("Ea5B8WI0K9gbxqCIRG4mYcjpy6Vrf2OM219IhhZu6lI=").is_ok());
+        assert!(parse_pin("sha256/c2hvcnQ=").is_err());
+        assert!(parse_pin("sha1/Ea5B8WI0K9gbxqCIRG4mYcjpy6Vrf2OM219IhhZu6lI=").is_err());
+
+        assert!(client_config(&Config::default()).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_private_ca_and_pins() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        std::fs::write(&ca_file, CA_PEM).unwrap();
+
+        let key = rustls_pemfile::pkcs8_private_keys(&mut SERVER_KEY.as_bytes()).unwrap();
+        let server = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                pem_certs(SERVER_PEM).into_iter().map(Certificate).collect(),
+                rustls::PrivateKey(key[0].clone()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Stand-in backend that refuses every API key once the handshake is done
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut buf = [0u8; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                        .await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        let request = |pins: &[&str]| {
+            let config = Config {
+                base_url: format!("https://localhost:{}", port),
+                ca_bundle_file: Some(ca_file.clone()),
+                pinned_public_keys: pins.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            };
+            async move {
+                ApiClient::new(&config)
+                    .unwrap()
+                    .pending_command("key")
+                    .await
+            }
+        };
+
+        // Trusted through the extra CA, with and without a matching pin
+        let result = request(&[]).await;
+        assert!(
+            matches!(result, Err(ApiError::Unauthorized { .. })),
+            "{:?}",
+            result
+        );
+        let result = request(&[SERVER_PIN]).await;
+        assert!(
+            matches!(result, Err(ApiError::Unauthorized { .. })),
+            "{:?}",
+            result
+        );
+
+        // A valid chain with the wrong key is a pin mismatch, not a network error
+        let other = format!("sha256/{}", general_purpose::STANDARD.encode([7u8; 32]));
+        let result = request(&[&other]).await;
+        assert!(
+            matches!(result, Err(ApiError::PinMismatch { .. })),
+            "{:?}",
+            result
+        );
+    }
+}
@@ -8,13 +8,19 @@ use futures_util::StreamExt;
 use semver::Version;
 use serde::Deserialize;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
+use crate::api::{check_status, ApiClient};
+use crate::backoff::{retry_after_of, Backoff};
 use crate::config::{Config, AGENT_VERSION, GITHUB_RELEASES_URL};
+use crate::push::PushSignals;
+use crate::schedule::Schedule;
+use crate::server_config::LiveSettings;
 
 /// Information about an available update
 #[derive(Debug, Clone)]
@@ -50,22 +56,25 @@ struct GitHubAsset {
     size: u64,
 }
 
+/// First retry after a failed update check
+const UPDATE_RETRY_MIN: Duration = Duration::from_secs(300);
+
+/// Longest wait between retries of a failed update check
+const UPDATE_RETRY_MAX: Duration = Duration::from_secs(6 * 3600);
+
 /// Auto-updater for the RMM agent
 pub struct Updater {
     config: Config,
-    client: reqwest::Client,
+    api: ApiClient,
 }
 
 impl Updater {
     /// Create a new updater instance
-    pub fn new(config: Config) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .user_agent(format!("RMM-Agent/{}", AGENT_VERSION))
-            .build()
-            .context("Failed to create HTTP client")?;
-
-        Ok(Self { config, client })
+    ///
+    /// Releases come from GitHub rather than the backend, but share the API
+    /// client's connection pool and user agent.
+    pub fn new(config: Config, api: ApiClient) -> Self {
+        Self { config, api }
     }
 
     /// Get the update directory path
@@ -88,18 +97,16 @@ impl Updater {
         info!("Checking for updates at {}", GITHUB_RELEASES_URL);
 
         let response = self
-            .client
+            .api
+            .http()
             .get(GITHUB_RELEASES_URL)
             .header("Accept", "application/vnd.github.v3+json")
             .send()
             .await
             .context("Failed to fetch GitHub releases")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("GitHub API returned {}: {}", status, body);
-        }
+        let response = check_status(response)
+            .await
+            .context("GitHub releases request failed")?;
 
         let release: GitHubRelease = response
             .json()
@@ -165,15 +172,13 @@ impl Updater {
 
         // Download with streaming to handle large files
         let response = self
-            .client
+            .api
+            .http()
             .get(&info.download_url)
             .send()
             .await
             .context("Failed to start download")?;
-
-        if !response.status().is_success() {
-            anyhow::bail!("Download failed with status: {}", response.status());
-        }
+        let response = check_status(response).await.context("Download failed")?;
 
         let content_length = response.content_length();
         let mut file = fs::File::create(&new_exe_path)
@@ -353,7 +358,17 @@ impl Updater {
     }
 
     /// Start the update check loop
-    pub async fn start_update_loop(&self, cancellation_token: CancellationToken) {
+    ///
+    /// A `check_for_update` push event checks at once. The interval and the
+    /// server's `auto_updates` toggle follow `settings`; `skip_updates`
+    /// disables the loop whatever the server says.
+    pub async fn start_update_loop(
+        &self,
+        schedule: &Schedule,
+        push: &PushSignals,
+        mut settings: watch::Receiver<LiveSettings>,
+        cancellation_token: CancellationToken,
+    ) {
         if self.config.skip_updates {
             info!("Automatic updates are disabled");
             return;
@@ -361,24 +376,59 @@ impl Updater {
 
         info!(
             "Starting update check loop (interval: {}s)",
-            self.config.update_check_interval
+            settings.borrow().update_check_interval
         );
 
-        // Check immediately on startup
-        if let Err(e) = self.check_and_download().await {
-            warn!("Initial update check failed: {}", e);
-        }
+        // A failed check is retried sooner than the interval, backing off
+        let mut backoff = Backoff::new(UPDATE_RETRY_MIN, UPDATE_RETRY_MAX);
+        let mut retry_at: Option<Instant> = None;
+
+        // The first check runs shortly after startup, at this device's phase
+        let interval = Duration::from_secs(settings.borrow().update_check_interval);
+        let mut ticker = schedule.ticker("update", interval);
 
         loop {
+            let until_tick = ticker.remaining();
+            let until_retry = retry_at
+                .map(|at| at.saturating_duration_since(Instant::now()))
+                .filter(|r| *r < until_tick);
+
             tokio::select! {
                 _ = cancellation_token.cancelled() => {
                     info!("Update loop cancelled - shutting down");
                     break;
                 }
-                _ = tokio::time::sleep(Duration::from_secs(self.config.update_check_interval)) => {
-                    if let Err(e) = self.check_and_download().await {
-                        warn!("Scheduled update check failed: {}", e);
+                Ok(()) = settings.changed() => {
+                    ticker.set_interval(Duration::from_secs(settings.borrow().update_check_interval));
+                    continue;
+                }
+                _ = push.check_for_update.notified() => {
+                    if backoff.is_waiting() {
+                        info!("Update check requested by the server, but backing off after a failure");
+                        continue;
                     }
+                    info!("Update check requested by the server");
+                }
+                _ = tokio::time::sleep(until_retry.unwrap_or(until_tick)) => {
+                    if until_retry.is_none() {
+                        ticker.advance();
+                    }
+                    if !settings.borrow().auto_updates {
+                        debug!("Skipping update check, turned off by the server");
+                        continue;
+                    }
+                }
+            }
+
+            match self.check_and_download().await {
+                Ok(()) => {
+                    backoff.succeed();
+                    retry_at = None;
+                }
+                Err(e) => {
+                    let delay = backoff.fail(retry_after_of(&e));
+                    warn!("Update check failed, retrying in {}s: {:#}", delay.as_secs(), e);
+                    retry_at = Some(Instant::now() + delay);
                 }
             }
         }
@@ -391,22 +441,16 @@ impl Updater {
                 info!("Update available: v{}", info.version);
 
                 // Download the update
-                match self.download_update(&info).await {
-                    Ok(_) => {
-                        info!("Update downloaded, triggering restart to apply");
-                        self.trigger_restart()?;
-                    }
-                    Err(e) => {
-                        error!("Failed to download update: {}", e);
-                    }
-                }
+                self.download_update(&info)
+                    .await
+                    .context("Failed to download update")?;
+                info!("Update downloaded, triggering restart to apply");
+                self.trigger_restart()?;
             }
             Ok(None) => {
                 debug!("No update available");
             }
-            Err(e) => {
-                warn!("Update check error: {}", e);
-            }
+            Err(e) => return Err(e),
         }
         Ok(())
     }
@@ -417,6 +461,7 @@ impl Updater {
     }
 
     /// Manual update (for CLI command)
+    #[allow(dead_code)]
     pub async fn update_now(&self) -> Result<bool> {
         match self.check_for_update().await? {
             Some(info) => {
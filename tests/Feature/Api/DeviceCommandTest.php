@@ -177,6 +177,72 @@
     expect($command->error_message)->toBe('Command not found');
 });
 
+it('records a timed out result as timed out', function (): void {
+    $device = Device::factory()->active()->create([
+        'api_key' => 'VALID-KEY-123',
+    ]);
+
+    $user = User::factory()->create();
+
+    $command = DeviceCommand::create([
+        'device_id' => $device->id,
+        'script_content' => 'Start-Sleep 600',
+        'script_type' => 'powershell',
+        'status' => DeviceCommand::STATUS_RUNNING,
+        'queued_at' => now(),
+        'queued_by' => $user->id,
+        'timeout_seconds' => 30,
+    ]);
+
+    $response = $this->withHeaders(['X-Agent-Key' => 'VALID-KEY-123'])
+        ->postJson("/api/commands/{$command->id}/result", [
+            'status' => 'timed_out',
+            'exit_code' => -1,
+            'output' => 'partial output',
+            'error_message' => 'Command timed out after 30 seconds',
+        ]);
+
+    $response->assertSuccessful();
+
+    $command->refresh();
+    expect($command->status)->toBe(DeviceCommand::STATUS_TIMED_OUT);
+    expect($command->output)->toBe('partial output');
+    expect($command->error_message)->toBe('Command timed out after 30 seconds');
+    expect($command->completed_at)->not->toBeNull();
+});
+
+it('records a cancelled result as cancelled', function (): void {
+    $device = Device::factory()->active()->create([
+        'api_key' => 'VALID-KEY-123',
+    ]);
+
+    $user = User::factory()->create();
+
+    $command = DeviceCommand::create([
+        'device_id' => $device->id,
+        'script_content' => 'Start-Sleep 600',
+        'script_type' => 'powershell',
+        'status' => DeviceCommand::STATUS_RUNNING,
+        'queued_at' => now(),
+        'queued_by' => $user->id,
+        'timeout_seconds' => 300,
+    ]);
+
+    $response = $this->withHeaders(['X-Agent-Key' => 'VALID-KEY-123'])
+        ->postJson("/api/commands/{$command->id}/result", [
+            'status' => 'cancelled',
+            'exit_code' => -1,
+            'output' => '',
+            'error_message' => 'Command cancelled by server',
+        ]);
+
+    $response->assertSuccessful();
+
+    $command->refresh();
+    expect($command->status)->toBe(DeviceCommand::STATUS_CANCELLED);
+    expect($command->error_message)->toBe('Command cancelled by server');
+});
+
 it('rejects commands without valid api key', function (): void {
     $response = $this->getJson('/api/commands/pending');
     $response->assertUnauthorized();